@@ -0,0 +1,6 @@
+-- Fixture for the batched notification tests. IDs start at 900001 so they don't clash with
+-- development data, the tests roll everything back afterwards.
+insert into users (id, username, password, email, user_type)
+values (900001, 'fixture_channel', '', 'fixture_channel@example.com', 'CHANNEL'),
+       (900002, 'fixture_voter', '', 'fixture_voter@example.com', 'SUBSCRIBER'),
+       (900003, 'fixture_other_voter', '', 'fixture_other_voter@example.com', 'SUBSCRIBER');
@@ -0,0 +1,15 @@
+-- Fixture for the fake payments flow test. Running the server with PAYMENTS_PROVIDER=fake leaves
+-- rows with the same Stripe IDs the test gets, so they are cleared first. IDs start at 900001 so
+-- they don't clash with development data, the tests roll everything back afterwards.
+delete from stripe_events where id like 'evt_fake_%';
+update users set stripe_customer = '' where stripe_customer like 'cus_fake_%';
+update users set stripe_account = null where stripe_account like 'acct_fake_%';
+update withdrawal_requests set stripe_transfer_id = null where stripe_transfer_id like 'tr_fake_%';
+update token_transactions set stripe_transfer_id = null where stripe_transfer_id like 'tr_fake_%';
+
+insert into users (id, username, password, email, user_type)
+values (900001, 'fixture_subscriber', '', 'fixture_subscriber@example.com', 'SUBSCRIBER'),
+       (900002, 'fixture_channel', '', 'fixture_channel@example.com', 'CHANNEL');
+
+insert into job_runs (id, job_name, period)
+values (900001, 'Convert tokens', 'fixture');
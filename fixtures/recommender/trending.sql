@@ -0,0 +1,31 @@
+-- Fixture for the trending fallback tests. IDs start at 900001 so they don't clash with
+-- development data, and the trending scores are high enough to come before any of it.
+insert into users (id, username, password, email, user_type)
+values (900001, 'fixture_channel_a', '', 'fixture_channel_a@example.com', 'CHANNEL'),
+       (900002, 'fixture_channel_b', '', 'fixture_channel_b@example.com', 'CHANNEL'),
+       (900003, 'fixture_viewer', '', 'fixture_viewer@example.com', 'SUBSCRIBER');
+
+insert into tags (id, name)
+values (900001, 'fixture-see-less'),
+       (900002, 'fixture-other');
+
+insert into videos (id, file_name, user_id, title, status, upvote_count, downvote_count, trending_score)
+values (900001, 'fixture', 900001, 'Most trending, see less tag', 'READY', 0, 0, 3000000),
+       (900002, 'fixture', 900001, 'Trending', 'READY', 0, 0, 2000000),
+       (900003, 'fixture', 900002, 'Less trending', 'READY', 0, 0, 1000000),
+       -- not interested in the video
+       (900004, 'fixture', 900002, 'Hidden', 'READY', 0, 0, 5000000),
+       -- already watched
+       (900005, 'fixture', 900002, 'Watched', 'READY', 0, 0, 5000000);
+
+insert into videos_tags (video_id, tag_id)
+values (900001, 900001),
+       (900002, 900002),
+       (900003, 900002);
+
+insert into video_plays (user_id, video_id)
+values (900003, 900005);
+
+insert into recommendation_feedback (user_id, feedback_type, target_id)
+values (900003, 'SEE_LESS_TAG', 900001),
+       (900003, 'NOT_INTERESTED_VIDEO', 900004);
@@ -0,0 +1,47 @@
+-- Fixture for the related videos tests. IDs start at 900001 so they don't clash with
+-- development data, the tests roll everything back afterwards.
+insert into users (id, username, password, email, user_type)
+values (900001, 'fixture_channel_a', '', 'fixture_channel_a@example.com', 'CHANNEL'),
+       (900002, 'fixture_channel_b', '', 'fixture_channel_b@example.com', 'CHANNEL'),
+       (900003, 'fixture_viewer_1', '', 'fixture_viewer_1@example.com', 'SUBSCRIBER'),
+       (900004, 'fixture_viewer_2', '', 'fixture_viewer_2@example.com', 'SUBSCRIBER');
+
+insert into tags (id, name)
+values (900001, 'fixture-a'),
+       (900002, 'fixture-b');
+
+-- 900001 is the video the related videos are for
+insert into videos (id, file_name, user_id, title, status, upvote_count, downvote_count)
+values (900001, 'fixture', 900001, 'Current', 'READY', 0, 0),
+       -- both tags: 6 + 0.5
+       (900002, 'fixture', 900002, 'Both tags', 'READY', 0, 0),
+       -- same channel: 2 + 0.5
+       (900003, 'fixture', 900001, 'Same channel', 'READY', 0, 0),
+       -- one tag and well liked: 3 + 4 / 6
+       (900004, 'fixture', 900002, 'One tag, upvoted', 'READY', 3, 1),
+       -- one tag each, the same score, so ordered by ID
+       (900005, 'fixture', 900002, 'One tag', 'READY', 0, 0),
+       (900006, 'fixture', 900002, 'One tag again', 'READY', 0, 0),
+       -- played by both viewers of the current video: 2 * ln(3) + 0.5
+       (900007, 'fixture', 900002, 'Co-watched', 'READY', 0, 0),
+       -- not READY, so never related
+       (900008, 'fixture', 900002, 'Processing', 'WAITING', 0, 0),
+       -- nothing in common
+       (900009, 'fixture', 900002, 'Unrelated', 'READY', 0, 0);
+
+insert into videos_tags (video_id, tag_id)
+values (900001, 900001),
+       (900001, 900002),
+       (900002, 900001),
+       (900002, 900002),
+       (900004, 900001),
+       (900005, 900001),
+       (900006, 900002),
+       (900008, 900001),
+       (900008, 900002);
+
+insert into video_plays (user_id, video_id)
+values (900003, 900001),
+       (900004, 900001),
+       (900003, 900007),
+       (900004, 900007);
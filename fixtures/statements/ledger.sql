@@ -0,0 +1,44 @@
+-- Fixture for the statement tests: a deposit in May, then a deposit, a withdrawal which is paid
+-- and a partial reversal in June, and a deposit in July. Everything is in XTS, the currency
+-- code reserved for testing, so it doesn't mix with development data. IDs start at 900001 and
+-- the tests roll everything back afterwards.
+insert into users (id, username, password, email, user_type)
+values (900001, 'fixture_channel', '', 'fixture_channel@example.com', 'CHANNEL');
+
+insert into token_economics (id, effective_from, token_value, currency, channel_token_expiry_days)
+values (900001, '2000-01-01', 50, 'XTS', 30);
+
+insert into ledger_accounts (id, account_type, user_id, currency)
+values (900001, 'PLATFORM_REVENUE', null, 'XTS'),
+       (900002, 'CHANNEL_EARNINGS', 900001, 'XTS'),
+       (900003, 'PAYOUTS_IN_FLIGHT', 900001, 'XTS'),
+       (900004, 'PAYOUTS_PAID', null, 'XTS');
+
+insert into ledger_transactions (id, transaction_type, created)
+values (900001, 'TOKEN_CONVERSION', '2021-05-01 12:00'),
+       (900002, 'TOKEN_CONVERSION', '2021-06-01 12:00'),
+       (900003, 'WITHDRAWAL', '2021-06-10 09:00'),
+       (900004, 'WITHDRAWAL_PAID', '2021-06-12 09:00'),
+       (900005, 'WITHDRAWAL_REVERSED', '2021-06-20 09:00'),
+       (900006, 'TOKEN_CONVERSION', '2021-07-01 12:00');
+
+insert into ledger_entries (id, transaction_id, account_id, amount)
+values (900001, 900001, 900001, -500),
+       (900002, 900001, 900002, 500),
+       (900003, 900002, 900001, -200),
+       (900004, 900002, 900002, 200),
+       (900005, 900003, 900002, -300),
+       (900006, 900003, 900003, 300),
+       (900007, 900004, 900003, -300),
+       (900008, 900004, 900004, 300),
+       (900009, 900005, 900004, -100),
+       (900010, 900005, 900002, 100),
+       (900011, 900006, 900001, -100),
+       (900012, 900006, 900002, 100);
+
+insert into token_transactions (id, channel_user_id, transaction_type, amount, date, economics_id, token_count, ledger_transaction_id)
+values (900001, 900001, 'DEPOSIT', 500, '2021-05-01 12:00', 900001, 10, 900001),
+       (900002, 900001, 'DEPOSIT', 200, '2021-06-01 12:00', 900001, 4, 900002),
+       (900003, 900001, 'WITHDRAWAL', 300, '2021-06-10 09:00', 900001, null, 900003),
+       (900004, 900001, 'REVERSAL', 100, '2021-06-20 09:00', 900001, null, 900005),
+       (900005, 900001, 'DEPOSIT', 100, '2021-07-01 12:00', 900001, 2, 900006);
@@ -0,0 +1,12 @@
+-- Fixture for the webhook tests. Fixture events sent to a local server are removed so every test
+-- starts without them, the tests roll everything back afterwards.
+delete from stripe_events where id like 'evt_fixture_%';
+
+insert into users (id, username, password, email, user_type, stripe_account)
+values (900001, 'fixture_channel', '', 'fixture_channel@example.com', 'CHANNEL', 'acct_fixture');
+
+-- A subscriber who resumed after sub_fixture ended, so they are on a newer subscription
+update users set stripe_customer = '' where stripe_customer = 'cus_fixture';
+
+insert into users (id, username, password, email, user_type, stripe_customer, stripe_subscription, subscription_status, subscribed)
+values (900002, 'fixture_subscriber', '', 'fixture_subscriber@example.com', 'SUBSCRIBER', 'cus_fixture', 'sub_resumed', 'ACTIVE', true);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+alter table videos_tags
+    drop constraint videos_tags_video_tag_key;
+
+alter table tags
+    drop constraint tags_name_key;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+delete from videos_tags a
+    using videos_tags b
+where a.id > b.id
+  and a.video_id = b.video_id
+  and a.tag_id = b.tag_id;
+
+alter table videos_tags
+    add constraint videos_tags_video_tag_key
+        unique (video_id, tag_id);
+
+alter table tags
+    add constraint tags_name_key
+        unique (name);
@@ -0,0 +1,45 @@
+-- Your SQL goes here
+create table if not exists playlists
+(
+    id serial not null primary key ,
+    user_id integer not null,
+    name varchar(128) not null,
+    visibility varchar(16) not null default 'PRIVATE',
+    watch_later boolean not null default false,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+alter table playlists drop constraint if exists fk_user;
+alter table playlists
+    add constraint fk_user
+        foreign key (user_id)
+            references users (id)
+            on delete cascade;
+
+-- Every user has at most one "Watch later" playlist
+create unique index playlists_watch_later_key on playlists (user_id) where watch_later;
+
+create table if not exists playlists_videos
+(
+    id serial not null primary key ,
+    playlist_id integer not null,
+    video_id integer not null,
+    position integer not null,
+    date_added timestamp default CURRENT_TIMESTAMP not null,
+    constraint playlists_videos_playlist_video_key
+        unique (playlist_id, video_id)
+);
+
+alter table playlists_videos drop constraint if exists fk_playlist;
+alter table playlists_videos
+    add constraint fk_playlist
+        foreign key (playlist_id)
+            references playlists (id)
+            on delete cascade;
+
+alter table playlists_videos drop constraint if exists fk_video;
+alter table playlists_videos
+    add constraint fk_video
+        foreign key (video_id)
+            references videos (id)
+            on delete cascade;
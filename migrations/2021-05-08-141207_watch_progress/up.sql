@@ -0,0 +1,46 @@
+-- Your SQL goes here
+alter table videos
+    add column duration integer;
+
+create table if not exists video_progress
+(
+    id serial not null primary key ,
+    user_id integer not null,
+    video_id integer not null,
+    position integer not null default 0,
+    watched_seconds integer not null default 0,
+    duration integer,
+    last_updated timestamp default CURRENT_TIMESTAMP not null,
+    constraint video_progress_user_video_key
+        unique (user_id, video_id)
+);
+
+alter table video_progress drop constraint if exists fk_user;
+alter table video_progress
+    add constraint fk_user
+        foreign key (user_id)
+            references users (id)
+            on delete cascade;
+
+alter table video_progress drop constraint if exists fk_video;
+alter table video_progress
+    add constraint fk_video
+        foreign key (video_id)
+            references videos (id)
+            on delete cascade;
+
+create index video_progress_user_last_updated_idx on video_progress (user_id, last_updated desc);
+
+-- The previous version compared against the future, so every play counted as recent
+create or replace function video_recently_watched(u_id integer, v_id integer)
+    returns boolean
+    language 'plpgsql'
+as $BODY$
+begin
+    return exists(
+            select *
+            from video_plays
+            where user_id = u_id and video_id = v_id and date > current_timestamp - interval '5 days'
+        );
+end
+$BODY$;
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+alter table video_progress
+    drop column session_seconds;
+
+drop index video_plays_user_video_date_idx;
+
+alter table video_plays
+    drop column play_event_id;
+
+drop table play_flags;
+drop table play_events;
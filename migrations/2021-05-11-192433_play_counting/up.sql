@@ -0,0 +1,55 @@
+-- Your SQL goes here
+
+-- Every call to /video/increment-play is kept here. Only plays which pass the
+-- counting rules make it into video_plays.
+create table if not exists play_events
+(
+    id serial not null primary key ,
+    user_id integer not null,
+    video_id integer not null,
+    ip varchar(64),
+    date timestamp default CURRENT_TIMESTAMP not null,
+    counted boolean not null default false,
+    flagged boolean not null default false
+);
+
+alter table play_events drop constraint if exists fk_user;
+alter table play_events
+    add constraint fk_user
+        foreign key (user_id)
+            references users (id)
+            on delete cascade;
+
+alter table play_events drop constraint if exists fk_video;
+alter table play_events
+    add constraint fk_video
+        foreign key (video_id)
+            references videos (id)
+            on delete cascade;
+
+create index play_events_user_date_idx on play_events (user_id, date);
+create index play_events_ip_date_idx on play_events (ip, date);
+create index play_events_user_video_date_idx on play_events (user_id, video_id, date);
+
+create table if not exists play_flags
+(
+    id serial not null primary key ,
+    user_id integer,
+    ip varchar(64),
+    reason varchar(128) not null,
+    date timestamp default CURRENT_TIMESTAMP not null
+);
+
+alter table video_plays
+    add column play_event_id integer;
+
+alter table video_plays
+    add constraint fk_play_event
+        foreign key (play_event_id)
+            references play_events (id)
+            on delete set null;
+
+create index video_plays_user_video_date_idx on video_plays (user_id, video_id, date);
+
+alter table video_progress
+    add column session_seconds integer not null default 0;
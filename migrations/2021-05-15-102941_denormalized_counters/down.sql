@@ -0,0 +1,29 @@
+-- This file should undo anything in `up.sql`
+drop trigger channels_tokens_counter on channels_tokens;
+drop trigger video_plays_counter on video_plays;
+drop trigger comment_upvotes_counter on comment_upvotes;
+drop trigger video_upvotes_counter on video_upvotes;
+
+drop function reconcile_counters;
+drop function channels_tokens_counter_trigger;
+drop function video_plays_counter_trigger;
+drop function comment_upvotes_counter_trigger;
+drop function video_upvotes_counter_trigger;
+drop function refresh_comment_vote_counts;
+drop function refresh_video_vote_counts;
+
+drop index channels_tokens_channel_user_idx;
+drop index comment_upvotes_comment_idx;
+drop index video_upvotes_video_idx;
+
+alter table users
+    drop column subscriber_count;
+
+alter table comments
+    drop column upvote_count,
+    drop column downvote_count;
+
+alter table videos
+    drop column upvote_count,
+    drop column downvote_count,
+    drop column play_count;
@@ -0,0 +1,180 @@
+-- Your SQL goes here
+alter table videos
+    add column upvote_count integer not null default 0,
+    add column downvote_count integer not null default 0,
+    add column play_count integer not null default 0;
+
+alter table comments
+    add column upvote_count integer not null default 0,
+    add column downvote_count integer not null default 0;
+
+alter table users
+    add column subscriber_count integer not null default 0;
+
+create index if not exists video_upvotes_video_idx on video_upvotes (video_id);
+create index if not exists comment_upvotes_comment_idx on comment_upvotes (comment_id);
+create index if not exists channels_tokens_channel_user_idx on channels_tokens (channel_user_id);
+
+-- Votes can be toggled, so the counts for the affected row are recalculated rather than incremented
+create or replace function refresh_video_vote_counts(v_id integer)
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    update videos set
+        upvote_count = (select count(*) from video_upvotes where video_id = v_id and inactive = false and upvote_type = 'UP'),
+        downvote_count = (select count(*) from video_upvotes where video_id = v_id and inactive = false and upvote_type = 'DOWN')
+    where id = v_id;
+end
+$BODY$;
+
+create or replace function refresh_comment_vote_counts(c_id integer)
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    update comments set
+        upvote_count = (select count(*) from comment_upvotes where comment_id = c_id and inactive = false and upvote_type = 'UP'),
+        downvote_count = (select count(*) from comment_upvotes where comment_id = c_id and inactive = false and upvote_type = 'DOWN')
+    where id = c_id;
+end
+$BODY$;
+
+create or replace function video_upvotes_counter_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+begin
+    if tg_op in ('UPDATE', 'DELETE') then
+        perform refresh_video_vote_counts(old.video_id);
+    end if;
+
+    if tg_op in ('INSERT', 'UPDATE') then
+        perform refresh_video_vote_counts(new.video_id);
+    end if;
+
+    return null;
+end
+$BODY$;
+
+create or replace function comment_upvotes_counter_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+begin
+    if tg_op in ('UPDATE', 'DELETE') then
+        perform refresh_comment_vote_counts(old.comment_id);
+    end if;
+
+    if tg_op in ('INSERT', 'UPDATE') then
+        perform refresh_comment_vote_counts(new.comment_id);
+    end if;
+
+    return null;
+end
+$BODY$;
+
+create or replace function video_plays_counter_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+begin
+    if tg_op = 'INSERT' then
+        update videos set play_count = play_count + 1 where id = new.video_id;
+    elsif tg_op = 'DELETE' then
+        update videos set play_count = play_count - 1 where id = old.video_id;
+    end if;
+
+    return null;
+end
+$BODY$;
+
+create or replace function channels_tokens_counter_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+begin
+    if tg_op = 'INSERT' then
+        update users set subscriber_count = subscriber_count + 1 where id = new.channel_user_id;
+    elsif tg_op = 'DELETE' then
+        update users set subscriber_count = subscriber_count - 1 where id = old.channel_user_id;
+    end if;
+
+    return null;
+end
+$BODY$;
+
+create trigger video_upvotes_counter
+    after insert or update or delete on video_upvotes
+    for each row execute procedure video_upvotes_counter_trigger();
+
+create trigger comment_upvotes_counter
+    after insert or update or delete on comment_upvotes
+    for each row execute procedure comment_upvotes_counter_trigger();
+
+create trigger video_plays_counter
+    after insert or delete on video_plays
+    for each row execute procedure video_plays_counter_trigger();
+
+create trigger channels_tokens_counter
+    after insert or delete on channels_tokens
+    for each row execute procedure channels_tokens_counter_trigger();
+
+-- Recalculates every counter from the source tables. Returns the number of rows which were
+-- out of date, so drift can be spotted.
+create or replace function reconcile_counters(out fixed integer)
+    returns integer
+    language 'plpgsql'
+as $BODY$
+declare
+    changed integer;
+begin
+    fixed := 0;
+
+    update videos set
+        upvote_count = counts.upvotes,
+        downvote_count = counts.downvotes,
+        play_count = counts.plays
+    from (
+        select v.id,
+               (select count(*) from video_upvotes where video_id = v.id and inactive = false and upvote_type = 'UP') as upvotes,
+               (select count(*) from video_upvotes where video_id = v.id and inactive = false and upvote_type = 'DOWN') as downvotes,
+               (select count(*) from video_plays where video_id = v.id) as plays
+        from videos v
+    ) counts
+    where videos.id = counts.id
+      and (videos.upvote_count, videos.downvote_count, videos.play_count) is distinct from (counts.upvotes, counts.downvotes, counts.plays);
+    get diagnostics changed = row_count;
+    fixed := fixed + changed;
+
+    update comments set
+        upvote_count = counts.upvotes,
+        downvote_count = counts.downvotes
+    from (
+        select c.id,
+               (select count(*) from comment_upvotes where comment_id = c.id and inactive = false and upvote_type = 'UP') as upvotes,
+               (select count(*) from comment_upvotes where comment_id = c.id and inactive = false and upvote_type = 'DOWN') as downvotes
+        from comments c
+    ) counts
+    where comments.id = counts.id
+      and (comments.upvote_count, comments.downvote_count) is distinct from (counts.upvotes, counts.downvotes);
+    get diagnostics changed = row_count;
+    fixed := fixed + changed;
+
+    update users set
+        subscriber_count = counts.subscribers
+    from (
+        select u.id, (select count(*) from channels_tokens where channel_user_id = u.id) as subscribers
+        from users u
+    ) counts
+    where users.id = counts.id
+      and users.subscriber_count <> counts.subscribers;
+    get diagnostics changed = row_count;
+    fixed := fixed + changed;
+
+    return;
+end
+$BODY$;
+
+-- Backfill
+select reconcile_counters();
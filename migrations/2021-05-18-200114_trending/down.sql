@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+drop function refresh_trending_scores;
+
+drop index video_plays_date_idx;
+drop index videos_trending_score_idx;
+
+alter table videos
+    drop column trending_score;
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+alter table videos
+    add column trending_score double precision not null default 0;
+
+create index videos_trending_score_idx on videos (trending_score desc);
+create index video_plays_date_idx on video_plays (date);
+
+-- Trending score = plays in the last 48 hours * smoothed upvote ratio, decayed by age in hours.
+-- A video with no recent plays isn't trending.
+create or replace function refresh_trending_scores()
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    update videos set
+        trending_score = scores.score
+    from (
+        select v.id,
+               coalesce(recent.plays, 0)
+                   * ((v.upvote_count + 1.0) / (v.upvote_count + v.downvote_count + 2.0))
+                   / power(extract(epoch from (current_timestamp - v.upload_date)) / 3600 + 2, 1.5) as score
+        from videos v
+                 left join (
+                     select video_id, count(*) as plays
+                     from video_plays
+                     where date > current_timestamp - interval '48 hours'
+                     group by video_id
+                 ) recent on recent.video_id = v.id
+    ) scores
+    where videos.id = scores.id
+      and videos.trending_score <> scores.score;
+end
+$BODY$;
+
+select refresh_trending_scores();
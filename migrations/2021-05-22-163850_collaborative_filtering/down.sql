@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+drop function refresh_user_recommendations;
+drop function refresh_video_similarities;
+
+drop table user_recommendations;
+drop table video_similarities;
+
+drop view video_interactions;
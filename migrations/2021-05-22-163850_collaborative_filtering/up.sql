@@ -0,0 +1,117 @@
+-- Your SQL goes here
+
+-- How strongly each user has interacted with each video. A counted play is worth 1,
+-- an upvote 2 and a downvote -1. Only positive interactions are kept.
+create or replace view video_interactions as
+select user_id, video_id, sum(weight) as weight
+from (
+         select user_id, video_id, 1.0 as weight
+         from video_plays
+         group by user_id, video_id
+         union all
+         select user_id, video_id, case when upvote_type = 'UP' then 2.0 else -1.0 end as weight
+         from video_upvotes
+         where inactive = false
+     ) interactions
+group by user_id, video_id
+having sum(weight) > 0;
+
+create table if not exists video_similarities
+(
+    video_id integer not null,
+    similar_video_id integer not null,
+    score double precision not null,
+    primary key (video_id, similar_video_id)
+);
+
+create table if not exists user_recommendations
+(
+    user_id integer not null,
+    video_id integer not null,
+    score double precision not null,
+    rank integer not null,
+    generated timestamp default CURRENT_TIMESTAMP not null,
+    primary key (user_id, video_id)
+);
+
+create index user_recommendations_user_rank_idx on user_recommendations (user_id, rank);
+
+-- Item to item cosine similarity over video_interactions. Pairs need at least two users in
+-- common and only the 50 most similar videos are kept for each video.
+create or replace function refresh_video_similarities()
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    delete from video_similarities;
+
+    insert into video_similarities (video_id, similar_video_id, score)
+    select video_id, similar_video_id, score
+    from (
+             select pairs.video_id,
+                    pairs.similar_video_id,
+                    pairs.dot / (na.norm * nb.norm) as score,
+                    row_number() over (partition by pairs.video_id order by pairs.dot / (na.norm * nb.norm) desc, pairs.similar_video_id) as position
+             from (
+                      select a.video_id, b.video_id as similar_video_id, sum(a.weight * b.weight) as dot
+                      from video_interactions a
+                               inner join video_interactions b on a.user_id = b.user_id and a.video_id <> b.video_id
+                      group by a.video_id, b.video_id
+                      having count(*) >= 2
+                  ) pairs
+                      inner join (select video_id, sqrt(sum(weight * weight)) as norm from video_interactions group by video_id) na
+                                 on na.video_id = pairs.video_id
+                      inner join (select video_id, sqrt(sum(weight * weight)) as norm from video_interactions group by video_id) nb
+                                 on nb.video_id = pairs.similar_video_id
+         ) ranked
+    where position <= 50;
+end
+$BODY$;
+
+-- Scores unwatched READY videos for every user with at least one interaction. The
+-- collaborative filtering score is blended with tag affinity (how often the user has played
+-- videos with the same tags). Users with few interactions lean on tag affinity more.
+create or replace function refresh_user_recommendations()
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    delete from user_recommendations;
+
+    insert into user_recommendations (user_id, video_id, score, rank)
+    select user_id, video_id, score, rank
+    from (
+             select candidates.user_id,
+                    candidates.video_id,
+                    candidates.score,
+                    row_number() over (partition by candidates.user_id order by candidates.score desc, candidates.video_id desc) as rank
+             from (
+                      select scores.user_id,
+                             scores.video_id,
+                             sum(scores.cf_score) + sum(scores.tag_score) / (1.0 + activity.interactions / 5.0) as score
+                      from (
+                               select i.user_id, s.similar_video_id as video_id, i.weight * s.score as cf_score, 0.0 as tag_score
+                               from video_interactions i
+                                        inner join video_similarities s on s.video_id = i.video_id
+                               union all
+                               select user_tags.user_id, vt.video_id, 0.0 as cf_score, user_tags.share as tag_score
+                               from (
+                                        select p.user_id, vt.tag_id, count(*)::float / sum(count(*)) over (partition by p.user_id) as share
+                                        from video_plays p
+                                                 inner join videos_tags vt on vt.video_id = p.video_id
+                                        group by p.user_id, vt.tag_id
+                                    ) user_tags
+                                        inner join videos_tags vt on vt.tag_id = user_tags.tag_id
+                           ) scores
+                               inner join (select user_id, count(*) as interactions from video_interactions group by user_id) activity
+                                          on activity.user_id = scores.user_id
+                               inner join videos v on v.id = scores.video_id
+                      where v.status = 'READY'
+                        and v.user_id <> scores.user_id
+                        and not exists(select * from video_plays p where p.user_id = scores.user_id and p.video_id = scores.video_id)
+                      group by scores.user_id, scores.video_id, activity.interactions
+                  ) candidates
+         ) ranked
+    where rank <= 200;
+end
+$BODY$;
@@ -0,0 +1,52 @@
+-- This file should undo anything in `up.sql`
+create or replace function refresh_user_recommendations()
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    delete from user_recommendations;
+
+    insert into user_recommendations (user_id, video_id, score, rank)
+    select user_id, video_id, score, rank
+    from (
+             select candidates.user_id,
+                    candidates.video_id,
+                    candidates.score,
+                    row_number() over (partition by candidates.user_id order by candidates.score desc, candidates.video_id desc) as rank
+             from (
+                      select scores.user_id,
+                             scores.video_id,
+                             sum(scores.cf_score) + sum(scores.tag_score) / (1.0 + activity.interactions / 5.0) as score
+                      from (
+                               select i.user_id, s.similar_video_id as video_id, i.weight * s.score as cf_score, 0.0 as tag_score
+                               from video_interactions i
+                                        inner join video_similarities s on s.video_id = i.video_id
+                               union all
+                               select user_tags.user_id, vt.video_id, 0.0 as cf_score, user_tags.share as tag_score
+                               from (
+                                        select p.user_id, vt.tag_id, count(*)::float / sum(count(*)) over (partition by p.user_id) as share
+                                        from video_plays p
+                                                 inner join videos_tags vt on vt.video_id = p.video_id
+                                        group by p.user_id, vt.tag_id
+                                    ) user_tags
+                                        inner join videos_tags vt on vt.tag_id = user_tags.tag_id
+                           ) scores
+                               inner join (select user_id, count(*) as interactions from video_interactions group by user_id) activity
+                                          on activity.user_id = scores.user_id
+                               inner join videos v on v.id = scores.video_id
+                      where v.status = 'READY'
+                        and v.user_id <> scores.user_id
+                        and not exists(select * from video_plays p where p.user_id = scores.user_id and p.video_id = scores.video_id)
+                      group by scores.user_id, scores.video_id, activity.interactions
+                  ) candidates
+         ) ranked
+    where rank <= 200;
+end
+$BODY$;
+
+drop table recommendation_feedback;
+
+alter table user_recommendations
+    drop column reason_type,
+    drop column reason_video_id,
+    drop column reason_tag_id;
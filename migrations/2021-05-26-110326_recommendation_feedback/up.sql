@@ -0,0 +1,102 @@
+-- Your SQL goes here
+alter table user_recommendations
+    add column reason_type varchar(16) not null default 'TAG',
+    add column reason_video_id integer,
+    add column reason_tag_id integer;
+
+create table if not exists recommendation_feedback
+(
+    id serial not null primary key ,
+    user_id integer not null,
+    feedback_type varchar(32) not null,
+    target_id integer not null,
+    date timestamp default CURRENT_TIMESTAMP not null,
+    constraint recommendation_feedback_user_type_target_key
+        unique (user_id, feedback_type, target_id)
+);
+
+alter table recommendation_feedback drop constraint if exists fk_user;
+alter table recommendation_feedback
+    add constraint fk_user
+        foreign key (user_id)
+            references users (id)
+            on delete cascade;
+
+-- Same as before, but each recommendation records why it was made and the user's feedback
+-- is respected:
+--   NOT_INTERESTED_VIDEO  the video is never recommended
+--   NOT_INTERESTED_CHANNEL  no videos from the channel are recommended
+--   SEE_LESS_TAG  the tag doesn't count towards tag affinity, and videos with the tag
+--                 score a quarter as much per "see less" tag they have
+-- The reason is WATCHED (reason_video_id is the watched video which contributed most) when
+-- collaborative filtering outweighs tag affinity, otherwise TAG (reason_tag_id).
+create or replace function refresh_user_recommendations()
+    returns void
+    language 'plpgsql'
+as $BODY$
+begin
+    delete from user_recommendations;
+
+    insert into user_recommendations (user_id, video_id, score, rank, reason_type, reason_video_id, reason_tag_id)
+    select user_id, video_id, score, rank, reason_type, reason_video_id, reason_tag_id
+    from (
+             select candidates.*,
+                    row_number() over (partition by candidates.user_id order by candidates.score desc, candidates.video_id desc) as rank
+             from (
+                      select scores.user_id,
+                             scores.video_id,
+                             (sum(scores.cf_score) + sum(scores.tag_score) / (1.0 + activity.interactions / 5.0))
+                                 * power(0.25, (select count(*)
+                                                from videos_tags vt
+                                                         inner join recommendation_feedback f
+                                                                    on f.target_id = vt.tag_id and f.feedback_type = 'SEE_LESS_TAG'
+                                                where vt.video_id = scores.video_id and f.user_id = scores.user_id)) as score,
+                             case
+                                 when sum(scores.cf_score) > 0 and sum(scores.cf_score) >= sum(scores.tag_score) / (1.0 + activity.interactions / 5.0)
+                                     then 'WATCHED'
+                                 else 'TAG'
+                                 end as reason_type,
+                             (array_agg(scores.source_video_id order by scores.cf_score desc) filter (where scores.source_video_id is not null))[1] as reason_video_id,
+                             (array_agg(scores.source_tag_id order by scores.tag_score desc) filter (where scores.source_tag_id is not null))[1] as reason_tag_id
+                      from (
+                               select i.user_id,
+                                      s.similar_video_id as video_id,
+                                      i.weight * s.score as cf_score,
+                                      0.0 as tag_score,
+                                      i.video_id as source_video_id,
+                                      null::integer as source_tag_id
+                               from video_interactions i
+                                        inner join video_similarities s on s.video_id = i.video_id
+                               union all
+                               select user_tags.user_id,
+                                      vt.video_id,
+                                      0.0 as cf_score,
+                                      user_tags.share as tag_score,
+                                      null::integer as source_video_id,
+                                      user_tags.tag_id as source_tag_id
+                               from (
+                                        select p.user_id, vt.tag_id, count(*)::float / sum(count(*)) over (partition by p.user_id) as share
+                                        from video_plays p
+                                                 inner join videos_tags vt on vt.video_id = p.video_id
+                                        where not exists(select * from recommendation_feedback f
+                                                         where f.user_id = p.user_id and f.feedback_type = 'SEE_LESS_TAG' and f.target_id = vt.tag_id)
+                                        group by p.user_id, vt.tag_id
+                                    ) user_tags
+                                        inner join videos_tags vt on vt.tag_id = user_tags.tag_id
+                           ) scores
+                               inner join (select user_id, count(*) as interactions from video_interactions group by user_id) activity
+                                          on activity.user_id = scores.user_id
+                               inner join videos v on v.id = scores.video_id
+                      where v.status = 'READY'
+                        and v.user_id <> scores.user_id
+                        and not exists(select * from video_plays p where p.user_id = scores.user_id and p.video_id = scores.video_id)
+                        and not exists(select * from recommendation_feedback f
+                                       where f.user_id = scores.user_id and f.feedback_type = 'NOT_INTERESTED_VIDEO' and f.target_id = v.id)
+                        and not exists(select * from recommendation_feedback f
+                                       where f.user_id = scores.user_id and f.feedback_type = 'NOT_INTERESTED_CHANNEL' and f.target_id = v.user_id)
+                      group by scores.user_id, scores.video_id, activity.interactions
+                  ) candidates
+         ) ranked
+    where rank <= 200;
+end
+$BODY$;
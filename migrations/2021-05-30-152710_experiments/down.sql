@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop view experiment_report;
+drop table experiment_events;
+drop table experiment_variants;
+drop table experiments;
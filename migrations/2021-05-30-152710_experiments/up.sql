@@ -0,0 +1,83 @@
+-- Your SQL goes here
+create table if not exists experiments
+(
+    id serial not null primary key ,
+    name varchar(128) not null
+        constraint experiments_name_key
+            unique,
+    description varchar(1024),
+    active boolean not null default false,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+create table if not exists experiment_variants
+(
+    id serial not null primary key ,
+    experiment_id integer not null,
+    name varchar(128) not null,
+    weight integer not null
+        constraint experiment_variants_weight_check
+            check (weight > 0),
+    constraint experiment_variants_experiment_name_key
+        unique (experiment_id, name)
+);
+
+alter table experiment_variants drop constraint if exists fk_experiment;
+alter table experiment_variants
+    add constraint fk_experiment
+        foreign key (experiment_id)
+            references experiments (id)
+            on delete cascade;
+
+create table if not exists experiment_events
+(
+    id serial not null primary key ,
+    experiment_id integer not null,
+    variant_id integer not null,
+    user_id integer not null,
+    event_type varchar(32) not null,
+    date timestamp default CURRENT_TIMESTAMP not null
+);
+
+alter table experiment_events drop constraint if exists fk_experiment;
+alter table experiment_events
+    add constraint fk_experiment
+        foreign key (experiment_id)
+            references experiments (id)
+            on delete cascade;
+
+alter table experiment_events drop constraint if exists fk_variant;
+alter table experiment_events
+    add constraint fk_variant
+        foreign key (variant_id)
+            references experiment_variants (id)
+            on delete cascade;
+
+alter table experiment_events drop constraint if exists fk_user;
+alter table experiment_events
+    add constraint fk_user
+        foreign key (user_id)
+            references users (id)
+            on delete cascade;
+
+-- A user is only exposed to an experiment once
+create unique index experiment_events_exposure_key on experiment_events (experiment_id, user_id) where event_type = 'EXPOSURE';
+create index experiment_events_user_type_idx on experiment_events (user_id, event_type);
+create index experiment_events_variant_type_idx on experiment_events (variant_id, event_type);
+
+-- Per variant totals, e.g. select * from experiment_report where experiment = 'recommender'
+create or replace view experiment_report as
+select x.name as experiment,
+       v.name as variant,
+       v.weight,
+       count(*) filter (where e.event_type = 'EXPOSURE') as exposed_users,
+       count(*) filter (where e.event_type = 'PLAY') as plays,
+       count(*) filter (where e.event_type = 'UPVOTE') as upvotes,
+       count(*) filter (where e.event_type = 'TOKEN_TRANSFER') as token_transfers,
+       count(*) filter (where e.event_type = 'PLAY')::float / nullif(count(*) filter (where e.event_type = 'EXPOSURE'), 0) as plays_per_user,
+       count(*) filter (where e.event_type = 'UPVOTE')::float / nullif(count(*) filter (where e.event_type = 'EXPOSURE'), 0) as upvotes_per_user,
+       count(*) filter (where e.event_type = 'TOKEN_TRANSFER')::float / nullif(count(*) filter (where e.event_type = 'EXPOSURE'), 0) as token_transfers_per_user
+from experiments x
+         inner join experiment_variants v on v.experiment_id = x.id
+         left join experiment_events e on e.variant_id = v.id
+group by x.id, v.id;
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+alter table token_transactions
+    drop column economics_id,
+    drop column token_count;
+
+alter table users
+    drop column plan;
+
+drop table token_plans;
+drop table token_economics;
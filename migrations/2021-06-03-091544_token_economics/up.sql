@@ -0,0 +1,60 @@
+-- Your SQL goes here
+
+-- Each row is a version of the token economics. The version in effect at a point in time is
+-- the one with the latest effective_from before it.
+create table if not exists token_economics
+(
+    id serial not null primary key ,
+    effective_from timestamp not null
+        constraint token_economics_effective_from_key
+            unique,
+    token_value integer not null, -- in the smallest unit of the currency, e.g. pence
+    currency varchar(3) not null,
+    channel_token_expiry_days integer not null,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+create table if not exists token_plans
+(
+    id serial not null primary key ,
+    economics_id integer not null,
+    plan varchar(32) not null,
+    stripe_price_id varchar(64) not null,
+    monthly_tokens integer not null,
+    constraint token_plans_economics_plan_key
+        unique (economics_id, plan)
+);
+
+alter table token_plans drop constraint if exists fk_economics;
+alter table token_plans
+    add constraint fk_economics
+        foreign key (economics_id)
+            references token_economics (id)
+            on delete cascade;
+
+alter table users
+    add column plan varchar(32) not null default 'STANDARD';
+
+-- Which rates a conversion used
+alter table token_transactions
+    add column economics_id integer,
+    add column token_count integer;
+
+alter table token_transactions
+    add constraint fk_economics
+        foreign key (economics_id)
+            references token_economics (id)
+            on delete restrict;
+
+-- The values which used to be hard coded
+insert into token_economics (effective_from, token_value, currency, channel_token_expiry_days)
+values ('2021-01-01 00:00:00', 180, 'gbp', 30);
+
+insert into token_plans (economics_id, plan, stripe_price_id, monthly_tokens)
+select id, 'STANDARD', 'price_1IQztpIahEIGROhzWnYhQv1I', 5
+from token_economics;
+
+update token_transactions
+set economics_id = (select id from token_economics),
+    token_count = amount / 180
+where transaction_type = 'DEPOSIT';
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+alter table channels_tokens drop constraint if exists fk_transaction;
+alter table channels_tokens drop column if exists transaction_id;
+
+drop index if exists token_transactions_deposit_period_key;
+alter table token_transactions drop column if exists period;
+
+alter table tokens drop constraint if exists fk_grant;
+alter table tokens drop column if exists grant_id;
+
+drop table if exists token_grants;
+drop table if exists job_runs;
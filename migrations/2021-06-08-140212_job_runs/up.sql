@@ -0,0 +1,65 @@
+-- Your SQL goes here
+
+-- One row per run of a periodic job. The period is the key the job does its work for,
+-- e.g. '2021-06' for the monthly token jobs, so a second run for the same period can tell
+-- that the work has already been done.
+create table if not exists job_runs
+(
+    id serial not null primary key ,
+    job_name varchar(64) not null,
+    period varchar(32) not null,
+    status varchar(16) default 'RUNNING' not null, -- RUNNING, COMPLETED, FAILED
+    processed integer default 0 not null,
+    attempts integer default 1 not null,
+    error text,
+    started_at timestamp default CURRENT_TIMESTAMP not null,
+    finished_at timestamp,
+    constraint job_runs_job_name_period_key
+        unique (job_name, period)
+);
+
+-- Monthly token grants. The unique key stops a user from being granted tokens twice
+-- for the same period no matter how many times the job runs.
+create table if not exists token_grants
+(
+    id serial not null primary key ,
+    user_id integer not null,
+    period varchar(32) not null,
+    token_count integer not null,
+    created timestamp default CURRENT_TIMESTAMP not null,
+    constraint token_grants_user_id_period_key
+        unique (user_id, period)
+);
+
+alter table token_grants drop constraint if exists fk_user;
+alter table token_grants
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+alter table tokens add column if not exists grant_id integer;
+
+alter table tokens drop constraint if exists fk_grant;
+alter table tokens
+    add constraint fk_grant
+        foreign key (grant_id)
+            references token_grants(id)
+            on delete set null;
+
+-- Deposits from converting channel tokens are made once per channel per period.
+alter table token_transactions add column if not exists period varchar(32);
+
+create unique index if not exists token_transactions_deposit_period_key
+    on token_transactions (channel_user_id, period)
+    where transaction_type = 'DEPOSIT' and period is not null;
+
+-- The deposit a channel token was converted into
+alter table channels_tokens add column if not exists transaction_id integer;
+
+alter table channels_tokens drop constraint if exists fk_transaction;
+alter table channels_tokens
+    add constraint fk_transaction
+        foreign key (transaction_id)
+            references token_transactions(id)
+            on delete set null;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Schedule and controls for each background job. Rows are created by the scheduler on start
+-- with the job's default schedule, after that the schedule is managed from the admin endpoints.
+create table if not exists scheduled_jobs
+(
+    id serial not null primary key ,
+    name varchar(64) not null
+        constraint scheduled_jobs_name_key
+            unique,
+    schedule varchar(64) not null, -- cron expression with seconds, e.g. '0 0 3 * * *'
+    paused boolean default false not null,
+    run_requested boolean default false not null,
+    max_attempts integer default 3 not null,
+    retry_delay_seconds integer default 60 not null,
+    next_run_at timestamp,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
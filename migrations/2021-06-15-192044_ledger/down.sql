@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+alter table token_transactions drop constraint if exists fk_ledger_transaction;
+alter table token_transactions drop column if exists ledger_transaction_id;
+
+drop function if exists ledger_balance(integer);
+drop trigger if exists ledger_entries_balanced on ledger_entries;
+drop function if exists ledger_entries_balanced_trigger();
+
+drop table if exists ledger_entries;
+drop table if exists ledger_transactions;
+drop table if exists ledger_accounts;
@@ -0,0 +1,162 @@
+-- Your SQL goes here
+
+-- Double-entry ledger for money owed to and paid to channels. Every ledger transaction has
+-- entries which add up to zero, an account's balance is the sum of its entries. Positive
+-- amounts are credits.
+--
+-- Account types:
+--   PLATFORM_REVENUE   one per currency, what the platform pays channels out of
+--   CHANNEL_EARNINGS   per channel, money the channel can withdraw
+--   PAYOUTS_IN_FLIGHT  per channel, withdrawals sent to Stripe which haven't completed yet
+--   PAYOUTS_PAID       one per currency, money which has left the platform
+create table if not exists ledger_accounts
+(
+    id serial not null primary key ,
+    account_type varchar(32) not null,
+    user_id integer,
+    currency varchar(3) not null,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+create unique index if not exists ledger_accounts_user_key
+    on ledger_accounts (account_type, user_id, currency)
+    where user_id is not null;
+
+create unique index if not exists ledger_accounts_platform_key
+    on ledger_accounts (account_type, currency)
+    where user_id is null;
+
+-- Money isn't deleted with the user
+alter table ledger_accounts drop constraint if exists fk_user;
+alter table ledger_accounts
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete restrict;
+
+create table if not exists ledger_transactions
+(
+    id serial not null primary key ,
+    transaction_type varchar(32) not null, -- TOKEN_CONVERSION, WITHDRAWAL, WITHDRAWAL_PAID, WITHDRAWAL_FAILED
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+create table if not exists ledger_entries
+(
+    id serial not null primary key ,
+    transaction_id integer not null,
+    account_id integer not null,
+    amount bigint not null
+        constraint ledger_entries_amount_check
+            check (amount <> 0)
+);
+
+create index if not exists ledger_entries_account_id_idx on ledger_entries (account_id);
+create index if not exists ledger_entries_transaction_id_idx on ledger_entries (transaction_id);
+
+alter table ledger_entries drop constraint if exists fk_transaction;
+alter table ledger_entries
+    add constraint fk_transaction
+        foreign key (transaction_id)
+            references ledger_transactions(id)
+            on delete restrict;
+
+alter table ledger_entries drop constraint if exists fk_account;
+alter table ledger_entries
+    add constraint fk_account
+        foreign key (account_id)
+            references ledger_accounts(id)
+            on delete restrict;
+
+-- Checked at commit, so all the entries of a transaction can be inserted first
+create or replace function ledger_entries_balanced_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+declare
+    total bigint;
+begin
+    select coalesce(sum(amount), 0) into total
+    from ledger_entries
+    where transaction_id = new.transaction_id;
+
+    if total <> 0 then
+        raise exception 'Ledger transaction % does not balance, entries add up to %', new.transaction_id, total;
+    end if;
+
+    return null;
+end
+$BODY$;
+
+drop trigger if exists ledger_entries_balanced on ledger_entries;
+create constraint trigger ledger_entries_balanced
+    after insert or update on ledger_entries
+    deferrable initially deferred
+    for each row execute procedure ledger_entries_balanced_trigger();
+
+create or replace function ledger_balance(target_account_id integer)
+    returns bigint
+    language 'sql'
+as $BODY$
+    select coalesce(sum(amount), 0)::bigint from ledger_entries where account_id = target_account_id;
+$BODY$;
+
+-- The ledger transaction a deposit or withdrawal was posted as
+alter table token_transactions add column if not exists ledger_transaction_id integer;
+
+alter table token_transactions drop constraint if exists fk_ledger_transaction;
+alter table token_transactions
+    add constraint fk_ledger_transaction
+        foreign key (ledger_transaction_id)
+            references ledger_transactions(id)
+            on delete restrict;
+
+-- Post the existing deposits and withdrawals to the ledger
+do $BODY$
+declare
+    tt record;
+    tt_currency varchar(3);
+    channel_account integer;
+    platform_account integer;
+    ledger_transaction integer;
+begin
+    for tt in select t.*, e.currency from token_transactions t
+        left join token_economics e on e.id = t.economics_id
+        where t.ledger_transaction_id is null
+        order by t.id
+    loop
+        tt_currency := coalesce(tt.currency, 'gbp');
+
+        insert into ledger_accounts (account_type, user_id, currency)
+        values ('CHANNEL_EARNINGS', tt.channel_user_id, tt_currency)
+        on conflict do nothing;
+
+        select id into channel_account from ledger_accounts
+        where account_type = 'CHANNEL_EARNINGS' and user_id = tt.channel_user_id and currency = tt_currency;
+
+        insert into ledger_accounts (account_type, user_id, currency)
+        values (case when tt.transaction_type = 'DEPOSIT' then 'PLATFORM_REVENUE' else 'PAYOUTS_PAID' end, null, tt_currency)
+        on conflict do nothing;
+
+        select id into platform_account from ledger_accounts
+        where account_type = case when tt.transaction_type = 'DEPOSIT' then 'PLATFORM_REVENUE' else 'PAYOUTS_PAID' end
+            and user_id is null and currency = tt_currency;
+
+        insert into ledger_transactions (transaction_type, created)
+        values (case when tt.transaction_type = 'DEPOSIT' then 'TOKEN_CONVERSION' else 'WITHDRAWAL_PAID' end, tt.date)
+        returning id into ledger_transaction;
+
+        if tt.transaction_type = 'DEPOSIT' then
+            insert into ledger_entries (transaction_id, account_id, amount)
+            values (ledger_transaction, platform_account, -tt.amount),
+                   (ledger_transaction, channel_account, tt.amount);
+        else
+            insert into ledger_entries (transaction_id, account_id, amount)
+            values (ledger_transaction, channel_account, -tt.amount),
+                   (ledger_transaction, platform_account, tt.amount);
+        end if;
+
+        update token_transactions set ledger_transaction_id = ledger_transaction where id = tt.id;
+    end loop;
+end
+$BODY$;
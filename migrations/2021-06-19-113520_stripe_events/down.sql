@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+alter table token_transactions drop column if exists stripe_transfer_id;
+
+drop table if exists stripe_events;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Every Stripe webhook event we have received. Stripe can deliver an event more than once,
+-- the id is used to only handle it once.
+create table if not exists stripe_events
+(
+    id varchar(255) not null primary key ,
+    event_type varchar(128) not null,
+    payload text not null,
+    received timestamp default CURRENT_TIMESTAMP not null,
+    processed_at timestamp,
+    error text
+);
+
+-- The Stripe transfer a withdrawal was paid with, so reversals can be matched to it
+alter table token_transactions add column if not exists stripe_transfer_id varchar(255);
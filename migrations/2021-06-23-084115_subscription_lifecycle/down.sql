@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+drop index if exists users_stripe_customer_idx;
+
+alter table users
+    drop column if exists subscription_status,
+    drop column if exists stripe_subscription,
+    drop column if exists cancel_at_period_end,
+    drop column if exists current_period_end,
+    drop column if exists grace_period_ends;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+-- subscription_status is driven by Stripe billing events:
+--   NONE        not a subscriber, e.g. a channel
+--   INCOMPLETE  subscription created, first invoice not paid yet
+--   ACTIVE      paid up
+--   PAST_DUE    a payment failed, still subscribed until grace_period_ends
+--   SUSPENDED   the grace period ran out without a payment
+--   CANCELED    the subscription has ended
+-- users.subscribed is true while ACTIVE or PAST_DUE.
+alter table users
+    add column if not exists subscription_status varchar(16) not null default 'NONE',
+    add column if not exists stripe_subscription varchar(255),
+    add column if not exists cancel_at_period_end boolean not null default false,
+    add column if not exists current_period_end timestamp,
+    add column if not exists grace_period_ends timestamp;
+
+update users set subscription_status = 'ACTIVE', subscribed = true
+where user_type = 'SUBSCRIBER' and stripe_customer <> '';
+
+create index if not exists users_stripe_customer_idx on users (stripe_customer);
+
+-- Tokens are granted when an invoice is paid now, the monthly job is gone
+delete from scheduled_jobs where name = 'Assign tokens';
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+
+-- A channel withdrawing money. Status goes
+--   REQUESTED -> SUBMITTED -> PAID -> REVERSED
+-- and REQUESTED can go to FAILED when Stripe refuses the transfer. The money is held in the
+-- channel's payouts in flight ledger account from REQUESTED until the request is PAID or FAILED.
+create table if not exists withdrawal_requests
+(
+    id serial not null primary key ,
+    channel_user_id integer not null,
+    -- Sent by the client, the same key always gives back the same request
+    idempotency_key varchar(64) not null,
+    amount integer not null
+        constraint withdrawal_requests_amount_check
+            check (amount > 0),
+    currency varchar(3) not null,
+    status varchar(16) default 'REQUESTED' not null,
+    stripe_transfer_id varchar(255)
+        constraint withdrawal_requests_stripe_transfer_id_key
+            unique,
+    failure_reason text,
+    created timestamp default CURRENT_TIMESTAMP not null,
+    updated timestamp default CURRENT_TIMESTAMP not null,
+    constraint withdrawal_requests_channel_key
+        unique (channel_user_id, idempotency_key)
+);
+
+create index if not exists withdrawal_requests_status_idx on withdrawal_requests (status);
+
+alter table withdrawal_requests drop constraint if exists fk_user;
+alter table withdrawal_requests
+    add constraint fk_user
+        foreign key (channel_user_id)
+            references users(id)
+            on delete restrict;
@@ -0,0 +1,44 @@
+-- Your SQL goes here
+
+-- How a channel wants to be paid. Channels without a row withdraw by hand.
+--   MANUAL   only when the channel asks
+--   WEEKLY   once a week, as soon as the balance reaches minimum_amount
+--   MONTHLY  once a month, as soon as the balance reaches minimum_amount
+create table if not exists payout_preferences
+(
+    user_id integer not null primary key,
+    schedule varchar(16) default 'MANUAL' not null
+        constraint payout_preferences_schedule_check
+            check (schedule in ('MANUAL', 'WEEKLY', 'MONTHLY')),
+    -- In the smallest unit of the currency, like withdrawal amounts
+    minimum_amount integer default 1000 not null
+        constraint payout_preferences_minimum_amount_check
+            check (minimum_amount > 0),
+    updated timestamp default CURRENT_TIMESTAMP not null
+);
+
+alter table payout_preferences drop constraint if exists fk_user;
+alter table payout_preferences
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+create table if not exists notifications
+(
+    id serial not null primary key,
+    user_id integer not null,
+    notification_type varchar(32) not null,
+    -- JSON with whatever the notification type needs to be shown
+    data text not null,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+create index if not exists notifications_user_id_idx on notifications (user_id, created desc);
+
+alter table notifications drop constraint if exists fk_user;
+alter table notifications
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+drop function if exists refresh_analytics(date);
+drop table if exists channel_stats;
+drop table if exists video_stats;
+drop trigger if exists video_vote_events on video_upvotes;
+drop function if exists video_vote_events_trigger();
+drop table if exists video_vote_events;
+drop table if exists progress_events;
+drop index if exists comments_date_idx;
+alter table channels_tokens drop column if exists created;
@@ -0,0 +1,260 @@
+-- Your SQL goes here
+
+-- When a token was given to the channel. Older rows use the time the token was used.
+alter table channels_tokens
+    add column if not exists created timestamp default CURRENT_TIMESTAMP not null;
+
+update channels_tokens
+set created = tokens.date_used
+from tokens
+where tokens.id = channels_tokens.token_id
+  and tokens.date_used is not null;
+
+create index if not exists channels_tokens_created_idx on channels_tokens (created);
+create index if not exists comments_date_idx on comments (date);
+
+-- Every progress heartbeat which added watch time, from_position to to_position in seconds
+create table if not exists progress_events
+(
+    id bigserial not null primary key,
+    user_id integer not null,
+    video_id integer not null,
+    from_position integer not null,
+    to_position integer not null,
+    watched_seconds integer not null,
+    date timestamp default CURRENT_TIMESTAMP not null
+);
+
+create index if not exists progress_events_date_idx on progress_events (date);
+create index if not exists progress_events_video_id_idx on progress_events (video_id);
+
+alter table progress_events drop constraint if exists fk_user;
+alter table progress_events
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+alter table progress_events drop constraint if exists fk_video;
+alter table progress_events
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+-- Votes are toggled in place, so the changes are kept here to know when they happened
+create table if not exists video_vote_events
+(
+    id serial not null primary key,
+    video_id integer not null,
+    upvote_delta integer not null,
+    downvote_delta integer not null,
+    date timestamp default CURRENT_TIMESTAMP not null
+);
+
+create index if not exists video_vote_events_date_idx on video_vote_events (date);
+
+alter table video_vote_events drop constraint if exists fk_video;
+alter table video_vote_events
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+create or replace function video_vote_events_trigger()
+    returns trigger
+    language 'plpgsql'
+as $BODY$
+declare
+    v_id integer;
+    up_delta integer := 0;
+    down_delta integer := 0;
+begin
+    if tg_op in ('UPDATE', 'DELETE') and not old.inactive then
+        if old.upvote_type = 'UP' then
+            up_delta := up_delta - 1;
+        else
+            down_delta := down_delta - 1;
+        end if;
+    end if;
+
+    if tg_op in ('INSERT', 'UPDATE') and not new.inactive then
+        if new.upvote_type = 'UP' then
+            up_delta := up_delta + 1;
+        else
+            down_delta := down_delta + 1;
+        end if;
+    end if;
+
+    if tg_op = 'DELETE' then
+        v_id := old.video_id;
+    else
+        v_id := new.video_id;
+    end if;
+
+    if up_delta <> 0 or down_delta <> 0 then
+        insert into video_vote_events (video_id, upvote_delta, downvote_delta)
+        values (v_id, up_delta, down_delta);
+    end if;
+
+    return null;
+end
+$BODY$;
+
+drop trigger if exists video_vote_events on video_upvotes;
+create trigger video_vote_events
+    after insert or update or delete on video_upvotes
+    for each row execute procedure video_vote_events_trigger();
+
+-- The votes which are active now, as of when they were cast
+insert into video_vote_events (video_id, upvote_delta, downvote_delta, date)
+select video_id,
+       case when upvote_type = 'UP' then 1 else 0 end,
+       case when upvote_type = 'UP' then 0 else 1 end,
+       date
+from video_upvotes
+where not inactive;
+
+-- Rollups for the analytics endpoints, one row per video or channel and day or week (weeks
+-- start on Monday). Periods without any activity have no row. upvotes and downvotes are the
+-- net change in the period.
+create table if not exists video_stats
+(
+    video_id integer not null,
+    granularity varchar(4) not null,
+    period_start date not null,
+    plays integer default 0 not null,
+    unique_viewers integer default 0 not null,
+    watch_seconds integer default 0 not null,
+    upvotes integer default 0 not null,
+    downvotes integer default 0 not null,
+    comments integer default 0 not null,
+    primary key (video_id, granularity, period_start)
+);
+
+alter table video_stats drop constraint if exists fk_video;
+alter table video_stats
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+create table if not exists channel_stats
+(
+    channel_user_id integer not null,
+    granularity varchar(4) not null,
+    period_start date not null,
+    plays integer default 0 not null,
+    unique_viewers integer default 0 not null,
+    watch_seconds integer default 0 not null,
+    new_supporters integer default 0 not null,
+    upvotes integer default 0 not null,
+    downvotes integer default 0 not null,
+    comments integer default 0 not null,
+    -- Deposits from converted tokens, in the smallest unit of the currency
+    earnings integer default 0 not null,
+    primary key (channel_user_id, granularity, period_start)
+);
+
+alter table channel_stats drop constraint if exists fk_user;
+alter table channel_stats
+    add constraint fk_user
+        foreign key (channel_user_id)
+            references users(id)
+            on delete cascade;
+
+-- Rebuilds the rollups for every day and week from since onwards. Rows are replaced rather
+-- than updated so periods which lost all their activity (e.g. deleted comments) go as well.
+create or replace function refresh_analytics(since date)
+    returns void
+    language 'plpgsql'
+as $BODY$
+declare
+    g varchar;
+    from_date date;
+begin
+    foreach g in array array['DAY', 'WEEK'] loop
+        from_date := date_trunc(lower(g), since)::date;
+
+        delete from video_stats where granularity = g and period_start >= from_date;
+        delete from channel_stats where granularity = g and period_start >= from_date;
+
+        insert into video_stats (video_id, granularity, period_start, plays, unique_viewers, watch_seconds, upvotes, downvotes, comments)
+        select m.video_id, g, m.period_start,
+               sum(m.plays), sum(m.unique_viewers), sum(m.watch_seconds), sum(m.upvotes), sum(m.downvotes), sum(m.comments)
+        from (
+            select video_id, date_trunc(lower(g), date)::date as period_start,
+                   count(*) as plays, count(distinct user_id) as unique_viewers,
+                   0 as watch_seconds, 0 as upvotes, 0 as downvotes, 0 as comments
+            from video_plays
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, sum(watched_seconds), 0, 0, 0
+            from progress_events
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, 0, sum(upvote_delta), sum(downvote_delta), 0
+            from video_vote_events
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, 0, 0, 0, count(*)
+            from comments
+            where date >= from_date
+              and not inactive
+            group by 1, 2
+        ) m
+        group by m.video_id, m.period_start;
+
+        insert into channel_stats (channel_user_id, granularity, period_start, plays, unique_viewers, watch_seconds, new_supporters, upvotes, downvotes, comments, earnings)
+        select m.channel_user_id, g, m.period_start,
+               sum(m.plays), sum(m.unique_viewers), sum(m.watch_seconds), sum(m.new_supporters),
+               sum(m.upvotes), sum(m.downvotes), sum(m.comments), sum(m.earnings)
+        from (
+            select v.user_id as channel_user_id, s.period_start,
+                   s.plays, 0 as unique_viewers, s.watch_seconds, 0 as new_supporters,
+                   s.upvotes, s.downvotes, s.comments, 0 as earnings
+            from video_stats s
+                     inner join videos v on v.id = s.video_id
+            where s.granularity = g
+              and s.period_start >= from_date
+
+            union all
+
+            -- Viewers of several of the channel's videos are only counted once
+            select v.user_id, date_trunc(lower(g), p.date)::date, 0, count(distinct p.user_id), 0, 0, 0, 0, 0, 0
+            from video_plays p
+                     inner join videos v on v.id = p.video_id
+            where p.date >= from_date
+            group by 1, 2
+
+            union all
+
+            select ct.channel_user_id, date_trunc(lower(g), ct.created)::date, 0, 0, 0, count(distinct t.user_id), 0, 0, 0, 0
+            from channels_tokens ct
+                     inner join tokens t on t.id = ct.token_id
+            where ct.created >= from_date
+            group by 1, 2
+
+            union all
+
+            select channel_user_id, date_trunc(lower(g), date)::date, 0, 0, 0, 0, 0, 0, 0, sum(amount)
+            from token_transactions
+            where transaction_type = 'DEPOSIT'
+              and date >= from_date
+            group by 1, 2
+        ) m
+        group by m.channel_user_id, m.period_start;
+    end loop;
+end
+$BODY$;
+
+select refresh_analytics(date '1970-01-01');
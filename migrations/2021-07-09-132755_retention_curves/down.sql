@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+drop function if exists update_retention(integer);
+drop table if exists video_retention_totals;
+drop table if exists video_retention;
+drop table if exists video_viewer_buckets;
+drop table if exists video_retention_viewers;
+drop table if exists retention_cursor;
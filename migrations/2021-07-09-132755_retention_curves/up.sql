@@ -0,0 +1,152 @@
+-- Your SQL goes here
+
+-- Retention is built up from progress_events by update_retention, a batch at a time. Each video
+-- is split into 100 buckets of 1% of its duration, and a viewer counts towards every bucket
+-- they watched any part of, once however many times they watch it.
+
+-- The last progress event included
+create table if not exists retention_cursor
+(
+    id boolean default true not null primary key
+        constraint retention_cursor_single_row
+            check (id),
+    last_event_id bigint default 0 not null
+);
+
+insert into retention_cursor default values on conflict do nothing;
+
+create table if not exists video_retention_viewers
+(
+    video_id integer not null,
+    user_id integer not null,
+    primary key (video_id, user_id)
+);
+
+create table if not exists video_viewer_buckets
+(
+    video_id integer not null,
+    user_id integer not null,
+    bucket smallint not null,
+    primary key (video_id, user_id, bucket)
+);
+
+-- Viewers who watched each bucket
+create table if not exists video_retention
+(
+    video_id integer not null,
+    bucket smallint not null,
+    viewers integer default 0 not null,
+    primary key (video_id, bucket)
+);
+
+create table if not exists video_retention_totals
+(
+    video_id integer not null primary key,
+    viewers integer default 0 not null,
+    watch_seconds bigint default 0 not null
+);
+
+alter table video_retention_viewers drop constraint if exists fk_video;
+alter table video_retention_viewers
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+alter table video_viewer_buckets drop constraint if exists fk_video;
+alter table video_viewer_buckets
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+alter table video_retention drop constraint if exists fk_video;
+alter table video_retention
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+alter table video_retention_totals drop constraint if exists fk_video;
+alter table video_retention_totals
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+-- Adds the next batch_size progress events to the retention tables and returns how many there
+-- were. Events from the last minute are left for the next call, so one committed late with a
+-- lower ID than the cursor can't be skipped.
+create or replace function update_retention(batch_size integer)
+    returns integer
+    language 'plpgsql'
+as $BODY$
+declare
+    from_id bigint;
+    to_id bigint;
+    processed integer;
+begin
+    select last_event_id into from_id from retention_cursor for update;
+
+    select max(id), count(*) into to_id, processed
+    from (
+        select id
+        from progress_events
+        where id > from_id
+          and date < current_timestamp - interval '1 minute'
+        order by id
+        limit batch_size
+    ) batch;
+
+    if processed = 0 then
+        return 0;
+    end if;
+
+    with new_viewers as (
+        insert into video_retention_viewers (video_id, user_id)
+        select distinct video_id, user_id
+        from progress_events
+        where id > from_id and id <= to_id
+        on conflict do nothing
+        returning video_id
+    )
+    insert into video_retention_totals (video_id, viewers)
+    select video_id, count(*)
+    from new_viewers
+    group by video_id
+    on conflict (video_id) do update set viewers = video_retention_totals.viewers + excluded.viewers;
+
+    insert into video_retention_totals (video_id, watch_seconds)
+    select video_id, sum(watched_seconds)
+    from progress_events
+    where id > from_id and id <= to_id
+    group by video_id
+    on conflict (video_id) do update set watch_seconds = video_retention_totals.watch_seconds + excluded.watch_seconds;
+
+    -- Only forward watching counts towards buckets, seeking back and forth doesn't
+    with new_buckets as (
+        insert into video_viewer_buckets (video_id, user_id, bucket)
+        select distinct e.video_id, e.user_id, b.bucket
+        from progress_events e
+                 inner join videos v on v.id = e.video_id
+                 cross join lateral generate_series(
+                     least(floor(e.from_position * 100.0 / v.duration), 99)::integer,
+                     least(ceil(e.to_position * 100.0 / v.duration) - 1, 99)::integer
+                 ) as b(bucket)
+        where e.id > from_id and e.id <= to_id
+          and v.duration > 0
+          and e.to_position > e.from_position
+        on conflict do nothing
+        returning video_id, bucket
+    )
+    insert into video_retention (video_id, bucket, viewers)
+    select video_id, bucket, count(*)
+    from new_buckets
+    group by video_id, bucket
+    on conflict (video_id, bucket) do update set viewers = video_retention.viewers + excluded.viewers;
+
+    update retention_cursor set last_event_id = to_id;
+
+    return processed;
+end
+$BODY$;
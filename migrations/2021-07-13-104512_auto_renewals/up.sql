@@ -0,0 +1,32 @@
+-- Your SQL goes here
+
+-- Channels a subscriber supports again whenever their support runs out, lowest priority first
+create table if not exists auto_renewals
+(
+    id serial not null
+        constraint auto_renewals_pk
+            primary key,
+    user_id integer not null,
+    channel_user_id integer not null,
+    priority integer not null,
+    created timestamp default now() not null,
+    constraint auto_renewals_unique
+        unique (user_id, channel_user_id)
+);
+
+create index if not exists auto_renewals_user_id_priority_index
+    on auto_renewals (user_id, priority);
+
+alter table auto_renewals drop constraint if exists fk_user;
+alter table auto_renewals
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+alter table auto_renewals drop constraint if exists fk_channel_user;
+alter table auto_renewals
+    add constraint fk_channel_user
+        foreign key (channel_user_id)
+            references users(id)
+            on delete cascade;
@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+drop table if exists token_expirations;
+
+drop index if exists tokens_user_id_unused_index;
+
+alter table tokens
+    drop column if exists date_expired;
+
+alter table token_grants
+    drop column if exists period_end;
+
+alter table token_economics
+    drop column if exists token_rollover,
+    drop column if exists rollover_limit;
@@ -0,0 +1,66 @@
+-- Your SQL goes here
+
+-- What happens to a subscriber's unused tokens when a billing period ends
+--   EXPIRE    they all expire
+--   ROLLOVER  up to rollover_limit of them are kept, the oldest of the rest expire
+--   NEVER     they are all kept
+alter table token_economics
+    add column token_rollover varchar(16) not null default 'NEVER'
+        constraint token_economics_token_rollover_check
+            check (token_rollover in ('EXPIRE', 'ROLLOVER', 'NEVER')),
+    add column rollover_limit integer not null default 0
+        constraint token_economics_rollover_limit_check
+            check (rollover_limit >= 0);
+
+-- The end of the billing period the tokens were granted for. Grants made before this have
+-- none, so the policy is never applied for them.
+alter table token_grants
+    add column period_end timestamp;
+
+alter table tokens
+    add column date_expired timestamp;
+
+create index if not exists tokens_user_id_unused_index
+    on tokens (user_id)
+    where date_used is null and date_expired is null;
+
+-- One row per grant once the policy has been applied at the end of its period
+create table if not exists token_expirations
+(
+    id serial not null
+        constraint token_expirations_pk
+            primary key,
+    user_id integer not null,
+    grant_id integer not null
+        constraint token_expirations_grant_id_key
+            unique,
+    economics_id integer not null,
+    token_rollover varchar(16) not null,
+    expired_count integer not null,
+    kept_count integer not null,
+    created timestamp default now() not null
+);
+
+create index if not exists token_expirations_user_id_index
+    on token_expirations (user_id);
+
+alter table token_expirations drop constraint if exists fk_user;
+alter table token_expirations
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+alter table token_expirations drop constraint if exists fk_grant;
+alter table token_expirations
+    add constraint fk_grant
+        foreign key (grant_id)
+            references token_grants(id)
+            on delete cascade;
+
+alter table token_expirations drop constraint if exists fk_economics;
+alter table token_expirations
+    add constraint fk_economics
+        foreign key (economics_id)
+            references token_economics(id)
+            on delete restrict;
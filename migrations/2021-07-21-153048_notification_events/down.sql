@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+drop table if exists video_announcements;
+
+drop table if exists notification_preferences;
+
+drop index if exists notifications_unread_group_key;
+drop index if exists notifications_user_id_updated_idx;
+
+create index if not exists notifications_user_id_idx on notifications (user_id, created desc);
+
+alter table notifications
+    drop column if exists read_at,
+    drop column if exists group_key,
+    drop column if exists event_count,
+    drop column if exists updated;
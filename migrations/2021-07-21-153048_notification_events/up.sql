@@ -0,0 +1,57 @@
+-- Your SQL goes here
+
+-- Noisy events, e.g. upvotes on a video, are batched: while a notification with the same
+-- group_key is unread, another event updates it and increments event_count instead of adding
+-- a new one. updated is when the last event happened.
+alter table notifications
+    add column read_at timestamp,
+    add column group_key varchar(64),
+    add column event_count integer not null default 1,
+    add column updated timestamp not null default CURRENT_TIMESTAMP;
+
+update notifications set updated = created;
+
+drop index if exists notifications_user_id_idx;
+
+create index if not exists notifications_user_id_updated_idx on notifications (user_id, updated desc);
+
+create unique index if not exists notifications_unread_group_key
+    on notifications (user_id, group_key)
+    where read_at is null;
+
+-- Types a user has turned off. Every type is on unless there is a row saying otherwise.
+create table if not exists notification_preferences
+(
+    user_id integer not null,
+    notification_type varchar(32) not null,
+    enabled boolean not null,
+    primary key (user_id, notification_type)
+);
+
+alter table notification_preferences drop constraint if exists fk_user;
+alter table notification_preferences
+    add constraint fk_user
+        foreign key (user_id)
+            references users(id)
+            on delete cascade;
+
+-- Videos whose supporters have been told about them, see the "Announce uploads" job
+create table if not exists video_announcements
+(
+    video_id integer not null primary key,
+    supporter_count integer not null,
+    created timestamp default CURRENT_TIMESTAMP not null
+);
+
+alter table video_announcements drop constraint if exists fk_video;
+alter table video_announcements
+    add constraint fk_video
+        foreign key (video_id)
+            references videos(id)
+            on delete cascade;
+
+-- Videos which are already up don't get announced
+insert into video_announcements (video_id, supporter_count)
+select id, 0
+from videos
+on conflict do nothing;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table notifications
+    drop column if exists actor_ids;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- Who caused each of a batched notification's events. Another event from someone already in
+-- the batch, e.g. the same user toggling their upvote, doesn't change it, so event_count is the
+-- number of different people.
+alter table notifications
+    add column if not exists actor_ids integer[] not null default '{}';
+
+update notifications
+set actor_ids = array[(data::json ->> 'user_id')::integer]
+where group_key is not null
+  and data::json ->> 'user_id' is not null;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table channels_tokens
+    drop constraint if exists channels_tokens_token_id_key;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- A token can only be given to one channel. Two renewals racing for the same token would
+-- otherwise make two channels_tokens rows for it, and convert_tokens pays for each row.
+alter table channels_tokens drop constraint if exists channels_tokens_token_id_key;
+alter table channels_tokens
+    add constraint channels_tokens_token_id_key
+        unique (token_id);
@@ -0,0 +1,107 @@
+-- Your SQL goes here
+
+-- new_supporters counted everyone who gave the channel a token in the period, so supporters
+-- renewing every month were new every month. Only their first token counts now, and the
+-- rollups are rebuilt with it.
+
+-- Rebuilds the rollups for every day and week from since onwards. Rows are replaced rather
+-- than updated so periods which lost all their activity (e.g. deleted comments) go as well.
+create or replace function refresh_analytics(since date)
+    returns void
+    language 'plpgsql'
+as $BODY$
+declare
+    g varchar;
+    from_date date;
+begin
+    foreach g in array array['DAY', 'WEEK'] loop
+        from_date := date_trunc(lower(g), since)::date;
+
+        delete from video_stats where granularity = g and period_start >= from_date;
+        delete from channel_stats where granularity = g and period_start >= from_date;
+
+        insert into video_stats (video_id, granularity, period_start, plays, unique_viewers, watch_seconds, upvotes, downvotes, comments)
+        select m.video_id, g, m.period_start,
+               sum(m.plays), sum(m.unique_viewers), sum(m.watch_seconds), sum(m.upvotes), sum(m.downvotes), sum(m.comments)
+        from (
+            select video_id, date_trunc(lower(g), date)::date as period_start,
+                   count(*) as plays, count(distinct user_id) as unique_viewers,
+                   0 as watch_seconds, 0 as upvotes, 0 as downvotes, 0 as comments
+            from video_plays
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, sum(watched_seconds), 0, 0, 0
+            from progress_events
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, 0, sum(upvote_delta), sum(downvote_delta), 0
+            from video_vote_events
+            where date >= from_date
+            group by 1, 2
+
+            union all
+
+            select video_id, date_trunc(lower(g), date)::date, 0, 0, 0, 0, 0, count(*)
+            from comments
+            where date >= from_date
+              and not inactive
+            group by 1, 2
+        ) m
+        group by m.video_id, m.period_start;
+
+        insert into channel_stats (channel_user_id, granularity, period_start, plays, unique_viewers, watch_seconds, new_supporters, upvotes, downvotes, comments, earnings)
+        select m.channel_user_id, g, m.period_start,
+               sum(m.plays), sum(m.unique_viewers), sum(m.watch_seconds), sum(m.new_supporters),
+               sum(m.upvotes), sum(m.downvotes), sum(m.comments), sum(m.earnings)
+        from (
+            select v.user_id as channel_user_id, s.period_start,
+                   s.plays, 0 as unique_viewers, s.watch_seconds, 0 as new_supporters,
+                   s.upvotes, s.downvotes, s.comments, 0 as earnings
+            from video_stats s
+                     inner join videos v on v.id = s.video_id
+            where s.granularity = g
+              and s.period_start >= from_date
+
+            union all
+
+            -- Viewers of several of the channel's videos are only counted once
+            select v.user_id, date_trunc(lower(g), p.date)::date, 0, count(distinct p.user_id), 0, 0, 0, 0, 0, 0
+            from video_plays p
+                     inner join videos v on v.id = p.video_id
+            where p.date >= from_date
+            group by 1, 2
+
+            union all
+
+            -- Supporters are new in the period of the first token they gave the channel, so
+            -- monthly renewals don't count them again
+            select f.channel_user_id, date_trunc(lower(g), f.first_token)::date, 0, 0, 0, count(*), 0, 0, 0, 0
+            from (
+                select ct.channel_user_id, t.user_id, min(ct.created) as first_token
+                from channels_tokens ct
+                         inner join tokens t on t.id = ct.token_id
+                group by 1, 2
+            ) f
+            where f.first_token >= from_date
+            group by 1, 2
+
+            union all
+
+            select channel_user_id, date_trunc(lower(g), date)::date, 0, 0, 0, 0, 0, 0, 0, sum(amount)
+            from token_transactions
+            where transaction_type = 'DEPOSIT'
+              and date >= from_date
+            group by 1, 2
+        ) m
+        group by m.channel_user_id, m.period_start;
+    end loop;
+end
+$BODY$;
+
+select refresh_analytics(date '1970-01-01');
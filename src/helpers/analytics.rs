@@ -0,0 +1,284 @@
+use chrono::{Duration, NaiveDate, Utc};
+use diesel::{PgConnection, QueryResult};
+use diesel::sql_types::{BigInt, Double, Integer, VarChar};
+use serde::Serialize;
+
+use crate::diesel::RunQueryDsl;
+
+/*
+ * Channel analytics are read from the video_stats and channel_stats rollups, which the
+ * "Refresh analytics" job rebuilds every 15 minutes. See the refresh_analytics SQL function
+ * for how each number is counted.
+ */
+
+// Longest range a series can cover
+pub const MAX_RANGE_DAYS: i64 = 366;
+
+// Range used when none is given
+pub const DEFAULT_RANGE_DAYS: i64 = 30;
+
+pub struct AnalyticsRange {
+    pub from: String,
+    pub to: String,
+    // DAY or WEEK
+    pub granularity: String,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct ChannelStatsPeriod {
+    #[sql_type = "VarChar"]
+    pub period_start: String,
+    #[sql_type = "Integer"]
+    pub plays: i32,
+    #[sql_type = "Integer"]
+    pub unique_viewers: i32,
+    #[sql_type = "Integer"]
+    pub watch_seconds: i32,
+    #[sql_type = "Integer"]
+    pub new_supporters: i32,
+    #[sql_type = "Integer"]
+    pub upvotes: i32,
+    #[sql_type = "Integer"]
+    pub downvotes: i32,
+    #[sql_type = "Integer"]
+    pub comments: i32,
+    #[sql_type = "Integer"]
+    pub earnings: i32,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct VideoStatsPeriod {
+    #[sql_type = "VarChar"]
+    pub period_start: String,
+    #[sql_type = "Integer"]
+    pub plays: i32,
+    #[sql_type = "Integer"]
+    pub unique_viewers: i32,
+    #[sql_type = "Integer"]
+    pub watch_seconds: i32,
+    #[sql_type = "Integer"]
+    pub upvotes: i32,
+    #[sql_type = "Integer"]
+    pub downvotes: i32,
+    #[sql_type = "Integer"]
+    pub comments: i32,
+}
+
+// Unique viewers aren't included, they can't be added up across days
+#[derive(QueryableByName, Serialize)]
+pub struct VideoStatsTotal {
+    #[sql_type = "Integer"]
+    pub video_id: i32,
+    #[sql_type = "VarChar"]
+    pub title: String,
+    #[sql_type = "BigInt"]
+    pub plays: i64,
+    #[sql_type = "BigInt"]
+    pub watch_seconds: i64,
+    #[sql_type = "BigInt"]
+    pub upvotes: i64,
+    #[sql_type = "BigInt"]
+    pub downvotes: i64,
+    #[sql_type = "BigInt"]
+    pub comments: i64,
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date {}, dates look like 2021-07-05", date))
+}
+
+// Dates are inclusive and default to the last 30 days, by day
+pub fn parse_range(from: Option<&str>, to: Option<&str>, granularity: Option<&str>) -> Result<AnalyticsRange, String> {
+    let to = match to {
+        Some(v) => parse_date(v)?,
+        None => Utc::today().naive_utc()
+    };
+
+    let from = match from {
+        Some(v) => parse_date(v)?,
+        None => to - Duration::days(DEFAULT_RANGE_DAYS - 1)
+    };
+
+    if from > to {
+        return Err(String::from("from must be before to"));
+    }
+
+    if (to - from).num_days() >= MAX_RANGE_DAYS {
+        return Err(format!("The range can be at most {} days", MAX_RANGE_DAYS));
+    }
+
+    let granularity = match granularity.unwrap_or("day") {
+        "day" => "DAY",
+        "week" => "WEEK",
+        _ => { return Err(String::from("granularity must be day or week")); }
+    };
+
+    Ok(AnalyticsRange {
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+        granularity: granularity.to_string(),
+    })
+}
+
+// One entry for every day or week in the range, including the ones with nothing in them.
+// Weeks start on Monday, the first one is the week from is in.
+pub fn get_channel_series(db: &PgConnection, channel_id: i32, range: &AnalyticsRange) -> QueryResult<Vec<ChannelStatsPeriod>> {
+    diesel::sql_query("
+        select to_char(p.period_start, 'YYYY-MM-DD') as period_start,
+               coalesce(s.plays, 0) as plays,
+               coalesce(s.unique_viewers, 0) as unique_viewers,
+               coalesce(s.watch_seconds, 0) as watch_seconds,
+               coalesce(s.new_supporters, 0) as new_supporters,
+               coalesce(s.upvotes, 0) as upvotes,
+               coalesce(s.downvotes, 0) as downvotes,
+               coalesce(s.comments, 0) as comments,
+               coalesce(s.earnings, 0) as earnings
+        from generate_series(date_trunc(lower($2), $3::date), $4::date, ('1 ' || lower($2))::interval) as p(period_start)
+                 left join channel_stats s
+                           on s.channel_user_id = $1
+                               and s.granularity = $2
+                               and s.period_start = p.period_start::date
+        order by p.period_start
+    ")
+        .bind::<Integer, _>(channel_id)
+        .bind::<VarChar, _>(&range.granularity)
+        .bind::<VarChar, _>(&range.from)
+        .bind::<VarChar, _>(&range.to)
+        .load(db)
+}
+
+pub fn get_video_series(db: &PgConnection, target_video_id: i32, range: &AnalyticsRange) -> QueryResult<Vec<VideoStatsPeriod>> {
+    diesel::sql_query("
+        select to_char(p.period_start, 'YYYY-MM-DD') as period_start,
+               coalesce(s.plays, 0) as plays,
+               coalesce(s.unique_viewers, 0) as unique_viewers,
+               coalesce(s.watch_seconds, 0) as watch_seconds,
+               coalesce(s.upvotes, 0) as upvotes,
+               coalesce(s.downvotes, 0) as downvotes,
+               coalesce(s.comments, 0) as comments
+        from generate_series(date_trunc(lower($2), $3::date), $4::date, ('1 ' || lower($2))::interval) as p(period_start)
+                 left join video_stats s
+                           on s.video_id = $1
+                               and s.granularity = $2
+                               and s.period_start = p.period_start::date
+        order by p.period_start
+    ")
+        .bind::<Integer, _>(target_video_id)
+        .bind::<VarChar, _>(&range.granularity)
+        .bind::<VarChar, _>(&range.from)
+        .bind::<VarChar, _>(&range.to)
+        .load(db)
+}
+
+// Totals for each of the channel's videos over the range, most played first
+pub fn get_video_totals(db: &PgConnection, channel_id: i32, range: &AnalyticsRange) -> QueryResult<Vec<VideoStatsTotal>> {
+    diesel::sql_query("
+        select v.id as video_id,
+               v.title,
+               coalesce(sum(s.plays), 0)::bigint as plays,
+               coalesce(sum(s.watch_seconds), 0)::bigint as watch_seconds,
+               coalesce(sum(s.upvotes), 0)::bigint as upvotes,
+               coalesce(sum(s.downvotes), 0)::bigint as downvotes,
+               coalesce(sum(s.comments), 0)::bigint as comments
+        from videos v
+                 left join video_stats s
+                           on s.video_id = v.id
+                               and s.granularity = 'DAY'
+                               and s.period_start between $2::date and $3::date
+        where v.user_id = $1
+        group by v.id
+        order by plays desc, v.id
+    ")
+        .bind::<Integer, _>(channel_id)
+        .bind::<VarChar, _>(&range.from)
+        .bind::<VarChar, _>(&range.to)
+        .load(db)
+}
+
+#[derive(QueryableByName)]
+struct RetentionTotals {
+    #[sql_type = "Integer"]
+    viewers: i32,
+    #[sql_type = "Double"]
+    average_view_seconds: f64,
+}
+
+#[derive(QueryableByName)]
+struct RetentionPoint {
+    #[sql_type = "Double"]
+    percent: f64,
+}
+
+#[derive(Serialize)]
+pub struct RetentionCurve {
+    pub viewers: i32,
+    pub average_view_seconds: f64,
+    // Percentage of viewers who watched each 1% of the video, 100 points
+    pub curve: Vec<f64>,
+    // The median of the same numbers across the channel's watched videos. Empty if none of
+    // them have been watched.
+    pub channel_median_curve: Vec<f64>,
+    pub channel_median_average_view_seconds: f64,
+}
+
+// Read from the tables update_retention maintains, so it can be a few minutes behind
+pub fn get_retention_curve(db: &PgConnection, channel_id: i32, target_video_id: i32) -> QueryResult<RetentionCurve> {
+    let totals: Option<RetentionTotals> = diesel::sql_query("
+        select viewers, watch_seconds::float8 / viewers as average_view_seconds
+        from video_retention_totals
+        where video_id = $1
+          and viewers > 0
+    ")
+        .bind::<Integer, _>(target_video_id)
+        .load::<RetentionTotals>(db)?
+        .into_iter()
+        .next();
+
+    let totals = totals.unwrap_or(RetentionTotals {
+        viewers: 0,
+        average_view_seconds: 0.0,
+    });
+
+    let curve: Vec<RetentionPoint> = diesel::sql_query("
+        select coalesce(r.viewers * 100.0 / nullif(t.viewers, 0), 0)::float8 as percent
+        from generate_series(0, 99) as b(bucket)
+                 left join video_retention r on r.video_id = $1 and r.bucket = b.bucket
+                 left join video_retention_totals t on t.video_id = $1
+        order by b.bucket
+    ")
+        .bind::<Integer, _>(target_video_id)
+        .load(db)?;
+
+    let channel_median_curve: Vec<RetentionPoint> = diesel::sql_query("
+        select percentile_cont(0.5) within group (order by coalesce(r.viewers, 0) * 100.0 / t.viewers)::float8 as percent
+        from generate_series(0, 99) as b(bucket)
+                 cross join video_retention_totals t
+                 inner join videos v on v.id = t.video_id
+                 left join video_retention r on r.video_id = t.video_id and r.bucket = b.bucket
+        where v.user_id = $1
+          and t.viewers > 0
+        group by b.bucket
+        order by b.bucket
+    ")
+        .bind::<Integer, _>(channel_id)
+        .load(db)?;
+
+    let channel_median_average: Vec<RetentionPoint> = diesel::sql_query("
+        select coalesce(percentile_cont(0.5) within group (order by t.watch_seconds::float8 / t.viewers), 0)::float8 as percent
+        from video_retention_totals t
+                 inner join videos v on v.id = t.video_id
+        where v.user_id = $1
+          and t.viewers > 0
+    ")
+        .bind::<Integer, _>(channel_id)
+        .load(db)?;
+
+    Ok(RetentionCurve {
+        viewers: totals.viewers,
+        average_view_seconds: totals.average_view_seconds,
+        curve: curve.into_iter().map(|p| p.percent).collect(),
+        channel_median_curve: channel_median_curve.into_iter().map(|p| p.percent).collect(),
+        channel_median_average_view_seconds: channel_median_average.get(0).map(|p| p.percent).unwrap_or(0.0),
+    })
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{TokenEconomics, TokenPlan};
+use crate::schema::token_economics::dsl::{effective_from, token_economics};
+use crate::schema::token_plans::dsl::{economics_id, plan, stripe_price_id, token_plans};
+
+pub const DEFAULT_PLAN: &str = "STANDARD";
+
+// The version of the token economics in effect at the given time
+pub fn get_economics_at(db: &PgConnection, at: SystemTime) -> TokenEconomics {
+    token_economics
+        .filter(effective_from.le(at))
+        .order_by(effective_from.desc())
+        .first::<TokenEconomics>(db)
+        .expect("No token economics in effect")
+}
+
+pub fn get_current_economics(db: &PgConnection) -> TokenEconomics {
+    get_economics_at(db, SystemTime::now())
+}
+
+pub fn get_plans(db: &PgConnection, target_economics_id: i32) -> Vec<TokenPlan> {
+    token_plans
+        .filter(economics_id.eq(target_economics_id))
+        .order_by(plan.asc())
+        .load::<TokenPlan>(db)
+        .expect("Query failed")
+}
+
+pub fn get_plan(db: &PgConnection, target_economics_id: i32, target_plan: &str) -> Option<TokenPlan> {
+    token_plans
+        .filter(economics_id.eq(target_economics_id).and(plan.eq(target_plan)))
+        .first::<TokenPlan>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// The plan from the most recent version of the token economics in effect which has it. Existing
+// subscriptions stay on their plan when a new version drops it, so this is the plan they're on.
+pub fn get_latest_plan(db: &PgConnection, target_plan: &str) -> Option<TokenPlan> {
+    token_plans
+        .inner_join(token_economics)
+        .filter(plan.eq(target_plan).and(effective_from.le(SystemTime::now())))
+        .order_by(effective_from.desc())
+        .select(crate::schema::token_plans::all_columns)
+        .first::<TokenPlan>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// Like get_latest_plan, but by the Stripe price the subscription is billed at
+pub fn get_plan_by_price(db: &PgConnection, price_id: &str) -> Option<TokenPlan> {
+    token_plans
+        .inner_join(token_economics)
+        .filter(stripe_price_id.eq(price_id).and(effective_from.le(SystemTime::now())))
+        .order_by(effective_from.desc())
+        .select(crate::schema::token_plans::all_columns)
+        .first::<TokenPlan>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// When a token given to a channel now stops counting as support
+pub fn get_channel_token_expiry(economics: &TokenEconomics) -> SystemTime {
+    SystemTime::now() + Duration::from_secs(economics.channel_token_expiry_days as u64 * 24 * 60 * 60)
+}
@@ -0,0 +1,142 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::sql_types::{Integer, VarChar};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{Experiment, ExperimentAssignment, ExperimentVariant, ExperimentWithVariants};
+use crate::schema::experiment_variants::dsl::experiment_variants;
+use crate::schema::experiments::dsl::{active, experiments, name};
+
+// 64 bit FNV-1a. Used instead of the std hasher because its output has to stay the same
+// between releases, otherwise users would move between variants.
+fn fnv1a(input: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in input.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+// Picks a variant for the user. The same user always gets the same variant for an experiment,
+// and different experiments bucket users independently of each other.
+pub fn assign_variant<'a>(experiment: &Experiment, variants: &'a Vec<ExperimentVariant>, target_user_id: i32) -> Option<&'a ExperimentVariant> {
+    let total_weight: u64 = variants.iter().map(|v| v.weight.max(0) as u64).sum();
+
+    if total_weight == 0 {
+        return None;
+    }
+
+    let mut bucket = fnv1a(&format!("{}:{}", experiment.name, target_user_id)) % total_weight;
+
+    for variant in variants {
+        let weight = variant.weight.max(0) as u64;
+
+        if bucket < weight {
+            return Some(variant);
+        }
+
+        bucket -= weight;
+    }
+
+    None
+}
+
+pub fn get_experiments(db: &PgConnection, only_active: bool) -> Vec<ExperimentWithVariants> {
+    let mut query = experiments.into_boxed();
+
+    if only_active {
+        query = query.filter(active.eq(true));
+    }
+
+    let result: Vec<Experiment> = query
+        .order_by(crate::schema::experiments::id.asc())
+        .load::<Experiment>(db)
+        .expect("Query failed");
+
+    result.into_iter().map(|experiment| {
+        let variants = get_variants(db, experiment.id);
+
+        ExperimentWithVariants {
+            experiment,
+            variants,
+        }
+    }).collect()
+}
+
+// Variants are always walked in ID order so bucketing is stable
+fn get_variants(db: &PgConnection, target_experiment_id: i32) -> Vec<ExperimentVariant> {
+    experiment_variants
+        .filter(crate::schema::experiment_variants::experiment_id.eq(target_experiment_id))
+        .order_by(crate::schema::experiment_variants::id.asc())
+        .load::<ExperimentVariant>(db)
+        .expect("Query failed")
+}
+
+// Assigns the user to every active experiment and records their exposure
+pub fn get_assignments(db: &PgConnection, target_user_id: i32) -> Vec<ExperimentAssignment> {
+    let mut assignments: Vec<ExperimentAssignment> = vec![];
+
+    for experiment in get_experiments(db, true) {
+        if let Some(variant) = assign_variant(&experiment.experiment, &experiment.variants, target_user_id) {
+            record_exposure(db, target_user_id, experiment.experiment.id, variant.id)
+                .expect("Query failed");
+
+            assignments.push(ExperimentAssignment {
+                experiment: experiment.experiment.name.clone(),
+                variant: variant.name.clone(),
+            });
+        }
+    }
+
+    assignments
+}
+
+// The variant of a single experiment for the user, if the experiment is active. Only that
+// experiment's exposure is recorded.
+pub fn get_variant(db: &PgConnection, target_user_id: i32, experiment_name: &str) -> Option<String> {
+    let experiment: Experiment = experiments
+        .filter(name.eq(experiment_name).and(active.eq(true)))
+        .first::<Experiment>(db)
+        .optional()
+        .expect("Query failed")?;
+
+    let variants = get_variants(db, experiment.id);
+    let variant = assign_variant(&experiment, &variants, target_user_id)?;
+
+    record_exposure(db, target_user_id, experiment.id, variant.id)
+        .expect("Query failed");
+
+    Some(variant.name.clone())
+}
+
+fn record_exposure(db: &PgConnection, target_user_id: i32, target_experiment_id: i32, target_variant_id: i32) -> QueryResult<usize> {
+    // The partial unique index on exposures makes repeat exposures a no-op
+    diesel::sql_query("
+        insert into experiment_events (experiment_id, variant_id, user_id, event_type)
+        values ($1, $2, $3, 'EXPOSURE')
+        on conflict do nothing
+    ")
+        .bind::<Integer, _>(target_experiment_id)
+        .bind::<Integer, _>(target_variant_id)
+        .bind::<Integer, _>(target_user_id)
+        .execute(db)
+}
+
+// Records an outcome (PLAY, UPVOTE or TOKEN_TRANSFER) against every active experiment the user has
+// been exposed to, under the variant they were exposed to.
+pub fn record_outcome(db: &PgConnection, target_user_id: i32, event_type: &str) -> QueryResult<usize> {
+    diesel::sql_query("
+        insert into experiment_events (experiment_id, variant_id, user_id, event_type)
+        select exposure.experiment_id, exposure.variant_id, exposure.user_id, $2
+        from experiment_events exposure
+                 inner join experiments on experiments.id = exposure.experiment_id
+        where exposure.user_id = $1
+          and exposure.event_type = 'EXPOSURE'
+          and experiments.active
+    ")
+        .bind::<Integer, _>(target_user_id)
+        .bind::<VarChar, _>(event_type)
+        .execute(db)
+}
@@ -0,0 +1,155 @@
+use std::time::SystemTime;
+
+use diesel::{Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::dsl::not;
+use diesel::sql_types::{Integer, Timestamp};
+use serde::Serialize;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::economics::get_economics_at;
+use crate::models::{NewTokenExpiration, TokenEconomics, TokenExpiration, TokenGrant};
+use crate::schema::token_expirations::dsl::{created, token_expirations};
+use crate::schema::token_grants::dsl::{id, period_end, token_grants, user_id};
+use crate::schema::tokens::dsl::{date_expired, tokens};
+
+/*
+ * When a billing period ends, the rollover policy of the token economics in effect at that time
+ * is applied to the subscriber's unused tokens from that period and before. Tokens granted for
+ * a later period are never touched. Which tokens expire is decided by count_expiring, so the
+ * "Expire tokens" job and the expiring soon numbers shown to subscribers always agree.
+ */
+
+pub const ROLLOVER_POLICIES: [&str; 3] = ["EXPIRE", "ROLLOVER", "NEVER"];
+
+#[derive(QueryableByName)]
+struct UnusedToken {
+    #[sql_type = "Integer"]
+    id: i32,
+}
+
+#[derive(Serialize)]
+pub struct TokenExpiry {
+    pub token_rollover: String,
+    pub rollover_limit: i32,
+    // The end of the next period the policy will be applied at, None if there isn't one
+    pub expires_at: Option<SystemTime>,
+    // Unused tokens which will expire then unless they are given to channels first
+    pub expiring: i32,
+}
+
+// Unused tokens from periods ending at or before the given time, oldest first
+fn get_unused_token_ids(db: &PgConnection, subscriber: i32, end: SystemTime) -> QueryResult<Vec<i32>> {
+    let unused: Vec<UnusedToken> = diesel::sql_query("
+        select t.id
+        from tokens t
+                 left join token_grants g on g.id = t.grant_id
+        where t.user_id = $1
+          and t.date_used is null
+          and t.date_expired is null
+          and (g.period_end is null or g.period_end <= $2)
+        order by t.id
+    ")
+        .bind::<Integer, _>(subscriber)
+        .bind::<Timestamp, _>(end)
+        .load(db)?;
+
+    Ok(unused.into_iter().map(|t| t.id).collect())
+}
+
+// How many of the unused tokens expire under the policy. They are the oldest ones, so it is
+// always the start of the list.
+fn count_expiring(unused: &[i32], economics: &TokenEconomics) -> usize {
+    match economics.token_rollover.as_str() {
+        "EXPIRE" => unused.len(),
+        "ROLLOVER" => unused.len().saturating_sub(economics.rollover_limit.max(0) as usize),
+        _ => 0
+    }
+}
+
+// Grants whose period has ended and which the policy hasn't been applied for yet, oldest first
+pub fn get_due_grants(db: &PgConnection) -> QueryResult<Vec<TokenGrant>> {
+    token_grants
+        .filter(period_end.le(SystemTime::now()))
+        .filter(not(id.eq_any(token_expirations.select(crate::schema::token_expirations::grant_id))))
+        .order_by(period_end.asc())
+        .load::<TokenGrant>(db)
+}
+
+// Applies the rollover policy at the end of the grant's period and records what it did. Returns
+// false if it had already been applied for the grant.
+pub fn expire_grant(db: &PgConnection, grant: &TokenGrant) -> QueryResult<bool> {
+    let end = match grant.period_end {
+        Some(v) => v,
+        None => { return Ok(false); }
+    };
+
+    db.transaction(|| {
+        let economics = get_economics_at(db, end);
+
+        let unused = get_unused_token_ids(db, grant.user_id, end)?;
+        let (expiring, kept) = unused.split_at(count_expiring(&unused, &economics));
+
+        let inserted = diesel::insert_into(token_expirations)
+            .values(NewTokenExpiration {
+                user_id: grant.user_id,
+                grant_id: grant.id,
+                economics_id: economics.id,
+                token_rollover: &economics.token_rollover,
+                expired_count: expiring.len() as i32,
+                kept_count: kept.len() as i32,
+            })
+            .on_conflict_do_nothing()
+            .execute(db)?;
+
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        diesel::update(tokens.filter(crate::schema::tokens::id.eq_any(expiring)))
+            .set(date_expired.eq(SystemTime::now()))
+            .execute(db)?;
+
+        Ok(true)
+    })
+}
+
+// What happens to the subscriber's unused tokens at the end of their current period
+pub fn get_token_expiry(db: &PgConnection, subscriber: i32) -> TokenExpiry {
+    let next: Option<SystemTime> = token_grants
+        .filter(user_id.eq(subscriber))
+        .filter(period_end.gt(SystemTime::now()))
+        .filter(not(id.eq_any(token_expirations.select(crate::schema::token_expirations::grant_id))))
+        .order_by(period_end.asc())
+        .select(period_end)
+        .first::<Option<SystemTime>>(db)
+        .optional()
+        .expect("Query failed")
+        .flatten();
+
+    let economics = get_economics_at(db, next.unwrap_or(SystemTime::now()));
+
+    let expiring = match next {
+        Some(end) => {
+            let unused = get_unused_token_ids(db, subscriber, end).expect("Query failed");
+
+            count_expiring(&unused, &economics) as i32
+        }
+        None => 0
+    };
+
+    TokenExpiry {
+        token_rollover: economics.token_rollover,
+        rollover_limit: economics.rollover_limit,
+        expires_at: next,
+        expiring,
+    }
+}
+
+// Most recent first
+pub fn get_token_expirations(db: &PgConnection, subscriber: i32) -> Vec<TokenExpiration> {
+    token_expirations
+        .filter(crate::schema::token_expirations::user_id.eq(subscriber))
+        .order_by(created.desc())
+        .load::<TokenExpiration>(db)
+        .expect("Query failed")
+}
@@ -0,0 +1,280 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::helpers::payments::{Account, Customer, PaymentsError, PaymentsProvider, Subscription, Transfer};
+
+/*
+ * In-memory payments provider for running without Stripe. Instead of calling webhooks it
+ * queues the events Stripe would have sent, POST /webhooks/fake delivers them.
+ */
+
+#[derive(Default)]
+struct FakeState {
+    next_id: u64,
+    customers: Vec<String>,
+    subscriptions: Vec<(String, String, bool)>, // id, customer, cancel_at_period_end
+    accounts: Vec<String>,
+    transfers: Vec<(String, String, i64, String)>, // id, account, amount, idempotency key
+    events: Vec<String>,
+}
+
+pub struct FakeProvider {
+    state: Mutex<FakeState>,
+}
+
+const BILLING_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl FakeState {
+    fn id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+
+        format!("{}_fake_{}", prefix, self.next_id)
+    }
+
+    fn queue_event(&mut self, event_type: &str, object: serde_json::Value) {
+        let id = self.id("evt");
+
+        let event = json!({
+            "id": id,
+            "object": "event",
+            "type": event_type,
+            "created": now(),
+            "data": {
+                "object": object
+            }
+        });
+
+        self.events.push(event.to_string());
+    }
+}
+
+impl FakeProvider {
+    pub fn new() -> FakeProvider {
+        FakeProvider {
+            state: Mutex::new(FakeState::default()),
+        }
+    }
+
+    // Webhook payloads which haven't been delivered yet, oldest first
+    pub fn take_events(&self) -> Vec<String> {
+        self.state.lock().unwrap().events.drain(..).collect()
+    }
+}
+
+#[async_trait]
+impl PaymentsProvider for FakeProvider {
+    async fn create_customer(&self, _email: &str, _payment_method_id: &str) -> Result<Customer, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.id("cus");
+        state.customers.push(id.clone());
+
+        Ok(Customer { id })
+    }
+
+    // The first invoice is paid straight away
+    async fn create_subscription(&self, customer_id: &str, price_id: &str, _payment_method_id: Option<&str>) -> Result<Subscription, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.customers.iter().any(|c| c == customer_id) {
+            return Err(PaymentsError::Declined(format!("No such customer: {}", customer_id)));
+        }
+
+        let id = state.id("sub");
+        let invoice_id = state.id("in");
+        state.subscriptions.push((id.clone(), customer_id.to_string(), false));
+
+        let start = now();
+
+        state.queue_event("invoice.paid", json!({
+            "id": invoice_id,
+            "object": "invoice",
+            "customer": customer_id,
+            "subscription": id,
+            "lines": {
+                "object": "list",
+                "data": [{
+                    "type": "subscription",
+                    "period": { "start": start, "end": start + BILLING_PERIOD_SECONDS },
+                    "price": { "id": price_id }
+                }]
+            }
+        }));
+
+        Ok(Subscription {
+            id,
+            status: String::from("active"),
+            cancel_at_period_end: false,
+        })
+    }
+
+    async fn get_customer_subscription(&self, customer_id: &str) -> Result<Option<Subscription>, PaymentsError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.subscriptions.iter().find(|s| s.1 == customer_id).map(|subscription| Subscription {
+            id: subscription.0.clone(),
+            status: String::from("active"),
+            cancel_at_period_end: subscription.2,
+        }))
+    }
+
+    async fn set_subscription_cancel_at_period_end(&self, subscription_id: &str, cancel: bool) -> Result<Subscription, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+        let customer = match state.subscriptions.iter_mut().find(|s| s.0 == subscription_id) {
+            Some(subscription) => {
+                subscription.2 = cancel;
+                subscription.1.clone()
+            }
+            None => { return Err(PaymentsError::Declined(format!("No such subscription: {}", subscription_id))); }
+        };
+
+        state.queue_event("customer.subscription.updated", json!({
+            "id": subscription_id,
+            "object": "subscription",
+            "customer": customer,
+            "status": "active",
+            "cancel_at_period_end": cancel
+        }));
+
+        Ok(Subscription {
+            id: subscription_id.to_string(),
+            status: String::from("active"),
+            cancel_at_period_end: cancel,
+        })
+    }
+
+    async fn set_default_payment_method(&self, customer_id: &str, _subscription_id: Option<&str>, _payment_method_id: &str) -> Result<(), PaymentsError> {
+        let state = self.state.lock().unwrap();
+        if !state.customers.iter().any(|c| c == customer_id) {
+            return Err(PaymentsError::Declined(format!("No such customer: {}", customer_id)));
+        }
+
+        Ok(())
+    }
+
+    async fn create_account(&self, _email: &str, _country: &str) -> Result<Account, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.id("acct");
+        state.accounts.push(id.clone());
+
+        Ok(Account { id })
+    }
+
+    // Onboarding is treated as done as soon as the link is made
+    async fn create_account_link(&self, account_id: &str, _refresh_url: &str, return_url: &str) -> Result<String, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.accounts.iter().any(|a| a == account_id) {
+            return Err(PaymentsError::Declined(format!("No such account: {}", account_id)));
+        }
+
+        state.queue_event("account.updated", json!({
+            "id": account_id,
+            "object": "account",
+            "payouts_enabled": true
+        }));
+
+        Ok(return_url.to_string())
+    }
+
+    async fn create_transfer(&self, account_id: &str, amount: i64, currency: &str, transfer_group: &str, idempotency_key: &str) -> Result<Transfer, PaymentsError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(transfer) = state.transfers.iter().find(|t| t.3 == idempotency_key) {
+            return Ok(Transfer { id: transfer.0.clone() });
+        }
+
+        if !state.accounts.iter().any(|a| a == account_id) {
+            return Err(PaymentsError::Declined(format!("No such account: {}", account_id)));
+        }
+
+        let id = state.id("tr");
+        state.transfers.push((id.clone(), account_id.to_string(), amount, idempotency_key.to_string()));
+
+        state.queue_event("transfer.created", json!({
+            "id": id,
+            "object": "transfer",
+            "amount": amount,
+            "amount_reversed": 0,
+            "currency": currency,
+            "destination": account_id,
+            "transfer_group": transfer_group
+        }));
+
+        Ok(Transfer { id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{ExpressionMethods, QueryDsl};
+    use futures::executor::block_on;
+
+    use crate::diesel::RunQueryDsl;
+    use crate::helpers::economics::{DEFAULT_PLAN, get_current_economics, get_latest_plan};
+    use crate::helpers::ledger::get_channel_balance;
+    use crate::helpers::payments::{create_onboarding_link, use_fake_payments};
+    use crate::helpers::tokens::transfer_token;
+    use crate::helpers::users::{get_user_by_id, set_up_channel_account, set_up_subscriber_billing};
+    use crate::helpers::webhooks::process_fake_events;
+    use crate::helpers::withdrawals::{get_withdrawal_by_key, request_withdrawal, submit_withdrawal};
+    use crate::jobs::channel_payouts::convert_tokens;
+    use crate::models::JobRun;
+    use crate::schema::job_runs::dsl::job_runs;
+    use crate::schema::tokens::dsl::{tokens, user_id};
+    use crate::test_helpers::with_fixture;
+
+    // Registration, the first invoice, the channel's earnings and a withdrawal, with the webhook
+    // events the fake provider queues handled along the way as Stripe would send them
+    #[test]
+    fn subscription_to_payout() {
+        let fake = use_fake_payments();
+
+        with_fixture(include_str!("../../fixtures/payments/flow.sql"), |db| block_on(async {
+            let plan = get_latest_plan(db, DEFAULT_PLAN).unwrap();
+
+            set_up_subscriber_billing(db, 900001, "fixture_subscriber@example.com", "pm_fixture", &plan.stripe_price_id).await.unwrap();
+            let account_id = set_up_channel_account(db, 900002, "fixture_channel@example.com").await.unwrap();
+            create_onboarding_link(&account_id).await.unwrap();
+
+            assert_eq!(get_user_by_id(db, 900001).unwrap().subscription_status, "INCOMPLETE");
+
+            // invoice.paid and account.updated
+            assert_eq!(process_fake_events(db, fake), Vec::<String>::new());
+
+            let subscriber = get_user_by_id(db, 900001).unwrap();
+            assert_eq!(subscriber.subscription_status, "ACTIVE");
+            assert!(subscriber.subscribed);
+            assert!(get_user_by_id(db, 900002).unwrap().channel_onboarded);
+
+            let granted: i64 = tokens.filter(user_id.eq(900001)).count().get_result(db).unwrap();
+            assert_eq!(granted, plan.monthly_tokens as i64);
+
+            // The channel is paid for the token when tokens are converted
+            transfer_token(db, 900001, 900002).unwrap().unwrap();
+
+            let run: JobRun = job_runs.find(900001).first(db).unwrap();
+            convert_tokens(db, &run).unwrap();
+
+            let economics = get_current_economics(db);
+            assert_eq!(get_channel_balance(db, 900002, &economics.currency), economics.token_value as i64);
+
+            let request = match request_withdrawal(db, 900002, "fixture-withdrawal", economics.token_value, &economics.currency) {
+                Ok(v) => v,
+                Err(e) => panic!("Withdrawal wasn't requested: {}", e)
+            };
+            let request = submit_withdrawal(db, &request, &account_id).await.unwrap();
+            assert_eq!(request.status, "SUBMITTED");
+            assert_eq!(get_channel_balance(db, 900002, &economics.currency), 0);
+
+            // transfer.created
+            assert_eq!(process_fake_events(db, fake), Vec::<String>::new());
+
+            assert_eq!(get_withdrawal_by_key(db, 900002, "fixture-withdrawal").unwrap().status, "PAID");
+        }));
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::dsl::{IntervalDsl, now};
+use diesel::sql_types::{Bool, VarChar};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{JobRun, JobRunWithDuration, NewJobRun, NewScheduledJob, ScheduledJob};
+use crate::schema::job_runs::dsl::{attempts, error, finished_at, job_name, job_runs, period, processed, started_at, status};
+use crate::schema::scheduled_jobs::dsl::{name as scheduled_name, scheduled_jobs};
+
+#[derive(QueryableByName)]
+struct Period {
+    #[sql_type = "VarChar"]
+    period: String,
+}
+
+#[derive(QueryableByName)]
+struct Locked {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+// The period key for monthly jobs, e.g. '2021-06'. Uses the database clock so every
+// instance agrees on it.
+pub fn get_current_period(db: &PgConnection) -> String {
+    let result: Period = diesel::sql_query("select to_char(now(), 'YYYY-MM') as period")
+        .get_result(db)
+        .expect("Query failed");
+
+    result.period
+}
+
+// The ISO week, e.g. '2021-W26'
+pub fn get_current_week(db: &PgConnection) -> String {
+    let result: Period = diesel::sql_query("select to_char(now(), 'IYYY-\"W\"IW') as period")
+        .get_result(db)
+        .expect("Query failed");
+
+    result.period
+}
+
+// Records the start of a run of the job for the period. Returns None when the job has already
+// completed for the period, so there is nothing left to do. A failed or interrupted run is
+// picked up again, the work it finished is skipped by the job itself.
+pub fn start_job_run(db: &PgConnection, name: &str, period_key: &str) -> QueryResult<Option<JobRun>> {
+    let inserted: Option<JobRun> = diesel::insert_into(job_runs)
+        .values(NewJobRun {
+            job_name: name,
+            period: period_key,
+        })
+        .on_conflict((job_name, period))
+        .do_nothing()
+        .get_result(db)
+        .optional()?;
+
+    if let Some(run) = inserted {
+        return Ok(Some(run));
+    }
+
+    let run: JobRun = job_runs
+        .filter(job_name.eq(name).and(period.eq(period_key)))
+        .first(db)?;
+
+    if run.status == "COMPLETED" {
+        return Ok(None);
+    }
+
+    let run: JobRun = diesel::update(job_runs.find(run.id))
+        .set((
+            status.eq("RUNNING"),
+            attempts.eq(attempts + 1),
+            error.eq(None::<String>),
+            finished_at.eq(None::<SystemTime>),
+        ))
+        .get_result(db)?;
+
+    Ok(Some(run))
+}
+
+// Called in the same transaction as the work for an item, so the count matches what was done
+pub fn add_job_progress(db: &PgConnection, run_id: i32) -> QueryResult<()> {
+    diesel::update(job_runs.find(run_id))
+        .set(processed.eq(processed + 1))
+        .execute(db)?;
+
+    Ok(())
+}
+
+pub fn complete_job_run(db: &PgConnection, run_id: i32) {
+    diesel::update(job_runs.find(run_id))
+        .set((
+            status.eq("COMPLETED"),
+            finished_at.eq(SystemTime::now()),
+        ))
+        .execute(db)
+        .expect("Query failed");
+}
+
+pub fn fail_job_run(db: &PgConnection, run_id: i32, message: &str) {
+    diesel::update(job_runs.find(run_id))
+        .set((
+            status.eq("FAILED"),
+            error.eq(message),
+            finished_at.eq(SystemTime::now()),
+        ))
+        .execute(db)
+        .expect("Query failed");
+}
+
+// Deletes completed runs of the given jobs which finished more than `days` ago. Failed runs are
+// kept so they can still be looked into. Returns the number of runs deleted.
+pub fn delete_completed_job_runs(db: &PgConnection, names: &[&str], days: i32) -> QueryResult<usize> {
+    diesel::delete(job_runs
+        .filter(job_name.eq_any(names))
+        .filter(status.eq("COMPLETED"))
+        .filter(finished_at.lt((now - days.days()).nullable())))
+        .execute(db)
+}
+
+// Creates the row for a job the first time the scheduler sees it
+pub fn ensure_scheduled_job(db: &PgConnection, target_name: &str, default_schedule: &str) {
+    diesel::insert_into(scheduled_jobs)
+        .values(NewScheduledJob {
+            name: target_name,
+            schedule: default_schedule,
+        })
+        .on_conflict_do_nothing()
+        .execute(db)
+        .expect("Query failed");
+}
+
+pub fn get_scheduled_jobs(db: &PgConnection) -> Vec<ScheduledJob> {
+    scheduled_jobs
+        .order_by(scheduled_name.asc())
+        .load::<ScheduledJob>(db)
+        .expect("Query failed")
+}
+
+pub fn get_scheduled_job(db: &PgConnection, target_name: &str) -> Option<ScheduledJob> {
+    scheduled_jobs
+        .filter(scheduled_name.eq(target_name))
+        .first::<ScheduledJob>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+pub fn with_duration(run: JobRun) -> JobRunWithDuration {
+    let duration_seconds = run.finished_at
+        .and_then(|finished| finished.duration_since(run.started_at).ok())
+        .map(|duration| duration.as_secs());
+
+    JobRunWithDuration {
+        run,
+        duration_seconds,
+    }
+}
+
+// Most recent runs first
+pub fn get_job_runs(db: &PgConnection, target_name: &str, limit: i64) -> Vec<JobRunWithDuration> {
+    let result: Vec<JobRun> = job_runs
+        .filter(job_name.eq(target_name))
+        .order_by(started_at.desc())
+        .limit(limit)
+        .load::<JobRun>(db)
+        .expect("Query failed");
+
+    result.into_iter().map(with_duration).collect()
+}
+
+// Session level advisory lock, so only one instance runs a job at a time. It is released by
+// unlock_job, or by Postgres if the connection goes away.
+pub fn try_lock_job(db: &PgConnection, target_name: &str) -> bool {
+    let result: Locked = diesel::sql_query("select pg_try_advisory_lock(hashtext($1)) as locked")
+        .bind::<VarChar, _>(target_name)
+        .get_result(db)
+        .expect("Query failed");
+
+    result.locked
+}
+
+pub fn unlock_job(db: &PgConnection, target_name: &str) {
+    diesel::sql_query("select pg_advisory_unlock(hashtext($1)) as locked")
+        .bind::<VarChar, _>(target_name)
+        .execute(db)
+        .expect("Query failed");
+}
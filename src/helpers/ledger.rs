@@ -0,0 +1,186 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::sql_types::{BigInt, Integer, Nullable, VarChar};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{LedgerAccount, LedgerTransaction, NewLedgerAccount, NewLedgerEntry, NewLedgerTransaction};
+use crate::schema::ledger_accounts::dsl::{account_type, currency, ledger_accounts, user_id};
+use crate::schema::ledger_entries::dsl::ledger_entries;
+use crate::schema::ledger_transactions::dsl::ledger_transactions;
+
+/*
+ * Double-entry ledger for channel money, see the ledger migration for the account types.
+ * Entries are signed, positive amounts are credits, and the entries of a transaction always
+ * add up to zero. Balances are never stored, they are summed from the entries.
+ */
+
+pub const PLATFORM_REVENUE: &str = "PLATFORM_REVENUE";
+pub const CHANNEL_EARNINGS: &str = "CHANNEL_EARNINGS";
+pub const PAYOUTS_IN_FLIGHT: &str = "PAYOUTS_IN_FLIGHT";
+pub const PAYOUTS_PAID: &str = "PAYOUTS_PAID";
+
+#[derive(QueryableByName)]
+struct Balance {
+    #[sql_type = "BigInt"]
+    balance: i64,
+}
+
+// Accounts for the platform have no user
+pub fn get_or_create_account(db: &PgConnection, target_type: &str, target_user_id: Option<i32>, target_currency: &str) -> QueryResult<LedgerAccount> {
+    diesel::insert_into(ledger_accounts)
+        .values(NewLedgerAccount {
+            account_type: target_type,
+            user_id: target_user_id,
+            currency: target_currency,
+        })
+        .on_conflict_do_nothing()
+        .execute(db)?;
+
+    let query = ledger_accounts
+        .filter(account_type.eq(target_type).and(currency.eq(target_currency)))
+        .into_boxed();
+
+    let query = match target_user_id {
+        Some(v) => query.filter(user_id.eq(v)),
+        None => query.filter(user_id.is_null())
+    };
+
+    query.first::<LedgerAccount>(db)
+}
+
+pub fn find_account(db: &PgConnection, target_type: &str, target_user_id: i32, target_currency: &str) -> Option<LedgerAccount> {
+    ledger_accounts
+        .filter(account_type.eq(target_type)
+            .and(user_id.eq(target_user_id))
+            .and(currency.eq(target_currency)))
+        .first::<LedgerAccount>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// Locks the account until the end of the transaction, so balance checks and the entries which
+// depend on them can't interleave with another transaction's
+pub fn lock_account(db: &PgConnection, account_id: i32) -> QueryResult<()> {
+    ledger_accounts
+        .find(account_id)
+        .for_update()
+        .first::<LedgerAccount>(db)?;
+
+    Ok(())
+}
+
+pub fn get_account_balance(db: &PgConnection, account_id: i32) -> QueryResult<i64> {
+    let result: Balance = diesel::sql_query("select ledger_balance($1) as balance")
+        .bind::<Integer, _>(account_id)
+        .get_result(db)?;
+
+    Ok(result.balance)
+}
+
+// What the channel can withdraw in the currency
+pub fn get_channel_balance(db: &PgConnection, channel_id: i32, target_currency: &str) -> i64 {
+    match find_account(db, CHANNEL_EARNINGS, channel_id, target_currency) {
+        Some(account) => get_account_balance(db, account.id).expect("Query failed"),
+        None => 0
+    }
+}
+
+// Posts a balanced transaction. Panics if the entries don't add up to zero, that is always a bug.
+pub fn post_transaction(db: &PgConnection, transaction_type: &str, entries: &[(i32, i64)]) -> QueryResult<LedgerTransaction> {
+    let total: i64 = entries.iter().map(|(_, amount)| amount).sum();
+
+    assert_eq!(total, 0, "Ledger transaction {} does not balance", transaction_type);
+
+    let transaction: LedgerTransaction = diesel::insert_into(ledger_transactions)
+        .values(NewLedgerTransaction {
+            transaction_type,
+        })
+        .get_result(db)?;
+
+    let new_entries: Vec<NewLedgerEntry> = entries.iter()
+        .filter(|(_, amount)| *amount != 0)
+        .map(|(account_id, amount)| {
+            NewLedgerEntry {
+                transaction_id: transaction.id,
+                account_id: *account_id,
+                amount: *amount,
+            }
+        })
+        .collect();
+
+    diesel::insert_into(ledger_entries)
+        .values(&new_entries)
+        .execute(db)?;
+
+    Ok(transaction)
+}
+
+#[derive(QueryableByName)]
+struct LedgerProblem {
+    #[sql_type = "VarChar"]
+    problem: String,
+    #[sql_type = "Nullable<Integer>"]
+    id: Option<i32>,
+    #[sql_type = "Nullable<BigInt>"]
+    amount: Option<i64>,
+}
+
+// Checks the invariants of the ledger and returns a description of everything which is wrong.
+// An empty result means every account reconciles.
+pub fn check_ledger(db: &PgConnection) -> Vec<String> {
+    let result: Vec<LedgerProblem> = diesel::sql_query("
+        select 'Transaction does not balance' as problem, t.id, coalesce(sum(e.amount), 0)::bigint as amount
+        from ledger_transactions t
+        left join ledger_entries e on e.transaction_id = t.id
+        group by t.id
+        having coalesce(sum(e.amount), 0) <> 0 or count(e.id) = 0
+
+        union all
+
+        select 'Ledger does not add up to zero', null, sum(amount)::bigint
+        from ledger_entries
+        having sum(amount) <> 0
+
+        union all
+
+        select 'Channel account is overdrawn', a.id, sum(e.amount)::bigint
+        from ledger_accounts a
+        inner join ledger_entries e on e.account_id = a.id
+        where a.account_type in ('CHANNEL_EARNINGS', 'PAYOUTS_IN_FLIGHT')
+        group by a.id
+        having sum(e.amount) < 0
+
+        union all
+
+        select 'Token transaction is not in the ledger', t.id, t.amount::bigint
+        from token_transactions t
+        where t.ledger_transaction_id is null
+
+        union all
+
+        -- What a channel has, earned or on its way out, must match its deposits and reversals less its withdrawals
+        select 'Channel balance does not match its transactions', u.id, (coalesce(l.balance, 0) - coalesce(t.balance, 0))::bigint
+        from users u
+        left join (
+            select a.user_id, sum(e.amount) as balance
+            from ledger_accounts a
+            inner join ledger_entries e on e.account_id = a.id
+            where a.account_type in ('CHANNEL_EARNINGS', 'PAYOUTS_IN_FLIGHT')
+            group by a.user_id
+        ) l on l.user_id = u.id
+        left join (
+            select channel_user_id, sum(case when transaction_type = 'WITHDRAWAL' then -amount else amount end) as balance
+            from token_transactions
+            group by channel_user_id
+        ) t on t.channel_user_id = u.id
+        where coalesce(l.balance, 0) <> coalesce(t.balance, 0)
+    ")
+        .load(db)
+        .expect("Query failed");
+
+    result.into_iter().map(|p| {
+        format!("{}: id {}, amount {}",
+                p.problem,
+                p.id.map(|v| v.to_string()).unwrap_or(String::from("-")),
+                p.amount.map(|v| v.to_string()).unwrap_or(String::from("-")))
+    }).collect()
+}
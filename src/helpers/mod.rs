@@ -2,4 +2,27 @@ pub mod multipart_parsing;
 pub mod tokens;
 pub mod users;
 pub mod stripe;
-pub mod recommender;
\ No newline at end of file
+pub mod recommender;
+pub mod tags;
+
+pub mod videos;
+pub mod playlists;
+pub mod progress;
+pub mod plays;
+pub mod related;
+pub mod experiments;
+pub mod economics;
+pub mod jobs;
+pub mod ledger;
+pub mod webhooks;
+pub mod subscriptions;
+pub mod payments;
+pub mod fake_payments;
+pub mod withdrawals;
+pub mod notifications;
+pub mod payouts;
+pub mod pdf;
+pub mod statements;
+pub mod analytics;
+pub mod renewals;
+pub mod expiry;
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::sql_types::{Integer, Text, VarChar};
+use serde::Serialize;
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{NewNotification, NewNotificationPreference, Notification};
+use crate::schema::notification_preferences::dsl::{enabled, notification_preferences};
+use crate::schema::notifications::dsl::{id, notifications, read_at, updated, user_id};
+
+/*
+ * In-app notifications. Each one has a type and JSON data with whatever that type needs to be
+ * shown. Users can turn types off, in which case they aren't stored at all.
+ *
+ * Noisy events are batched with notify_batched: while there is an unread notification with the
+ * same group key, an event from someone new replaces its data and increments its event_count, so
+ * a burst of upvotes is one "12 people upvoted your video" instead of twelve notifications.
+ * Events from someone already in the batch are ignored, so toggling an upvote doesn't add up.
+ */
+
+pub const COMMENT_CREATED: &str = "COMMENT_CREATED";
+pub const VIDEO_UPVOTED: &str = "VIDEO_UPVOTED";
+pub const TOKEN_RECEIVED: &str = "TOKEN_RECEIVED";
+pub const VIDEO_UPLOADED: &str = "VIDEO_UPLOADED";
+pub const PAYOUT_PAID: &str = "PAYOUT_PAID";
+pub const PAYOUT_FAILED: &str = "PAYOUT_FAILED";
+pub const PAYOUT_REVERSED: &str = "PAYOUT_REVERSED";
+
+pub const NOTIFICATION_TYPES: [&str; 7] = [
+    COMMENT_CREATED,
+    VIDEO_UPVOTED,
+    TOKEN_RECEIVED,
+    VIDEO_UPLOADED,
+    PAYOUT_PAID,
+    PAYOUT_FAILED,
+    PAYOUT_REVERSED,
+];
+
+#[derive(Serialize)]
+pub struct NotificationWithData {
+    pub id: i32,
+    pub notification_type: String,
+    pub data: serde_json::Value,
+    // How many events were batched into it, 1 for types which aren't batched
+    pub event_count: i32,
+    pub read_at: Option<SystemTime>,
+    pub created: SystemTime,
+    pub updated: SystemTime,
+}
+
+#[derive(Serialize)]
+pub struct NotificationTypePreference {
+    pub notification_type: String,
+    pub enabled: bool,
+}
+
+pub fn is_notification_type(notification_type: &str) -> bool {
+    NOTIFICATION_TYPES.contains(&notification_type)
+}
+
+fn is_enabled(db: &PgConnection, target_user_id: i32, notification_type: &str) -> QueryResult<bool> {
+    let preference: Option<bool> = notification_preferences
+        .filter(crate::schema::notification_preferences::user_id.eq(target_user_id)
+            .and(crate::schema::notification_preferences::notification_type.eq(notification_type)))
+        .select(enabled)
+        .first(db)
+        .optional()?;
+
+    Ok(preference.unwrap_or(true))
+}
+
+// Called in the same transaction as whatever the notification is about, so it only exists if
+// that happened
+pub fn notify(db: &PgConnection, target_user_id: i32, notification_type: &str, data: serde_json::Value) -> QueryResult<()> {
+    if !is_enabled(db, target_user_id, notification_type)? {
+        return Ok(());
+    }
+
+    diesel::insert_into(notifications)
+        .values(NewNotification {
+            user_id: target_user_id,
+            notification_type,
+            data: &data.to_string(),
+        })
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Like notify, but adds to the user's unread notification with the same group key if there is
+// one and the actor, whoever caused the event, isn't in it yet. The key should include the type,
+// e.g. 'VIDEO_UPVOTED:12'.
+pub fn notify_batched(db: &PgConnection, target_user_id: i32, notification_type: &str, group_key: &str, actor_id: i32, data: serde_json::Value) -> QueryResult<()> {
+    if !is_enabled(db, target_user_id, notification_type)? {
+        return Ok(());
+    }
+
+    // Diesel can't give the index predicate the partial unique index needs
+    diesel::sql_query("
+        insert into notifications (user_id, notification_type, data, group_key, actor_ids)
+        values ($1, $2, $3, $4, array[$5])
+        on conflict (user_id, group_key) where read_at is null
+            do update set data = excluded.data,
+                          event_count = notifications.event_count + 1,
+                          actor_ids = notifications.actor_ids || excluded.actor_ids,
+                          updated = CURRENT_TIMESTAMP
+            where not notifications.actor_ids @> excluded.actor_ids
+    ")
+        .bind::<Integer, _>(target_user_id)
+        .bind::<VarChar, _>(notification_type)
+        .bind::<Text, _>(data.to_string())
+        .bind::<VarChar, _>(group_key)
+        .bind::<Integer, _>(actor_id)
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Most recently updated first
+pub fn get_notifications(db: &PgConnection, target_user_id: i32, unread_only: bool, limit: i64) -> Vec<NotificationWithData> {
+    let mut query = notifications
+        .filter(user_id.eq(target_user_id))
+        .into_boxed();
+
+    if unread_only {
+        query = query.filter(read_at.is_null());
+    }
+
+    let result: Vec<Notification> = query
+        .order_by(updated.desc())
+        .limit(limit)
+        .load::<Notification>(db)
+        .expect("Query failed");
+
+    result.into_iter().map(|notification| {
+        NotificationWithData {
+            id: notification.id,
+            notification_type: notification.notification_type,
+            data: serde_json::from_str(&notification.data).unwrap_or(serde_json::Value::Null),
+            event_count: notification.event_count,
+            read_at: notification.read_at,
+            created: notification.created,
+            updated: notification.updated,
+        }
+    }).collect()
+}
+
+pub fn get_unread_count(db: &PgConnection, target_user_id: i32) -> i64 {
+    notifications
+        .filter(user_id.eq(target_user_id).and(read_at.is_null()))
+        .count()
+        .get_result(db)
+        .expect("Query failed")
+}
+
+// Returns false if the user has no such notification. Marking one which is already read
+// does nothing.
+pub fn mark_read(db: &PgConnection, target_user_id: i32, notification_id: i32) -> QueryResult<bool> {
+    let exists: Option<i32> = notifications
+        .filter(id.eq(notification_id).and(user_id.eq(target_user_id)))
+        .select(id)
+        .first(db)
+        .optional()?;
+
+    if exists.is_none() {
+        return Ok(false);
+    }
+
+    diesel::update(notifications.filter(id.eq(notification_id).and(read_at.is_null())))
+        .set(read_at.eq(SystemTime::now()))
+        .execute(db)?;
+
+    Ok(true)
+}
+
+// Returns how many were marked
+pub fn mark_all_read(db: &PgConnection, target_user_id: i32) -> QueryResult<usize> {
+    diesel::update(notifications.filter(user_id.eq(target_user_id).and(read_at.is_null())))
+        .set(read_at.eq(SystemTime::now()))
+        .execute(db)
+}
+
+// Every type, with the user's choice or the default
+pub fn get_notification_preferences(db: &PgConnection, target_user_id: i32) -> Vec<NotificationTypePreference> {
+    let saved: Vec<(String, bool)> = notification_preferences
+        .filter(crate::schema::notification_preferences::user_id.eq(target_user_id))
+        .select((crate::schema::notification_preferences::notification_type, enabled))
+        .load(db)
+        .expect("Query failed");
+
+    NOTIFICATION_TYPES.iter().map(|notification_type| {
+        NotificationTypePreference {
+            notification_type: notification_type.to_string(),
+            enabled: saved.iter()
+                .find(|(saved_type, _)| saved_type == notification_type)
+                .map(|(_, saved_enabled)| *saved_enabled)
+                .unwrap_or(true),
+        }
+    }).collect()
+}
+
+pub fn set_notification_preference(db: &PgConnection, target_user_id: i32, notification_type: &str, new_enabled: bool) -> QueryResult<()> {
+    diesel::insert_into(notification_preferences)
+        .values(NewNotificationPreference {
+            user_id: target_user_id,
+            notification_type,
+            enabled: new_enabled,
+        })
+        .on_conflict((crate::schema::notification_preferences::user_id, crate::schema::notification_preferences::notification_type))
+        .do_update()
+        .set(enabled.eq(new_enabled))
+        .execute(db)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{ExpressionMethods, QueryDsl};
+    use serde_json::json;
+
+    use crate::diesel::RunQueryDsl;
+    use crate::models::Notification;
+    use crate::schema::notifications::dsl::{notifications, user_id};
+    use crate::test_helpers::with_fixture;
+
+    use super::{notify_batched, VIDEO_UPVOTED};
+
+    #[test]
+    fn counts_each_actor_once() {
+        with_fixture(include_str!("../../fixtures/notifications/batched.sql"), |db| {
+            let key = format!("{}:{}", VIDEO_UPVOTED, 900001);
+
+            // While the notification is unread, another event from the same user, e.g. toggling an
+            // upvote off and on again, is ignored
+            notify_batched(db, 900001, VIDEO_UPVOTED, &key, 900002, json!({ "user_id": 900002 })).unwrap();
+            notify_batched(db, 900001, VIDEO_UPVOTED, &key, 900002, json!({ "user_id": 900002 })).unwrap();
+
+            let notification: Notification = notifications.filter(user_id.eq(900001)).first(db).unwrap();
+            assert_eq!(notification.event_count, 1);
+            assert_eq!(notification.actor_ids, vec![900002]);
+
+            notify_batched(db, 900001, VIDEO_UPVOTED, &key, 900003, json!({ "user_id": 900003 })).unwrap();
+
+            let notification: Notification = notifications.filter(user_id.eq(900001)).first(db).unwrap();
+            assert_eq!(notification.event_count, 2);
+            assert_eq!(notification.actor_ids, vec![900002, 900003]);
+            assert_eq!(notification.data, json!({ "user_id": 900003 }).to_string());
+        });
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+
+use crate::helpers::fake_payments::FakeProvider;
+use crate::helpers::stripe::StripeProvider;
+
+/*
+ * Everything the API needs from the payments provider. StripeProvider is used in production,
+ * FakeProvider keeps everything in memory so registration, payouts and webhooks can be run
+ * without network access. Pick one with PAYMENTS_PROVIDER=stripe|fake, stripe is the default.
+ */
+
+#[derive(Debug)]
+pub enum PaymentsError {
+    // The provider refused, trying again won't help
+    Declined(String),
+    // The provider couldn't be reached or had a problem of its own. Safe to retry with the
+    // same idempotency key.
+    Unavailable(String),
+}
+
+impl fmt::Display for PaymentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentsError::Declined(e) => write!(f, "{}", e),
+            PaymentsError::Unavailable(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub struct Customer {
+    pub id: String,
+}
+
+pub struct Subscription {
+    pub id: String,
+    pub status: String,
+    pub cancel_at_period_end: bool,
+}
+
+pub struct Account {
+    pub id: String,
+}
+
+pub struct Transfer {
+    pub id: String,
+}
+
+#[async_trait]
+pub trait PaymentsProvider: Send + Sync {
+    async fn create_customer(&self, email: &str, payment_method_id: &str) -> Result<Customer, PaymentsError>;
+
+    // Uses the customer's default payment method when payment_method_id is None
+    async fn create_subscription(&self, customer_id: &str, price_id: &str, payment_method_id: Option<&str>) -> Result<Subscription, PaymentsError>;
+
+    // The customer's subscription which hasn't ended yet, if they have one
+    async fn get_customer_subscription(&self, customer_id: &str) -> Result<Option<Subscription>, PaymentsError>;
+
+    // Cancelling at the end of the period keeps the subscription until what was paid for runs out
+    async fn set_subscription_cancel_at_period_end(&self, subscription_id: &str, cancel: bool) -> Result<Subscription, PaymentsError>;
+
+    // Attaches the payment method and makes it the one used for the customer's invoices, and
+    // for the subscription if there is one
+    async fn set_default_payment_method(&self, customer_id: &str, subscription_id: Option<&str>, payment_method_id: &str) -> Result<(), PaymentsError>;
+
+    // A connected account for a channel to be paid into
+    async fn create_account(&self, email: &str, country: &str) -> Result<Account, PaymentsError>;
+
+    // Onboarding link for a connected account
+    async fn create_account_link(&self, account_id: &str, refresh_url: &str, return_url: &str) -> Result<String, PaymentsError>;
+
+    // Sending the same idempotency key again returns the transfer made the first time instead
+    // of making another. The transfer group shows up on the transfer's webhook events.
+    async fn create_transfer(&self, account_id: &str, amount: i64, currency: &str, transfer_group: &str, idempotency_key: &str) -> Result<Transfer, PaymentsError>;
+}
+
+static STRIPE: OnceCell<StripeProvider> = OnceCell::new();
+static FAKE: OnceCell<FakeProvider> = OnceCell::new();
+
+// Called once from main, so a missing secret stops the server starting instead of failing
+// the first request which needs it
+pub fn init_payments_provider() -> Result<(), String> {
+    match std::env::var("PAYMENTS_PROVIDER").as_deref() {
+        Ok("fake") => {
+            FAKE.get_or_init(FakeProvider::new);
+        }
+        Ok("stripe") | Err(_) => {
+            let provider = StripeProvider::from_env()?;
+            STRIPE.get_or_init(|| provider);
+        }
+        Ok(other) => { return Err(format!("Unknown PAYMENTS_PROVIDER {}", other)); }
+    }
+
+    Ok(())
+}
+
+pub fn payments() -> &'static dyn PaymentsProvider {
+    if let Some(fake) = FAKE.get() {
+        return fake;
+    }
+
+    STRIPE.get().expect("Payments provider hasn't been set up")
+}
+
+// Tests always use the fake provider
+#[cfg(test)]
+pub fn use_fake_payments() -> &'static FakeProvider {
+    FAKE.get_or_init(FakeProvider::new)
+}
+
+// Only set when running with PAYMENTS_PROVIDER=fake
+pub fn fake_payments() -> Option<&'static FakeProvider> {
+    FAKE.get()
+}
+
+// Onboarding link which sends the channel back to the frontend, FRONTEND_URL, when they are done
+pub async fn create_onboarding_link(account_id: &str) -> Result<String, PaymentsError> {
+    let frontend_url = std::env::var("FRONTEND_URL").unwrap_or(String::from("http://localhost:3000"));
+
+    payments()
+        .create_account_link(account_id, &format!("{}/login", frontend_url), &frontend_url)
+        .await
+}
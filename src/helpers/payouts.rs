@@ -0,0 +1,46 @@
+use std::time::SystemTime;
+
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::pg::upsert::excluded;
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{NewPayoutPreference, PayoutPreference};
+use crate::schema::payout_preferences::dsl::{minimum_amount, payout_preferences, schedule, updated};
+
+pub const PAYOUT_SCHEDULES: [&str; 3] = ["MANUAL", "WEEKLY", "MONTHLY"];
+
+// Used until the channel picks their own, in the smallest unit of the currency
+pub const DEFAULT_MINIMUM_AMOUNT: i32 = 1000;
+
+// Channels which never set a preference withdraw by hand
+pub fn get_payout_preference(db: &PgConnection, target_user_id: i32) -> PayoutPreference {
+    let preference: Option<PayoutPreference> = payout_preferences
+        .find(target_user_id)
+        .first(db)
+        .optional()
+        .expect("Query failed");
+
+    preference.unwrap_or(PayoutPreference {
+        user_id: target_user_id,
+        schedule: String::from("MANUAL"),
+        minimum_amount: DEFAULT_MINIMUM_AMOUNT,
+        updated: SystemTime::now(),
+    })
+}
+
+pub fn set_payout_preference(db: &PgConnection, target_user_id: i32, new_schedule: &str, new_minimum_amount: i32) -> QueryResult<PayoutPreference> {
+    diesel::insert_into(payout_preferences)
+        .values(NewPayoutPreference {
+            user_id: target_user_id,
+            schedule: new_schedule,
+            minimum_amount: new_minimum_amount,
+        })
+        .on_conflict(crate::schema::payout_preferences::user_id)
+        .do_update()
+        .set((
+            schedule.eq(excluded(schedule)),
+            minimum_amount.eq(excluded(minimum_amount)),
+            updated.eq(SystemTime::now()),
+        ))
+        .get_result(db)
+}
@@ -0,0 +1,99 @@
+/*
+ * Just enough PDF to print lines of text: A4 pages, Courier so columns can be lined up with
+ * spaces, and nothing which changes between renders (no creation date or document ID), so the
+ * same lines always give the same file.
+ * See the PDF 1.4 reference, chapter 3 for the file structure and chapter 5 for text.
+ */
+
+const PAGE_WIDTH: usize = 595;
+const PAGE_HEIGHT: usize = 842;
+const MARGIN: usize = 50;
+const FONT_SIZE: usize = 9;
+const LEADING: usize = 12;
+
+// Courier is one of the standard fonts every reader has, so it isn't embedded. It only covers
+// ASCII here, anything else is shown as '?'.
+fn escape(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+
+    for c in line.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+
+    escaped
+}
+
+fn page_content(lines: &[String]) -> String {
+    let mut content = format!("BT\n/F1 {} Tf\n{} TL\n{} {} Td\n", FONT_SIZE, LEADING, MARGIN, PAGE_HEIGHT - MARGIN);
+
+    for line in lines {
+        // ' moves to the next line and shows the text
+        content.push_str(&format!("({}) '\n", escape(line)));
+    }
+
+    content.push_str("ET");
+
+    content
+}
+
+// Renders the lines top to bottom, starting a new page when one fills up
+pub fn render_lines(lines: &[String]) -> Vec<u8> {
+    let lines_per_page = (PAGE_HEIGHT - 2 * MARGIN) / LEADING;
+
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![&[]]
+    } else {
+        lines.chunks(lines_per_page).collect()
+    };
+
+    // Object 1 is the catalog, 2 the page tree and 3 the font. Each page is followed by its
+    // content stream.
+    let mut objects: Vec<String> = vec![
+        String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len()).map(|i| format!("{} 0 R", 4 + i * 2)).collect::<Vec<String>>().join(" "),
+            pages.len()
+        ),
+        String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>"),
+    ];
+
+    for (i, page) in pages.iter().enumerate() {
+        let content = page_content(page);
+
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT, 5 + i * 2
+        ));
+        // Everything is ASCII, so the length in characters is the length in bytes
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content));
+    }
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets: Vec<usize> = vec![];
+
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+
+    let xref_offset = pdf.len();
+
+    // Each cross-reference entry has to be exactly 20 bytes, including the end of line
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+
+    pdf.push_str(&format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref_offset));
+
+    pdf.into_bytes()
+}
@@ -0,0 +1,98 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::videos::get_videos_by_ids;
+use crate::models::{NewPlaylist, Playlist, PlaylistWithVideos};
+use crate::schema::playlists::dsl::{playlists, user_id, watch_later};
+use crate::schema::playlists_videos::dsl::{playlists_videos, position};
+
+pub const PLAYLIST_VISIBILITIES: [&str; 3] = ["PUBLIC", "UNLISTED", "PRIVATE"];
+pub const WATCH_LATER_NAME: &str = "Watch later";
+
+pub fn is_valid_visibility(visibility: &str) -> bool {
+    PLAYLIST_VISIBILITIES.contains(&visibility)
+}
+
+// Every user has a private "Watch later" playlist. It is created the first time it is needed.
+pub fn get_or_create_watch_later(db: &PgConnection, owner: i32) -> Playlist {
+    let new_playlist = NewPlaylist {
+        user_id: owner,
+        name: WATCH_LATER_NAME,
+        visibility: "PRIVATE",
+        watch_later: true,
+    };
+
+    // Does nothing if it already exists, including when another request created it at the same
+    // time, the unique index allows one per user
+    diesel::insert_into(playlists)
+        .values(&new_playlist)
+        .on_conflict_do_nothing()
+        .execute(db)
+        .expect("Couldn't create watch later playlist");
+
+    playlists
+        .filter(user_id.eq(owner).and(watch_later.eq(true)))
+        .first::<Playlist>(db)
+        .expect("Query failed")
+}
+
+pub fn get_playlist(db: &PgConnection, target_playlist_id: i32) -> Option<Playlist> {
+    playlists
+        .find(target_playlist_id)
+        .first::<Playlist>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// Returns the playlist only if it belongs to the given user
+pub fn get_owned_playlist(db: &PgConnection, target_playlist_id: i32, owner: i32) -> Option<Playlist> {
+    match get_playlist(db, target_playlist_id) {
+        Some(playlist) if playlist.user_id == owner => Some(playlist),
+        _ => None
+    }
+}
+
+// Private playlists are only visible to their owner. Unlisted playlists can be viewed by anyone
+// who has the ID, but aren't listed on the owner's profile.
+pub fn can_view_playlist(playlist: &Playlist, viewer: i32) -> bool {
+    playlist.user_id == viewer || playlist.visibility != "PRIVATE"
+}
+
+pub fn get_playlist_video_ids(db: &PgConnection, target_playlist_id: i32) -> Vec<i32> {
+    playlists_videos
+        .filter(crate::schema::playlists_videos::playlist_id.eq(target_playlist_id))
+        .order_by(position.asc())
+        .select(crate::schema::playlists_videos::video_id)
+        .load::<i32>(db)
+        .expect("Query failed")
+}
+
+pub fn load_playlist_with_videos(db: &PgConnection, playlist: Playlist, viewer: i32) -> PlaylistWithVideos {
+    let video_ids = get_playlist_video_ids(db, playlist.id);
+
+    PlaylistWithVideos {
+        id: playlist.id,
+        user_id: playlist.user_id,
+        name: playlist.name,
+        visibility: playlist.visibility,
+        watch_later: playlist.watch_later,
+        created: playlist.created,
+        videos: get_videos_by_ids(db, viewer, &video_ids),
+    }
+}
+
+// Rewrites the positions of a playlist so they match the order of video_ids
+pub fn set_playlist_order(db: &PgConnection, target_playlist_id: i32, video_ids: &Vec<i32>) -> QueryResult<()> {
+    db.transaction(|| {
+        for (index, target_video_id) in video_ids.iter().enumerate() {
+            diesel::update(playlists_videos.filter(
+                crate::schema::playlists_videos::playlist_id.eq(target_playlist_id)
+                    .and(crate::schema::playlists_videos::video_id.eq(target_video_id))
+            ))
+                .set(position.eq(index as i32))
+                .execute(db)?;
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,169 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::dsl::{exists, IntervalDsl, now};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::progress::get_progress;
+use crate::models::{NewPlayEvent, NewPlayFlag, NewVideoPlay, PlayEvent};
+use crate::schema::play_events::dsl::{counted, play_events};
+use crate::schema::play_flags::dsl::play_flags;
+use crate::schema::video_plays::dsl::video_plays;
+use crate::schema::videos::dsl::videos;
+
+/*
+ * Play counting rules
+ *
+ * Every call to /video/increment-play is stored as a raw play event. A play only counts
+ * (and ends up in video_plays) once the viewer has watched enough of it, at most once per
+ * user per video within DEDUPE_WINDOW_HOURS. Users or IPs sending too many play events are
+ * flagged and their events are never counted.
+ */
+
+pub const MIN_WATCH_SECONDS: i32 = 30;
+pub const DEDUPE_WINDOW_HOURS: i32 = 6;
+pub const MAX_EVENTS_PER_USER_PER_HOUR: i64 = 60;
+pub const MAX_EVENTS_PER_IP_PER_HOUR: i64 = 300;
+
+// Short videos count after half of the video has been watched
+pub fn required_watch_seconds(duration: Option<i32>) -> i32 {
+    match duration {
+        Some(d) if d > 0 => MIN_WATCH_SECONDS.min((d / 2).max(1)),
+        _ => MIN_WATCH_SECONDS
+    }
+}
+
+fn flag(db: &PgConnection, target_user_id: Option<i32>, ip: Option<&str>, reason: &str) -> QueryResult<()> {
+    // One flag per user / IP and reason an hour is enough
+    let mut query = play_flags
+        .filter(crate::schema::play_flags::reason.eq(reason))
+        .filter(crate::schema::play_flags::date.gt(now - 1.hour()))
+        .into_boxed();
+
+    query = match target_user_id {
+        Some(u) => query.filter(crate::schema::play_flags::user_id.eq(u)),
+        None => query.filter(crate::schema::play_flags::ip.eq(ip))
+    };
+
+    let already_flagged: i64 = query.count().get_result(db)?;
+
+    if already_flagged == 0 {
+        diesel::insert_into(play_flags)
+            .values(NewPlayFlag {
+                user_id: target_user_id,
+                ip,
+                reason,
+            })
+            .execute(db)?;
+    }
+
+    Ok(())
+}
+
+// Stores a raw play event. Returns whether the event was flagged as anomalous.
+pub fn record_play_event(db: &PgConnection, viewer: i32, target_video_id: i32, ip: Option<&str>) -> QueryResult<bool> {
+    db.transaction(|| {
+        let user_events: i64 = play_events
+            .filter(crate::schema::play_events::user_id.eq(viewer)
+                .and(crate::schema::play_events::date.gt(now - 1.hour())))
+            .count()
+            .get_result(db)?;
+
+        let ip_events: i64 = match ip {
+            Some(v) => play_events
+                .filter(crate::schema::play_events::ip.eq(v)
+                    .and(crate::schema::play_events::date.gt(now - 1.hour())))
+                .count()
+                .get_result(db)?,
+            None => 0
+        };
+
+        let user_anomalous = user_events >= MAX_EVENTS_PER_USER_PER_HOUR;
+        let ip_anomalous = ip_events >= MAX_EVENTS_PER_IP_PER_HOUR;
+
+        if user_anomalous {
+            flag(db, Some(viewer), ip, "USER_PLAY_RATE")?;
+        }
+
+        if ip_anomalous {
+            flag(db, None, ip, "IP_PLAY_RATE")?;
+        }
+
+        diesel::insert_into(play_events)
+            .values(NewPlayEvent {
+                user_id: viewer,
+                video_id: target_video_id,
+                ip,
+                flagged: user_anomalous || ip_anomalous,
+            })
+            .execute(db)?;
+
+        // A new play starts a new watch session
+        diesel::update(crate::schema::video_progress::dsl::video_progress.filter(
+            crate::schema::video_progress::user_id.eq(viewer)
+                .and(crate::schema::video_progress::video_id.eq(target_video_id))
+        ))
+            .set(crate::schema::video_progress::session_seconds.eq(0))
+            .execute(db)?;
+
+        Ok(user_anomalous || ip_anomalous)
+    })
+}
+
+// Called after every progress heartbeat. Counts the latest play event for the video once the
+// session has been watched for long enough. Returns whether a play was counted.
+pub fn count_play_if_eligible(db: &PgConnection, viewer: i32, target_video_id: i32) -> QueryResult<bool> {
+    let progress = match get_progress(db, viewer, target_video_id) {
+        Some(v) => v,
+        None => { return Ok(false); }
+    };
+
+    // The stored duration, the player doesn't get a say in how much has to be watched
+    let duration: Option<i32> = videos
+        .find(target_video_id)
+        .select(crate::schema::videos::duration)
+        .first(db)?;
+
+    if progress.session_seconds < required_watch_seconds(duration) {
+        return Ok(false);
+    }
+
+    db.transaction(|| {
+        let event: Option<PlayEvent> = play_events
+            .filter(crate::schema::play_events::user_id.eq(viewer)
+                .and(crate::schema::play_events::video_id.eq(target_video_id))
+                .and(crate::schema::play_events::date.gt(now - DEDUPE_WINDOW_HOURS.hours())))
+            .order_by(crate::schema::play_events::date.desc())
+            .for_update()
+            .first::<PlayEvent>(db)
+            .optional()?;
+
+        let event = match event {
+            Some(v) if !v.counted && !v.flagged => v,
+            _ => { return Ok(false); }
+        };
+
+        let recently_counted: bool = diesel::select(exists(video_plays
+            .filter(crate::schema::video_plays::user_id.eq(viewer)
+                .and(crate::schema::video_plays::video_id.eq(target_video_id))
+                .and(crate::schema::video_plays::date.gt(now - DEDUPE_WINDOW_HOURS.hours())))
+        )).get_result(db)?;
+
+        // Repeat plays inside the window are kept as raw events but never counted
+        if recently_counted {
+            return Ok(false);
+        }
+
+        diesel::update(play_events.find(event.id))
+            .set(counted.eq(true))
+            .execute(db)?;
+
+        diesel::insert_into(video_plays)
+            .values(NewVideoPlay {
+                user_id: viewer,
+                video_id: target_video_id,
+                play_event_id: Some(event.id),
+            })
+            .execute(db)?;
+
+        Ok(true)
+    })
+}
@@ -0,0 +1,130 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use diesel::pg::upsert::excluded;
+
+use crate::diesel::RunQueryDsl;
+use crate::models::{NewProgressEvent, NewVideoProgress, VideoProgress};
+use crate::schema::progress_events::dsl::progress_events;
+use crate::schema::video_progress::dsl::{last_updated, position, session_seconds, video_id, video_progress, user_id, watched_seconds};
+use crate::schema::videos::dsl::videos;
+
+// Heartbeats are expected roughly every 10 seconds. Anything longer than this between two
+// heartbeats (pausing, closing the tab) isn't counted as watch time.
+pub const MAX_HEARTBEAT_GAP_SECONDS: u64 = 30;
+
+// Videos watched past this fraction of their duration are treated as finished
+pub const FINISHED_FRACTION: f64 = 0.95;
+
+// Positions below this aren't worth resuming from
+pub const MIN_RESUME_SECONDS: i32 = 10;
+
+pub fn get_progress(db: &PgConnection, viewer: i32, target_video_id: i32) -> Option<VideoProgress> {
+    video_progress
+        .filter(user_id.eq(viewer).and(video_id.eq(target_video_id)))
+        .first::<VideoProgress>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+// Stores a progress heartbeat and returns the number of seconds of watch time it added.
+// session_seconds counts the watch time since the last play event, see helpers::plays.
+// The duration is always the one stored for the video, never what the player reports.
+pub fn record_progress(db: &PgConnection, viewer: i32, target_video_id: i32, new_position: i32) -> QueryResult<i32> {
+    let now = SystemTime::now();
+
+    let new_duration: Option<i32> = videos
+        .find(target_video_id)
+        .select(crate::schema::videos::duration)
+        .first(db)?;
+
+    let new_position = match new_duration {
+        Some(d) if new_position > d => d,
+        _ => new_position.max(0)
+    };
+
+    let existing = get_progress(db, viewer, target_video_id);
+
+    // Watch time only grows while the position is moving, and never by more than the wall
+    // clock time since the last heartbeat
+    let watched_delta = match &existing {
+        Some(progress) if progress.position != new_position => {
+            let elapsed = now.duration_since(progress.last_updated)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+                .min(MAX_HEARTBEAT_GAP_SECONDS) as i32;
+
+            elapsed.min((new_position - progress.position).abs())
+        }
+        _ => 0
+    };
+
+    let new_progress = NewVideoProgress {
+        user_id: viewer,
+        video_id: target_video_id,
+        position: new_position,
+        watched_seconds: watched_delta,
+        duration: new_duration,
+        last_updated: now,
+        session_seconds: watched_delta,
+    };
+
+    diesel::insert_into(video_progress)
+        .values(&new_progress)
+        .on_conflict((user_id, video_id))
+        .do_update()
+        .set((
+            position.eq(excluded(position)),
+            watched_seconds.eq(watched_seconds + excluded(watched_seconds)),
+            crate::schema::video_progress::duration.eq(excluded(crate::schema::video_progress::duration)),
+            last_updated.eq(excluded(last_updated)),
+            session_seconds.eq(session_seconds + excluded(session_seconds)),
+        ))
+        .execute(db)?;
+
+    // Kept for the analytics rollups, see refresh_analytics. Watch time is only ever added
+    // when there was a previous heartbeat.
+    if let Some(progress) = existing.as_ref().filter(|_| watched_delta > 0) {
+        diesel::insert_into(progress_events)
+            .values(NewProgressEvent {
+                user_id: viewer,
+                video_id: target_video_id,
+                from_position: progress.position,
+                to_position: new_position,
+                watched_seconds: watched_delta,
+            })
+            .execute(db)?;
+    }
+
+    Ok(watched_delta)
+}
+
+pub fn is_finished(progress: &VideoProgress) -> bool {
+    match progress.duration {
+        Some(d) if d > 0 => progress.position as f64 >= d as f64 * FINISHED_FRACTION,
+        _ => false
+    }
+}
+
+// Where the viewer should resume from, if anywhere
+pub fn get_resume_position(progress: &VideoProgress) -> Option<i32> {
+    if progress.position < MIN_RESUME_SECONDS || is_finished(progress) {
+        return None;
+    }
+
+    Some(progress.position)
+}
+
+// Videos the viewer started but didn't finish, most recently watched first
+pub fn get_continue_watching_ids(db: &PgConnection, viewer: i32, limit: i64) -> Vec<i32> {
+    video_progress
+        .filter(user_id.eq(viewer).and(position.ge(MIN_RESUME_SECONDS)))
+        .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+            &format!("(video_progress.duration is null or video_progress.position < video_progress.duration * {})", FINISHED_FRACTION)
+        ))
+        .order_by(last_updated.desc())
+        .select(video_id)
+        .limit(limit)
+        .load::<i32>(db)
+        .expect("Query failed")
+}
@@ -1,100 +1,187 @@
-use crate::establish_connection;
-use crate::schema::videos::dsl::{videos, id, status};
-use diesel::{QueryDsl, ExpressionMethods, JoinOnDsl, BoolExpressionMethods, QueryResult};
-use diesel::dsl::{sql, exists};
-use diesel::pg::types::sql_types::{Array, Record};
-use diesel::sql_types::{Integer, VarChar};
+use std::collections::HashMap;
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, JoinOnDsl, PgConnection, QueryDsl};
+use diesel::dsl::{exists, not};
+use diesel::sql_types::{BigInt, Integer};
+
 use crate::diesel::RunQueryDsl;
-use crate::schema::video_plays::dsl::video_plays;
-use crate::schema::videos_tags::dsl::{videos_tags, tag_id};
+use crate::establish_connection;
+use crate::helpers::experiments::get_variant;
+use crate::helpers::videos::get_videos_by_ids;
+use crate::models::RecommendedVideo;
+use crate::schema::recommendation_feedback::dsl::{feedback_type, recommendation_feedback, target_id};
 use crate::schema::tags::dsl::tags;
-use crate::models::{Tag, VideoWithUser, get_safe_user_fields};
-use crate::schema::channels_tokens::dsl::channels_tokens;
-use crate::schema::video_upvotes::dsl::{video_upvotes, video_id, upvote_type};
-use crate::schema::users::dsl::users;
-use crate::diesel::GroupByDsl;
-use diesel::expression::dsl::not;
-
-pub async fn get_recommended_videos(user: i32) -> Vec<VideoWithUser> {
+use crate::schema::user_recommendations::dsl::{rank, reason_tag_id, reason_type, reason_video_id, user_recommendations};
+use crate::schema::video_plays::dsl::video_plays;
+use crate::schema::videos::dsl::{id, title, videos};
+
+pub const RECOMMENDATIONS_PAGE_SIZE: i64 = 20;
+
+#[derive(QueryableByName)]
+struct TrendingVideo {
+    #[sql_type = "Integer"]
+    id: i32,
+}
+
+fn get_feedback_targets(db: &PgConnection, user: i32, target_feedback_type: &str) -> Vec<i32> {
+    recommendation_feedback
+        .filter(crate::schema::recommendation_feedback::user_id.eq(user).and(feedback_type.eq(target_feedback_type)))
+        .select(target_id)
+        .load::<i32>(db)
+        .expect("Query failed.")
+}
+
+// Trending videos the user hasn't watched, with feedback applied the same way as in
+// refresh_user_recommendations: each "see less" tag a video has divides its score by four, and
+// among equal scores videos with fewer of those tags come first.
+fn get_trending_video_ids(db: &PgConnection, user: i32, page: i64) -> Vec<i32> {
+    let result: Vec<TrendingVideo> = diesel::sql_query("
+        select v.id
+        from videos v
+                 cross join lateral (select count(*) as see_less_tags
+                                     from videos_tags vt
+                                              inner join recommendation_feedback f
+                                                         on f.target_id = vt.tag_id and f.feedback_type = 'SEE_LESS_TAG'
+                                     where vt.video_id = v.id and f.user_id = $1) feedback
+        where v.status = 'READY'
+          and v.user_id <> $1
+          and not exists(select * from video_plays p where p.user_id = $1 and p.video_id = v.id)
+          and not exists(select * from recommendation_feedback f
+                         where f.user_id = $1 and f.feedback_type = 'NOT_INTERESTED_VIDEO' and f.target_id = v.id)
+          and not exists(select * from recommendation_feedback f
+                         where f.user_id = $1 and f.feedback_type = 'NOT_INTERESTED_CHANNEL' and f.target_id = v.user_id)
+        order by v.trending_score * power(0.25, feedback.see_less_tags) desc, feedback.see_less_tags asc, v.id desc
+        limit $2 offset $3
+    ")
+        .bind::<Integer, _>(user)
+        .bind::<BigInt, _>(RECOMMENDATIONS_PAGE_SIZE)
+        .bind::<BigInt, _>(page * RECOMMENDATIONS_PAGE_SIZE)
+        .load(db)
+        .expect("Query failed.");
+
+    result.into_iter().map(|v| v.id).collect()
+}
+
+/*
+ * Recommendations are precomputed by the refresh_recommendations job (see the
+ * collaborative_filtering and recommendation_feedback migrations for the scoring). Users
+ * without any precomputed recommendations yet get trending videos they haven't watched.
+ *
+ * Feedback is also applied here so "not interested" takes effect before the next refresh.
+ *
+ * Users in the "trending" variant of the "recommender" experiment always get trending videos.
+ */
+pub async fn get_recommended_videos(user: i32, page: i64) -> Vec<RecommendedVideo> {
     let db = establish_connection();
 
-    // Get tags videos watched by the user
-    //
-    let tags_result: QueryResult<Vec<Tag>> = videos
-        .select(
-        sql::<Array<Record<(Integer, VarChar)>>>("array_remove(array_agg(distinct tags), null) as tags")
-        )
-        .inner_join(video_plays)
-        .inner_join(videos_tags.on(crate::schema::videos_tags::video_id.eq(id)))
-        .inner_join(tags.on(crate::schema::tags::id.eq(tag_id)))
-        .filter(crate::schema::video_plays::user_id.eq(user))
-        .first::<Vec<Tag>>(&db);
-
-    // Get X most viewed videos that have a tag in that list
-    let result: Vec<VideoWithUser> = videos
-        .filter(not(
-            exists(
-                video_plays.filter(
-                    crate::schema::video_plays::user_id.eq(user)
-                        .and(crate::schema::video_plays::video_id.eq(crate::schema::videos::id)
-                        )
-                )
-            )
-        ))
-        .filter(status.eq("READY"))
-        .inner_join(users)
-        .left_join(videos_tags.on(crate::schema::videos_tags::video_id.eq(crate::schema::videos::id)))
-        .inner_join(tags.on(crate::schema::videos_tags::tag_id.eq(crate::schema::tags::id)))
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
-        .select(
-            (
-                crate::schema::videos::id,
-                crate::schema::videos::file_name,
-                get_safe_user_fields(),
-                crate::schema::videos::title,
-                crate::schema::videos::description,
-                crate::schema::videos::upload_date,
-                exists(video_upvotes.
-                    filter(video_id.eq(id)
-                        .and(upvote_type.eq("UP"))
-                        .and(crate::schema::video_upvotes::inactive.eq(false))
-                        .and(crate::schema::video_upvotes::user_id.eq(user))
-                    )
-                ),
-                exists(video_upvotes
-                    .filter(video_id.eq(id)
-                        .and(upvote_type.eq("DOWN"))
-                        .and(crate::schema::video_upvotes::inactive.eq(false))
-                        .and(crate::schema::video_upvotes::user_id.eq(user))
-                    )
-                ),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_upvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_downvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_plays(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Array<Record<(Integer, VarChar)>>>("array_agg(\"tags\".*) as tags")
+    let page = page.max(0);
+
+    let hidden_videos = get_feedback_targets(&db, user, "NOT_INTERESTED_VIDEO");
+    let hidden_channels = get_feedback_targets(&db, user, "NOT_INTERESTED_CHANNEL");
+
+    let trending_variant = get_variant(&db, user, "recommender").as_deref() == Some("trending");
+
+    // Videos watched since the recommendations were generated are skipped
+    let recommendations: Vec<(i32, String, Option<i32>, Option<i32>)> = user_recommendations
+        .inner_join(videos.on(id.eq(crate::schema::user_recommendations::video_id)))
+        .filter(crate::schema::user_recommendations::user_id.eq(user))
+        .filter(not(crate::schema::user_recommendations::video_id.eq_any(&hidden_videos)))
+        .filter(not(crate::schema::videos::user_id.eq_any(&hidden_channels)))
+        .filter(not(exists(
+            video_plays.filter(
+                crate::schema::video_plays::user_id.eq(user)
+                    .and(crate::schema::video_plays::video_id.eq(crate::schema::user_recommendations::video_id))
             )
-        )
-        .distinct()
-        .group_by((crate::schema::videos::id, crate::schema::users::id, crate::schema::channels_tokens::id))
+        )))
+        .order_by(rank.asc())
+        .select((crate::schema::user_recommendations::video_id, reason_type, reason_video_id, reason_tag_id))
+        .limit(RECOMMENDATIONS_PAGE_SIZE)
+        .offset(page * RECOMMENDATIONS_PAGE_SIZE)
         .load(&db)
         .expect("Query failed.");
 
+    if recommendations.len() > 0 && !trending_variant {
+        let reason_video_ids: Vec<i32> = recommendations.iter().filter_map(|r| r.2).collect();
+        let reason_tag_ids: Vec<i32> = recommendations.iter().filter_map(|r| r.3).collect();
 
-    return if let Ok(tags_result) = tags_result {
-        // This is needed as Rust Diesel dose not support HAVING clauses
-        let tags_ids: Vec<i32> = tags_result.iter().map(|tag| tag.id).collect();
-        let result: Vec<VideoWithUser> = result.into_iter().filter(|video| {
-            for tag in video.tags.iter() {
-                if tags_ids.contains(&tag.id) {
-                    return true;
-                }
-            }
+        let reason_videos: HashMap<i32, String> = videos
+            .filter(id.eq_any(&reason_video_ids))
+            .select((id, title))
+            .load::<(i32, String)>(&db)
+            .expect("Query failed.")
+            .into_iter()
+            .collect();
 
-            return false;
+        let reason_tags: HashMap<i32, String> = tags
+            .filter(crate::schema::tags::id.eq_any(&reason_tag_ids))
+            .select((crate::schema::tags::id, crate::schema::tags::name))
+            .load::<(i32, String)>(&db)
+            .expect("Query failed.")
+            .into_iter()
+            .collect();
+
+        let video_ids: Vec<i32> = recommendations.iter().map(|r| r.0).collect();
+        let reasons: HashMap<i32, (String, String)> = recommendations.into_iter().map(|r| {
+            let watched = r.2.and_then(|v| reason_videos.get(&v));
+            let tag = r.3.and_then(|t| reason_tags.get(&t));
+
+            let reason = match (r.1.as_str(), watched, tag) {
+                ("WATCHED", Some(watched_title), _) => ("WATCHED", format!("Because you watched {}", watched_title)),
+                (_, _, Some(tag_name)) => ("TAG", format!("Popular in {}", tag_name)),
+                _ => ("TRENDING", String::from("Trending now"))
+            };
+
+            (r.0, (reason.0.to_string(), reason.1))
         }).collect();
 
-        result
-    } else {
-        result
+        return get_videos_by_ids(&db, user, &video_ids).into_iter().map(|video| {
+            let (video_reason_type, reason) = reasons.get(&video.id).unwrap().clone();
+
+            RecommendedVideo {
+                video,
+                reason_type: video_reason_type,
+                reason,
+            }
+        }).collect();
+    }
+
+    let has_recommendations: bool = diesel::select(exists(
+        user_recommendations.filter(crate::schema::user_recommendations::user_id.eq(user))
+    ))
+        .get_result(&db)
+        .expect("Query failed.");
+
+    // Past the end of the user's recommendations
+    if has_recommendations && !trending_variant {
+        return vec![];
+    }
+
+    let video_ids: Vec<i32> = get_trending_video_ids(&db, user, page);
+
+    get_videos_by_ids(&db, user, &video_ids).into_iter().map(|video| {
+        RecommendedVideo {
+            video,
+            reason_type: String::from("TRENDING"),
+            reason: String::from("Trending now"),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::with_fixture;
+
+    use super::get_trending_video_ids;
+
+    #[test]
+    fn trending_fallback_applies_feedback() {
+        with_fixture(include_str!("../../fixtures/recommender/trending.sql"), |db| {
+            let ids: Vec<i32> = get_trending_video_ids(db, 900003, 0).into_iter()
+                .filter(|v| *v >= 900001)
+                .collect();
+
+            // A quarter of 900001's score is less than 900003's
+            assert_eq!(ids, vec![900002, 900003, 900001]);
+        });
     }
-}
\ No newline at end of file
+}
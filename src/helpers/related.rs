@@ -0,0 +1,91 @@
+use diesel::PgConnection;
+use diesel::sql_types::{BigInt, Double, Integer};
+
+use crate::diesel::RunQueryDsl;
+
+#[derive(QueryableByName)]
+pub struct RelatedVideo {
+    #[sql_type = "Integer"]
+    pub video_id: i32,
+    #[sql_type = "Double"]
+    pub score: f64,
+}
+
+/*
+ * "Up next" scoring for a video. Each candidate gets
+ *   3 points per shared tag
+ *   2 points if it is from the same channel
+ *   2 * ln(1 + n) where n is the number of users who played both videos
+ *   the smoothed upvote ratio (between 0 and 1) as a tie breaker
+ * Candidates need at least one tag, the channel or a viewer in common. Ties are broken by
+ * video ID so the order is deterministic.
+ */
+pub fn get_related_videos(db: &PgConnection, target_video_id: i32, limit: i64) -> Vec<RelatedVideo> {
+    diesel::sql_query("
+        select candidates.video_id,
+               (candidates.shared_tags * 3.0
+                   + case when candidates.same_channel then 2.0 else 0.0 end
+                   + 2.0 * ln(1 + candidates.co_viewers)
+                   + (candidates.upvote_count + 1.0) / (candidates.upvote_count + candidates.downvote_count + 2.0))::double precision as score
+        from (
+                 select v.id as video_id,
+                        v.upvote_count,
+                        v.downvote_count,
+                        v.user_id = current_video.user_id as same_channel,
+                        (select count(*)
+                         from videos_tags a
+                                  inner join videos_tags b on a.tag_id = b.tag_id
+                         where a.video_id = current_video.id and b.video_id = v.id) as shared_tags,
+                        (select count(distinct a.user_id)
+                         from video_plays a
+                                  inner join video_plays b on a.user_id = b.user_id
+                         where a.video_id = current_video.id and b.video_id = v.id) as co_viewers
+                 from videos v
+                          inner join videos current_video on current_video.id = $1
+                 where v.id <> current_video.id
+                   and v.status = 'READY'
+             ) candidates
+        where candidates.shared_tags > 0 or candidates.same_channel or candidates.co_viewers > 0
+        order by score desc, candidates.video_id asc
+        limit $2
+    ")
+        .bind::<Integer, _>(target_video_id)
+        .bind::<BigInt, _>(limit)
+        .load::<RelatedVideo>(db)
+        .expect("Query failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::with_fixture;
+
+    use super::get_related_videos;
+
+    #[test]
+    fn ranks_by_score_then_video_id() {
+        with_fixture(include_str!("../../fixtures/related/videos.sql"), |db| {
+            let related = get_related_videos(db, 900001, 10);
+
+            let ids: Vec<i32> = related.iter().map(|v| v.video_id).collect();
+            assert_eq!(ids, vec![900002, 900004, 900005, 900006, 900007, 900003]);
+
+            assert!((related[0].score - 6.5).abs() < 1e-9);
+            assert!((related[1].score - (3.0 + 4.0 / 6.0)).abs() < 1e-9);
+            assert!((related[4].score - (2.0 * 3f64.ln() + 0.5)).abs() < 1e-9);
+            assert!((related[5].score - 2.5).abs() < 1e-9);
+
+            // The tie is broken by ID
+            assert_eq!(related[2].score, related[3].score);
+        });
+    }
+
+    #[test]
+    fn respects_the_limit() {
+        with_fixture(include_str!("../../fixtures/related/videos.sql"), |db| {
+            let related = get_related_videos(db, 900001, 2);
+
+            let ids: Vec<i32> = related.iter().map(|v| v.video_id).collect();
+            assert_eq!(ids, vec![900002, 900004]);
+        });
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, JoinOnDsl, PgConnection, QueryDsl, QueryResult};
+use diesel::dsl::max;
+use serde::Serialize;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::economics::get_latest_plan;
+use crate::helpers::expiry::get_token_expiry;
+use crate::helpers::tokens::{lock_token_holder, transfer_token};
+use crate::models::{NewAutoRenewal, User};
+use crate::schema::auto_renewals::dsl::{auto_renewals, channel_user_id, priority, user_id};
+use crate::schema::users::dsl::users;
+
+/*
+ * Subscribers can list channels to support again automatically. When their monthly tokens are
+ * granted they are spent on the listed channels in priority order, and the "Renew supported
+ * channels" job renews any channel whose support runs out during the month while there are
+ * tokens left. Channels which are still supported are skipped, so a renewal never spends a
+ * token twice on the same channel.
+ */
+
+#[derive(Serialize)]
+pub struct AutoRenewalWithChannel {
+    pub channel_user_id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub priority: i32,
+    // When the current support runs out, None if the channel isn't supported right now
+    pub supported_until: Option<SystemTime>,
+    // Whether there will be a token for the channel after the next allocation
+    pub funded: bool,
+}
+
+#[derive(Serialize)]
+pub struct AutoRenewalList {
+    pub channels: Vec<AutoRenewalWithChannel>,
+    pub unused_tokens: i64,
+    pub monthly_tokens: i32,
+    pub next_allocation: Option<SystemTime>,
+    // Tokens left over once every listed channel has been renewed for the next month, negative
+    // when there aren't enough
+    pub projected_leftover: i64,
+}
+
+pub fn get_renewal_channel_ids(db: &PgConnection, subscriber: i32) -> Vec<i32> {
+    auto_renewals
+        .filter(user_id.eq(subscriber))
+        .order_by(priority.asc())
+        .select(channel_user_id)
+        .load::<i32>(db)
+        .expect("Query failed")
+}
+
+fn get_unused_token_count(db: &PgConnection, subscriber: i32) -> i64 {
+    crate::schema::tokens::table
+        .filter(crate::schema::tokens::user_id.eq(subscriber)
+            .and(crate::schema::tokens::date_used.is_null())
+            .and(crate::schema::tokens::date_expired.is_null())
+        )
+        .count()
+        .get_result::<i64>(db)
+        .expect("Query failed")
+}
+
+// When the subscriber's support for the channel runs out, None if it already has
+fn get_supported_until(db: &PgConnection, subscriber: i32, channel: i32) -> Option<SystemTime> {
+    crate::schema::channels_tokens::table
+        .inner_join(crate::schema::tokens::table)
+        .filter(crate::schema::tokens::user_id.eq(subscriber)
+            .and(crate::schema::channels_tokens::channel_user_id.eq(channel))
+            .and(crate::schema::channels_tokens::expires.ge(SystemTime::now()))
+        )
+        .select(max(crate::schema::channels_tokens::expires))
+        .first::<Option<SystemTime>>(db)
+        .expect("Query failed")
+}
+
+// Every listed channel takes one token a month, since support lasts about as long as a billing
+// period. The tokens the subscriber has now plus the next allocation are spent in priority order.
+pub fn get_auto_renewal_list(db: &PgConnection, subscriber: &User) -> AutoRenewalList {
+    let monthly_tokens = match get_latest_plan(db, &subscriber.plan) {
+        Some(plan) if subscriber.subscribed => plan.monthly_tokens,
+        _ => 0
+    };
+
+    let unused_tokens = get_unused_token_count(db, subscriber.id);
+    // Tokens which expire at the end of the period won't be there for the next one
+    let expiring = get_token_expiry(db, subscriber.id).expiring as i64;
+    let available = unused_tokens - expiring + monthly_tokens as i64;
+
+    let result: Vec<(i32, i32, String, Option<String>)> = auto_renewals
+        .inner_join(users.on(crate::schema::users::id.eq(channel_user_id)))
+        .filter(user_id.eq(subscriber.id))
+        .order_by(priority.asc())
+        .select((channel_user_id, priority, crate::schema::users::username, crate::schema::users::display_name))
+        .load(db)
+        .expect("Query failed");
+
+    let listed = result.len() as i64;
+
+    let channels = result.into_iter().enumerate().map(|(index, (channel, channel_priority, username, display_name))| {
+        AutoRenewalWithChannel {
+            channel_user_id: channel,
+            username,
+            display_name,
+            priority: channel_priority,
+            supported_until: get_supported_until(db, subscriber.id, channel),
+            funded: (index as i64) < available,
+        }
+    }).collect();
+
+    AutoRenewalList {
+        channels,
+        unused_tokens,
+        monthly_tokens,
+        next_allocation: if subscriber.subscribed { subscriber.current_period_end } else { None },
+        projected_leftover: available - listed,
+    }
+}
+
+// Adds the channel to the end of the list. Does nothing if it is already listed.
+pub fn add_auto_renewal(db: &PgConnection, subscriber: i32, channel: i32) -> QueryResult<()> {
+    db.transaction(|| {
+        let last: Option<i32> = auto_renewals
+            .filter(user_id.eq(subscriber))
+            .select(max(priority))
+            .first(db)?;
+
+        diesel::insert_into(auto_renewals)
+            .values(NewAutoRenewal {
+                user_id: subscriber,
+                channel_user_id: channel,
+                priority: last.map(|p| p + 1).unwrap_or(0),
+            })
+            .on_conflict_do_nothing()
+            .execute(db)?;
+
+        Ok(())
+    })
+}
+
+// Returns false if the channel wasn't listed
+pub fn remove_auto_renewal(db: &PgConnection, subscriber: i32, channel: i32) -> QueryResult<bool> {
+    let deleted = diesel::delete(auto_renewals.filter(user_id.eq(subscriber).and(channel_user_id.eq(channel))))
+        .execute(db)?;
+
+    Ok(deleted > 0)
+}
+
+// Rewrites the priorities so they match the order of channel_ids
+pub fn set_auto_renewal_order(db: &PgConnection, subscriber: i32, channel_ids: &Vec<i32>) -> QueryResult<()> {
+    db.transaction(|| {
+        for (index, channel) in channel_ids.iter().enumerate() {
+            diesel::update(auto_renewals.filter(user_id.eq(subscriber).and(channel_user_id.eq(channel))))
+                .set(priority.eq(index as i32))
+                .execute(db)?;
+        }
+
+        Ok(())
+    })
+}
+
+// Gives a token to each listed channel which isn't supported right now, in priority order,
+// until the tokens run out. Returns the channels renewed. Must be called in a transaction, the
+// subscriber stays locked until it ends so the job, invoice.paid and a manual transfer can't
+// renew the same channel at once.
+pub fn renew_channels(db: &PgConnection, subscriber: &User) -> QueryResult<Vec<i32>> {
+    let mut renewed = vec![];
+
+    if !subscriber.subscribed {
+        return Ok(renewed);
+    }
+
+    lock_token_holder(db, subscriber.id)?;
+
+    for channel in get_renewal_channel_ids(db, subscriber.id) {
+        if get_supported_until(db, subscriber.id, channel).is_some() {
+            continue;
+        }
+
+        if get_unused_token_count(db, subscriber.id) == 0 {
+            break;
+        }
+
+        // Each renewal has its own savepoint, so one which fails is undone without losing the
+        // others or whatever the caller did in the transaction, e.g. granting the tokens. A
+        // channel which can't be given tokens any more stays on the list, it is only skipped.
+        let transferred = db.transaction(|| transfer_token(db, subscriber.id, channel))
+            .unwrap_or_else(|e| Err(e.to_string()));
+
+        match transferred {
+            Ok(_) => renewed.push(channel),
+            Err(e) => println!("Couldn't renew channel {} for user {}: {}", channel, subscriber.id, e)
+        }
+    }
+
+    Ok(renewed)
+}
@@ -0,0 +1,366 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::{PgConnection, QueryResult};
+use diesel::sql_types::{BigInt, Integer, Nullable, Timestamp, VarChar};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::pdf::render_lines;
+
+/*
+ * Monthly earnings statements. Everything comes from the ledger and the token transactions
+ * posted to it, dated by their ledger transaction, so a past month always gives the same
+ * statement. The balance is the channel's earnings plus payouts in flight, i.e. everything
+ * the platform still owes the channel, which is what check_ledger reconciles against.
+ */
+
+pub struct StatementLine {
+    pub date: SystemTime,
+    pub transaction_type: String,
+    pub token_count: Option<i32>,
+    // Value of a token in the economics the deposit was made under
+    pub token_value: Option<i32>,
+    // Signed, withdrawals are negative
+    pub amount: i64,
+    pub balance: i64,
+}
+
+pub struct Statement {
+    pub period: String,
+    // The channel's ID rather than their username, which can change after the month is over
+    pub channel_id: i32,
+    pub currency: String,
+    pub opening_balance: i64,
+    pub closing_balance: i64,
+    pub tokens_converted: i64,
+    pub deposits: i64,
+    pub withdrawals: i64,
+    pub reversals: i64,
+    pub lines: Vec<StatementLine>,
+}
+
+#[derive(QueryableByName)]
+struct Balances {
+    #[sql_type = "BigInt"]
+    opening: i64,
+    #[sql_type = "BigInt"]
+    closing: i64,
+}
+
+#[derive(QueryableByName)]
+struct StatementRow {
+    #[sql_type = "Timestamp"]
+    date: SystemTime,
+    #[sql_type = "VarChar"]
+    transaction_type: String,
+    #[sql_type = "Nullable<Integer>"]
+    token_count: Option<i32>,
+    #[sql_type = "Nullable<Integer>"]
+    token_value: Option<i32>,
+    #[sql_type = "Integer"]
+    amount: i32,
+}
+
+#[derive(QueryableByName)]
+struct StatementCurrency {
+    #[sql_type = "VarChar"]
+    currency: String,
+}
+
+#[derive(QueryableByName)]
+struct StatementPeriod {
+    #[sql_type = "VarChar"]
+    period: String,
+}
+
+// Periods are months, e.g. '2021-06'
+pub fn is_valid_period(period: &str) -> bool {
+    period.len() == 7 && NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d").is_ok()
+}
+
+// The months the channel has anything to show for, newest first
+pub fn get_statement_periods(db: &PgConnection, channel_id: i32) -> Vec<String> {
+    let result: Vec<StatementPeriod> = diesel::sql_query("
+        select distinct to_char(lt.created, 'YYYY-MM') as period
+        from token_transactions t
+                 inner join ledger_transactions lt on lt.id = t.ledger_transaction_id
+        where t.channel_user_id = $1
+        order by period desc
+    ")
+        .bind::<Integer, _>(channel_id)
+        .load(db)
+        .expect("Query failed");
+
+    result.into_iter().map(|p| p.period).collect()
+}
+
+// The currency of the token economics in effect at the end of the month, which the month's
+// conversions and withdrawals were made in. Months before the first version get its currency.
+pub fn get_statement_currency(db: &PgConnection, period: &str) -> QueryResult<String> {
+    let result: StatementCurrency = diesel::sql_query("
+        select coalesce(
+                       (select currency
+                        from token_economics
+                        where effective_from < to_date($1, 'YYYY-MM') + interval '1 month'
+                        order by effective_from desc
+                        limit 1),
+                       (select currency from token_economics order by effective_from limit 1)
+                   ) as currency
+    ")
+        .bind::<VarChar, _>(period)
+        .get_result(db)?;
+
+    Ok(result.currency)
+}
+
+pub fn get_statement(db: &PgConnection, channel_id: i32, period: &str, currency: &str) -> QueryResult<Statement> {
+    let balances: Balances = diesel::sql_query("
+        select coalesce(sum(e.amount) filter (where lt.created < to_date($2, 'YYYY-MM')), 0)::bigint as opening,
+               coalesce(sum(e.amount) filter (where lt.created < to_date($2, 'YYYY-MM') + interval '1 month'), 0)::bigint as closing
+        from ledger_entries e
+                 inner join ledger_accounts a on a.id = e.account_id
+                 inner join ledger_transactions lt on lt.id = e.transaction_id
+        where a.user_id = $1
+          and a.currency = $3
+          and a.account_type in ('CHANNEL_EARNINGS', 'PAYOUTS_IN_FLIGHT')
+    ")
+        .bind::<Integer, _>(channel_id)
+        .bind::<VarChar, _>(period)
+        .bind::<VarChar, _>(currency)
+        .get_result(db)?;
+
+    let rows: Vec<StatementRow> = diesel::sql_query("
+        select lt.created as date, t.transaction_type, t.token_count, te.token_value, t.amount
+        from token_transactions t
+                 inner join ledger_transactions lt on lt.id = t.ledger_transaction_id
+                 left join token_economics te on te.id = t.economics_id
+        where t.channel_user_id = $1
+          and coalesce(te.currency, $3) = $3
+          and lt.created >= to_date($2, 'YYYY-MM')
+          and lt.created < to_date($2, 'YYYY-MM') + interval '1 month'
+        order by lt.created, t.id
+    ")
+        .bind::<Integer, _>(channel_id)
+        .bind::<VarChar, _>(period)
+        .bind::<VarChar, _>(currency)
+        .load(db)?;
+
+    let mut statement = Statement {
+        period: period.to_string(),
+        channel_id,
+        currency: currency.to_string(),
+        opening_balance: balances.opening,
+        closing_balance: balances.closing,
+        tokens_converted: 0,
+        deposits: 0,
+        withdrawals: 0,
+        reversals: 0,
+        lines: vec![],
+    };
+
+    let mut balance = balances.opening;
+
+    for row in rows {
+        let amount = match row.transaction_type.as_str() {
+            "WITHDRAWAL" => {
+                statement.withdrawals += row.amount as i64;
+                -(row.amount as i64)
+            }
+            "REVERSAL" => {
+                statement.reversals += row.amount as i64;
+                row.amount as i64
+            }
+            _ => {
+                statement.deposits += row.amount as i64;
+                statement.tokens_converted += row.token_count.unwrap_or(0) as i64;
+                row.amount as i64
+            }
+        };
+
+        balance += amount;
+
+        statement.lines.push(StatementLine {
+            date: row.date,
+            transaction_type: row.transaction_type,
+            token_count: row.token_count,
+            token_value: row.token_value,
+            amount,
+            balance,
+        });
+    }
+
+    Ok(statement)
+}
+
+// Amounts are stored in the smallest unit of the currency
+fn format_amount(amount: i64) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+
+    format!("{}{}.{:02}", sign, amount.abs() / 100, amount.abs() % 100)
+}
+
+fn format_date(date: SystemTime) -> String {
+    DateTime::<Utc>::from(date).format("%Y-%m-%d").to_string()
+}
+
+// The rates deposits were converted at, in the order they first appear
+fn get_rates(statement: &Statement) -> String {
+    let mut rates: Vec<i32> = vec![];
+
+    for line in &statement.lines {
+        if let (Some(_), Some(value)) = (line.token_count, line.token_value) {
+            if !rates.contains(&value) {
+                rates.push(value);
+            }
+        }
+    }
+
+    if rates.is_empty() {
+        return String::from("-");
+    }
+
+    rates.iter().map(|r| format_amount(*r as i64)).collect::<Vec<String>>().join(" / ")
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or(String::new())
+}
+
+pub fn statement_to_csv(statement: &Statement) -> String {
+    let summary: Vec<(&str, String)> = vec![
+        ("Period", statement.period.clone()),
+        ("Channel", statement.channel_id.to_string()),
+        ("Currency", statement.currency.clone()),
+        ("Opening balance", format_amount(statement.opening_balance)),
+        ("Tokens converted", statement.tokens_converted.to_string()),
+        ("Rate per token", get_rates(statement)),
+        ("Deposits", format_amount(statement.deposits)),
+        ("Withdrawals", format_amount(statement.withdrawals)),
+        ("Reversals", format_amount(statement.reversals)),
+        ("Closing balance", format_amount(statement.closing_balance)),
+    ];
+
+    let mut csv = String::new();
+
+    for (name, value) in summary {
+        csv.push_str(&format!("{},{}\r\n", csv_field(name), csv_field(&value)));
+    }
+
+    csv.push_str("\r\nDate,Type,Tokens,Rate per token,Amount,Balance\r\n");
+
+    for line in &statement.lines {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\r\n",
+            format_date(line.date),
+            csv_field(&line.transaction_type),
+            optional(line.token_count.map(|c| c.to_string())),
+            optional(line.token_value.filter(|_| line.token_count.is_some()).map(|v| format_amount(v as i64))),
+            format_amount(line.amount),
+            format_amount(line.balance),
+        ));
+    }
+
+    csv
+}
+
+pub fn statement_to_pdf(statement: &Statement) -> Vec<u8> {
+    let mut lines: Vec<String> = vec![
+        format!("Earnings statement {}", statement.period),
+        String::new(),
+        format!("{:<24}{}", "Channel", statement.channel_id),
+        format!("{:<24}{}", "Currency", statement.currency),
+        String::new(),
+        format!("{:<24}{:>14}", "Opening balance", format_amount(statement.opening_balance)),
+        format!("{:<24}{:>14}", "Tokens converted", statement.tokens_converted),
+        format!("{:<24}{:>14}", "Rate per token", get_rates(statement)),
+        format!("{:<24}{:>14}", "Deposits", format_amount(statement.deposits)),
+        format!("{:<24}{:>14}", "Withdrawals", format_amount(statement.withdrawals)),
+        format!("{:<24}{:>14}", "Reversals", format_amount(statement.reversals)),
+        format!("{:<24}{:>14}", "Closing balance", format_amount(statement.closing_balance)),
+        String::new(),
+        format!("{:<12}{:<12}{:>8}{:>10}{:>14}{:>14}", "Date", "Type", "Tokens", "Rate", "Amount", "Balance"),
+    ];
+
+    if statement.lines.is_empty() {
+        lines.push(String::from("No transactions this month"));
+    }
+
+    for line in &statement.lines {
+        lines.push(format!(
+            "{:<12}{:<12}{:>8}{:>10}{:>14}{:>14}",
+            format_date(line.date),
+            line.transaction_type,
+            optional(line.token_count.map(|c| c.to_string())),
+            optional(line.token_value.filter(|_| line.token_count.is_some()).map(|v| format_amount(v as i64))),
+            format_amount(line.amount),
+            format_amount(line.balance),
+        ));
+    }
+
+    render_lines(&lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::test_helpers::with_fixture;
+
+    use super::{get_statement, Statement, statement_to_csv, statement_to_pdf, StatementLine};
+
+    fn fixed_statement() -> Statement {
+        let day = |d: u64| UNIX_EPOCH + Duration::from_secs(1622505600 + d * 24 * 60 * 60);
+
+        Statement {
+            period: String::from("2021-06"),
+            channel_id: 900001,
+            currency: String::from("GBP"),
+            opening_balance: 500,
+            closing_balance: 500,
+            tokens_converted: 4,
+            deposits: 200,
+            withdrawals: 300,
+            reversals: 100,
+            lines: vec![
+                StatementLine { date: day(0), transaction_type: String::from("DEPOSIT"), token_count: Some(4), token_value: Some(50), amount: 200, balance: 700 },
+                StatementLine { date: day(9), transaction_type: String::from("WITHDRAWAL"), token_count: None, token_value: Some(50), amount: -300, balance: 400 },
+                StatementLine { date: day(19), transaction_type: String::from("REVERSAL"), token_count: None, token_value: Some(50), amount: 100, balance: 500 },
+            ],
+        }
+    }
+
+    // Downloading a past month again has to give the same file
+    #[test]
+    fn renders_the_same_statement_identically() {
+        assert_eq!(statement_to_csv(&fixed_statement()), statement_to_csv(&fixed_statement()));
+        assert_eq!(statement_to_pdf(&fixed_statement()), statement_to_pdf(&fixed_statement()));
+    }
+
+    #[test]
+    fn adds_up_the_month_from_the_ledger() {
+        with_fixture(include_str!("../../fixtures/statements/ledger.sql"), |db| {
+            let statement = get_statement(db, 900001, "2021-06", "XTS").unwrap();
+
+            // May's deposit is brought forward, July's is left out
+            assert_eq!(statement.opening_balance, 500);
+            assert_eq!(statement.closing_balance, 500);
+            assert_eq!(statement.tokens_converted, 4);
+            assert_eq!(statement.deposits, 200);
+            assert_eq!(statement.withdrawals, 300);
+            assert_eq!(statement.reversals, 100);
+
+            let amounts: Vec<(i64, i64)> = statement.lines.iter().map(|line| (line.amount, line.balance)).collect();
+            assert_eq!(amounts, vec![(200, 700), (-300, 400), (100, 500)]);
+
+            assert!(statement_to_csv(&statement).contains("Rate per token,0.50\r\n"));
+        });
+    }
+}
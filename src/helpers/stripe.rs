@@ -1,72 +1,236 @@
-use std::collections::HashMap;
-
+use actix_web::error::BlockingError;
+use actix_web::web;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
+use crate::helpers::payments::{Account, Customer, PaymentsError, PaymentsProvider, Subscription, Transfer};
+
+// Uses the blocking client, since actix-web 3 runs on an older tokio than reqwest's async client
+// needs. Requests are sent from actix's blocking thread pool with web::block so they don't hold
+// up the server's workers, and the same futures work from the job scheduler's threads.
+pub struct StripeProvider {
+    client: reqwest::blocking::Client,
+    secret_key: String,
+    // STRIPE_API_BASE, e.g. to point at stripe-mock
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct ObjectResponse {
+    id: String,
+}
+
 #[derive(Deserialize)]
-pub struct CreateAccountLinkResponse {
+struct SubscriptionResponse {
+    id: String,
+    status: String,
+    cancel_at_period_end: bool,
+}
+
+#[derive(Deserialize)]
+struct ListResponse<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct AccountLinkResponse {
     url: String,
 }
 
-// TODO: handle errors better
-// TODO: redirect URLS
-pub async fn create_account_link(account_id: &str) -> Result<String, String> {
-    let client = reqwest::blocking::Client::new();
-    let mut params = HashMap::new();
-    params.insert("account", account_id);
-    params.insert("refresh_url", "http://localhost:3000/login");
-    params.insert("return_url", "http://localhost:3000");
-    params.insert("type", "account_onboarding");
-
-    let secret_key = std::env::var("STRIPE_SECRET").expect("Missing STRIPE_SECRET_KEY in env");
-
-    let res = client.post("https://api.stripe.com/v1/account_links")
-        .basic_auth(secret_key, Some(""))
-        .form(&params)
-        .send();
-
-    return match res {
-        Ok(res) => {
-            let account_link = res.json::<CreateAccountLinkResponse>().unwrap();
-            Ok(account_link.url)
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl From<SubscriptionResponse> for Subscription {
+    fn from(s: SubscriptionResponse) -> Self {
+        Subscription {
+            id: s.id,
+            status: s.status,
+            cancel_at_period_end: s.cancel_at_period_end,
         }
-        Err(_) => {
-            Err("Couldn't create account link".parse().unwrap())
+    }
+}
+
+impl StripeProvider {
+    pub fn from_env() -> Result<StripeProvider, String> {
+        let secret_key = std::env::var("STRIPE_SECRET")
+            .map_err(|_| String::from("Missing STRIPE_SECRET in env"))?;
+
+        let base_url = std::env::var("STRIPE_API_BASE")
+            .unwrap_or(String::from("https://api.stripe.com/v1"));
+
+        Ok(StripeProvider {
+            client: reqwest::blocking::Client::new(),
+            secret_key,
+            base_url,
+        })
+    }
+
+    async fn post<T: DeserializeOwned + Send + 'static>(&self, path: &str, params: &[(&str, &str)], idempotency_key: Option<&str>) -> Result<T, PaymentsError> {
+        let mut req = self.client.post(&format!("{}/{}", self.base_url, path))
+            .basic_auth(&self.secret_key, Some(""))
+            .form(params);
+
+        if let Some(key) = idempotency_key {
+            req = req.header("Idempotency-Key", key);
         }
-    };
+
+        send(path, req).await
+    }
+
+    async fn get<T: DeserializeOwned + Send + 'static>(&self, path: &str, params: &[(&str, &str)]) -> Result<T, PaymentsError> {
+        let req = self.client.get(&format!("{}/{}", self.base_url, path))
+            .basic_auth(&self.secret_key, Some(""))
+            .query(params);
+
+        send(path, req).await
+    }
 }
 
-#[derive(Deserialize)]
-pub struct CreateTransferResponse {
+// The request runs on the blocking thread pool, so the handler really is suspended here and
+// mustn't hold the AppState lock, see CheckLogin
+async fn send<T: DeserializeOwned + Send + 'static>(path: &str, req: reqwest::blocking::RequestBuilder) -> Result<T, PaymentsError> {
+    let path = path.to_string();
+
+    web::block(move || send_blocking(&path, req))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => PaymentsError::Unavailable(String::from("Stripe request was cancelled"))
+        })
 }
 
-pub async fn create_transfer(account_id: &str, amount: i32) -> Result<CreateTransferResponse, String> {
-    let client = reqwest::blocking::Client::new();
-    let mut params = HashMap::new();
-    params.insert("destination", account_id);
+fn send_blocking<T: DeserializeOwned>(path: &str, req: reqwest::blocking::RequestBuilder) -> Result<T, PaymentsError> {
+    let res = req.send()
+        .map_err(|e| PaymentsError::Unavailable(format!("Stripe request to {} failed: {}", path, e)))?;
+
+    let status = res.status();
 
-    let amount = amount.to_string();
-    let amount = amount.as_str();
-    params.insert("amount", amount);
-    params.insert("currency", "gbp");
+    if !status.is_success() {
+        let message = match res.json::<ErrorResponse>() {
+            Ok(e) => e.error.message,
+            Err(_) => String::from("Unknown error")
+        };
 
-    let secret_key = std::env::var("STRIPE_SECRET").expect("Missing STRIPE_SECRET_KEY in env");
+        let message = format!("Stripe request to {} failed: {}", path, message);
+
+        // Rate limits, Stripe's own errors and a request with the same idempotency key still
+        // being handled (409) can be retried, anything else was refused
+        return if status.as_u16() == 409 || status.as_u16() == 429 || status.is_server_error() {
+            Err(PaymentsError::Unavailable(message))
+        } else {
+            Err(PaymentsError::Declined(message))
+        };
+    }
+
+    res.json::<T>()
+        .map_err(|_| PaymentsError::Unavailable(format!("Couldn't read the response from {}", path)))
+}
 
-    let res = client.post("https://api.stripe.com/v1/transfers")
-        .basic_auth(secret_key, Some(""))
-        .form(&params)
-        .send();
+#[async_trait]
+impl PaymentsProvider for StripeProvider {
+    async fn create_customer(&self, email: &str, payment_method_id: &str) -> Result<Customer, PaymentsError> {
+        let customer: ObjectResponse = self.post("customers", &[
+            ("email", email),
+            ("payment_method", payment_method_id),
+        ], None).await?;
 
-    return match res {
-        Ok(res) => {
-            let res = res.json::<CreateTransferResponse>();
+        Ok(Customer { id: customer.id })
+    }
 
-            match res {
-                Ok(v) => {Ok(v)}
-                Err(_) => {Err("Couldn't create transfer".parse().unwrap())}
-            }
+    async fn create_subscription(&self, customer_id: &str, price_id: &str, payment_method_id: Option<&str>) -> Result<Subscription, PaymentsError> {
+        let mut params = vec![
+            ("customer", customer_id),
+            ("items[0][price]", price_id),
+            ("items[0][quantity]", "1"),
+            ("collection_method", "charge_automatically"),
+        ];
+
+        if let Some(payment_method_id) = payment_method_id {
+            params.push(("default_payment_method", payment_method_id));
         }
-        Err(_) => {
-            Err("Couldn't create transfer".parse().unwrap())
+
+        let subscription: SubscriptionResponse = self.post("subscriptions", &params, None).await?;
+
+        Ok(subscription.into())
+    }
+
+    // Stripe leaves out canceled subscriptions unless asked for them
+    async fn get_customer_subscription(&self, customer_id: &str) -> Result<Option<Subscription>, PaymentsError> {
+        let subscriptions: ListResponse<SubscriptionResponse> = self.get("subscriptions", &[
+            ("customer", customer_id),
+            ("limit", "1"),
+        ]).await?;
+
+        Ok(subscriptions.data.into_iter().next().map(|subscription| subscription.into()))
+    }
+
+    async fn set_subscription_cancel_at_period_end(&self, subscription_id: &str, cancel: bool) -> Result<Subscription, PaymentsError> {
+        let subscription: SubscriptionResponse = self.post(&format!("subscriptions/{}", subscription_id), &[
+            ("cancel_at_period_end", if cancel { "true" } else { "false" }),
+        ], None).await?;
+
+        Ok(subscription.into())
+    }
+
+    async fn set_default_payment_method(&self, customer_id: &str, subscription_id: Option<&str>, payment_method_id: &str) -> Result<(), PaymentsError> {
+        self.post::<ObjectResponse>(&format!("payment_methods/{}/attach", payment_method_id), &[
+            ("customer", customer_id),
+        ], None).await?;
+
+        self.post::<ObjectResponse>(&format!("customers/{}", customer_id), &[
+            ("invoice_settings[default_payment_method]", payment_method_id),
+        ], None).await?;
+
+        if let Some(subscription_id) = subscription_id {
+            self.post::<ObjectResponse>(&format!("subscriptions/{}", subscription_id), &[
+                ("default_payment_method", payment_method_id),
+            ], None).await?;
         }
-    };
-}
\ No newline at end of file
+
+        Ok(())
+    }
+
+    async fn create_account(&self, email: &str, country: &str) -> Result<Account, PaymentsError> {
+        let account: ObjectResponse = self.post("accounts", &[
+            ("type", "express"),
+            ("country", country),
+            ("email", email),
+            ("capabilities[card_payments][requested]", "true"),
+            ("capabilities[transfers][requested]", "true"),
+        ], None).await?;
+
+        Ok(Account { id: account.id })
+    }
+
+    async fn create_account_link(&self, account_id: &str, refresh_url: &str, return_url: &str) -> Result<String, PaymentsError> {
+        let account_link: AccountLinkResponse = self.post("account_links", &[
+            ("account", account_id),
+            ("refresh_url", refresh_url),
+            ("return_url", return_url),
+            ("type", "account_onboarding"),
+        ], None).await?;
+
+        Ok(account_link.url)
+    }
+
+    async fn create_transfer(&self, account_id: &str, amount: i64, currency: &str, transfer_group: &str, idempotency_key: &str) -> Result<Transfer, PaymentsError> {
+        let amount = amount.to_string();
+
+        let transfer: ObjectResponse = self.post("transfers", &[
+            ("destination", account_id),
+            ("amount", amount.as_str()),
+            ("currency", currency),
+            ("transfer_group", transfer_group),
+        ], Some(idempotency_key)).await?;
+
+        Ok(Transfer { id: transfer.id })
+    }
+}
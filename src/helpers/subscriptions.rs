@@ -0,0 +1,220 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, PgExpressionMethods, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::economics::{get_latest_plan, get_plan_by_price};
+use crate::helpers::payments::{payments, Subscription};
+use crate::helpers::renewals::renew_channels;
+use crate::helpers::tokens::grant_tokens;
+use crate::models::User;
+use crate::schema::users::dsl::{cancel_at_period_end, current_period_end, grace_period_ends, stripe_customer, stripe_subscription, subscribed, subscription_status, users};
+
+/*
+ * Subscription state for subscribers, see the subscription_lifecycle migration for the
+ * statuses. Changes come from Stripe webhooks, apart from suspension which is done by the
+ * "Suspend lapsed subscriptions" job once the grace period is over.
+ */
+
+// How long a subscriber keeps access after a failed payment
+pub const GRACE_PERIOD_DAYS: u64 = 7;
+
+pub fn get_user_by_customer(db: &PgConnection, customer: &str) -> QueryResult<Option<User>> {
+    users
+        .filter(stripe_customer.eq(customer))
+        .first::<User>(db)
+        .optional()
+}
+
+// Events for a subscription the user has since replaced, e.g. one which ended before they
+// resumed, mustn't change the one they have now. Users without a stripe_subscription haven't been
+// backfilled yet, so any of theirs counts.
+pub fn is_current_subscription(user: &User, subscription_id: &str) -> bool {
+    match &user.stripe_subscription {
+        Some(v) => v == subscription_id,
+        None => true
+    }
+}
+
+// Clears a subscription which has ended, before a new one is created, so the new one's events
+// are handled even if they arrive before subscription_created
+pub fn clear_ended_subscription(db: &PgConnection, user: &User) -> QueryResult<()> {
+    diesel::update(users.find(user.id).filter(subscription_status.eq("CANCELED")))
+        .set(stripe_subscription.eq(None::<String>))
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Stores a subscription which was just created. It is INCOMPLETE until its first invoice is
+// paid, but Stripe charges that invoice while creating it, so invoice.paid may have been handled
+// already. Then the row has the subscription and is left alone.
+pub fn subscription_created(db: &PgConnection, target_user_id: i32, subscription_id: &str) -> QueryResult<()> {
+    diesel::update(users.find(target_user_id).filter(stripe_subscription.is_distinct_from(subscription_id)))
+        .set((
+            stripe_subscription.eq(subscription_id),
+            subscription_status.eq("INCOMPLETE"),
+        ))
+        .execute(db)?;
+
+    Ok(())
+}
+
+// An invoice for the subscription was paid. Grants the tokens for the billing period it
+// covers, once, and reactivates the subscription if it was past due or suspended. Newly granted
+// tokens go to the subscriber's auto renewals first.
+//
+// The plan is the one the invoice's price belongs to, so subscribers whose plan was dropped from
+// the current token economics still get their tokens.
+pub fn subscription_paid(db: &PgConnection, user: &User, subscription_id: &str, price_id: Option<&str>, period_start: i64, period_end: SystemTime) -> QueryResult<()> {
+    let plan = price_id
+        .and_then(|price| get_plan_by_price(db, price))
+        .or_else(|| get_latest_plan(db, &user.plan));
+
+    let granted = match plan {
+        Some(plan) => {
+            let grant_period = format!("{}:{}", subscription_id, period_start);
+
+            grant_tokens(db, user.id, &grant_period, plan.monthly_tokens, period_end)?
+        }
+        None => {
+            println!("User {} is on unknown plan {}", user.id, user.plan);
+            false
+        }
+    };
+
+    let user: User = diesel::update(users.find(user.id))
+        .set((
+            subscription_status.eq("ACTIVE"),
+            subscribed.eq(true),
+            stripe_subscription.eq(subscription_id),
+            current_period_end.eq(period_end),
+            grace_period_ends.eq(None::<SystemTime>),
+        ))
+        .get_result(db)?;
+
+    if granted {
+        renew_channels(db, &user)?;
+    }
+
+    Ok(())
+}
+
+// Starts the grace period. Further failures during it don't extend it. A failed first invoice
+// suspends the subscription straight away, since nothing has been paid for yet; paying it with a
+// new payment method activates it.
+pub fn subscription_payment_failed(db: &PgConnection, user: &User) -> QueryResult<()> {
+    if user.subscription_status == "INCOMPLETE" {
+        return subscription_suspended(db, user);
+    }
+
+    if user.subscription_status != "ACTIVE" {
+        return Ok(());
+    }
+
+    diesel::update(users.find(user.id))
+        .set((
+            subscription_status.eq("PAST_DUE"),
+            grace_period_ends.eq(SystemTime::now() + Duration::from_secs(GRACE_PERIOD_DAYS * 24 * 60 * 60)),
+        ))
+        .execute(db)?;
+
+    Ok(())
+}
+
+pub fn subscription_suspended(db: &PgConnection, user: &User) -> QueryResult<()> {
+    diesel::update(users.find(user.id))
+        .set((
+            subscription_status.eq("SUSPENDED"),
+            subscribed.eq(false),
+        ))
+        .execute(db)?;
+
+    Ok(())
+}
+
+pub fn subscription_canceled(db: &PgConnection, user: &User) -> QueryResult<()> {
+    diesel::update(users.find(user.id))
+        .set((
+            subscription_status.eq("CANCELED"),
+            subscribed.eq(false),
+            cancel_at_period_end.eq(false),
+            grace_period_ends.eq(None::<SystemTime>),
+        ))
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Our status for a Stripe subscription status
+fn status_from_stripe(stripe_status: &str) -> &'static str {
+    match stripe_status {
+        "active" | "trialing" => "ACTIVE",
+        "past_due" => "PAST_DUE",
+        "incomplete" => "INCOMPLETE",
+        "unpaid" => "SUSPENDED",
+        _ => "CANCELED"
+    }
+}
+
+// Subscribers from before the subscription lifecycle were marked ACTIVE without a
+// stripe_subscription. Stores the subscription Stripe has for them, see
+// PaymentsProvider::get_customer_subscription, along with its status, or cancels it if they
+// don't have one any more.
+pub fn backfill_subscription(db: &PgConnection, user: &User, subscription: Option<&Subscription>) -> QueryResult<()> {
+    let subscription = match subscription {
+        Some(v) => v,
+        None => { return subscription_canceled(db, user); }
+    };
+
+    let status = status_from_stripe(&subscription.status);
+
+    diesel::update(users.find(user.id))
+        .set((
+            stripe_subscription.eq(&subscription.id),
+            subscription_status.eq(status),
+            subscribed.eq(status == "ACTIVE" || status == "PAST_DUE"),
+            cancel_at_period_end.eq(subscription.cancel_at_period_end),
+        ))
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Subscribers who need backfill_subscription, oldest first
+fn get_users_without_subscription(db: &PgConnection) -> QueryResult<Vec<User>> {
+    users
+        .filter(subscription_status.eq_any(vec!["ACTIVE", "PAST_DUE"]))
+        .filter(stripe_subscription.is_null())
+        .filter(stripe_customer.ne(""))
+        .order_by(crate::schema::users::id.asc())
+        .load::<User>(db)
+}
+
+// Backfills every subscriber who needs it, for `cargo run -- backfill-subscriptions`. Subscribers
+// Stripe couldn't be asked about are left for the next run.
+pub async fn backfill_subscriptions(db: &PgConnection) -> QueryResult<()> {
+    for user in get_users_without_subscription(db)? {
+        match payments().get_customer_subscription(&user.stripe_customer).await {
+            Ok(subscription) => {
+                backfill_subscription(db, &user, subscription.as_ref())?;
+
+                match subscription {
+                    Some(subscription) => println!("User {}: {} ({})", user.id, subscription.id, subscription.status),
+                    None => println!("User {}: no subscription, cancelled", user.id)
+                }
+            }
+            Err(e) => println!("Couldn't look up the subscription for user {}: {}", user.id, e)
+        }
+    }
+
+    Ok(())
+}
+
+pub fn set_cancel_at_period_end(db: &PgConnection, user: &User, cancel: bool) -> QueryResult<()> {
+    diesel::update(users.find(user.id))
+        .set(cancel_at_period_end.eq(cancel))
+        .execute(db)?;
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::NewVideoTag;
+use crate::schema::tags::dsl::tags;
+use crate::schema::videos_tags::dsl::videos_tags;
+
+pub const MAX_VIDEO_TAGS: usize = 10;
+
+// Checks a list of tag IDs supplied for a video. Every video needs at least one tag,
+// no more than MAX_VIDEO_TAGS and every tag has to exist.
+pub fn validate_video_tags(db: &PgConnection, tag_ids: &Vec<i32>) -> Result<(), String> {
+    if tag_ids.len() == 0 {
+        return Err(String::from("A video must have at least one tag"));
+    }
+
+    if tag_ids.len() > MAX_VIDEO_TAGS {
+        return Err(format!("A video can't have more than {} tags", MAX_VIDEO_TAGS));
+    }
+
+    let mut unique_ids = tag_ids.clone();
+    unique_ids.sort();
+    unique_ids.dedup();
+
+    if unique_ids.len() != tag_ids.len() {
+        return Err(String::from("Duplicate tags supplied"));
+    }
+
+    let found: i64 = tags
+        .filter(crate::schema::tags::id.eq_any(&unique_ids))
+        .count()
+        .get_result(db)
+        .expect("Query failed");
+
+    if found as usize != unique_ids.len() {
+        return Err(String::from("One or more tags do not exist"));
+    }
+
+    Ok(())
+}
+
+// Replaces the tags on a video. The caller is expected to have validated the tags first.
+pub fn set_video_tags(db: &PgConnection, target_video_id: i32, tag_ids: &Vec<i32>) -> QueryResult<()> {
+    db.transaction(|| {
+        diesel::delete(videos_tags.filter(crate::schema::videos_tags::video_id.eq(target_video_id)))
+            .execute(db)?;
+
+        let new_video_tags: Vec<NewVideoTag> = tag_ids.iter().map(|tag| {
+            NewVideoTag {
+                video_id: &target_video_id,
+                tag_id: tag,
+            }
+        }).collect();
+
+        diesel::insert_into(videos_tags)
+            .values(&new_video_tags)
+            .execute(db)?;
+
+        Ok(())
+    })
+}
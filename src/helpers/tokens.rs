@@ -1,19 +1,23 @@
 use std::time::SystemTime;
 
-use diesel::{BoolExpressionMethods, ExpressionMethods, JoinOnDsl, QueryDsl, QueryResult, RunQueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, JoinOnDsl, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use serde_json::json;
 
 use crate::establish_connection;
+use crate::helpers::ledger::get_channel_balance;
+use crate::helpers::notifications::{notify_batched, TOKEN_RECEIVED};
 use crate::helpers::users::get_user_by_id;
-use crate::models::{ChannelTokenWithUser, NewChannelToken, NewToken, Token, TokenTransaction, get_safe_user_fields};
+use crate::helpers::economics::{get_channel_token_expiry, get_current_economics};
+use crate::models::{ChannelTokenWithUser, NewChannelToken, NewToken, NewTokenGrant, Token, TokenGrant, TokenTransaction, get_safe_user_fields};
 use crate::schema::channels_tokens::dsl::channels_tokens;
+use crate::schema::token_grants::dsl::token_grants;
 use crate::schema::token_transactions::dsl::token_transactions;
-use crate::schema::tokens::columns::date_used;
+use crate::schema::tokens::columns::{date_expired, date_used};
 use crate::schema::tokens::dsl::{tokens, user_id};
 use crate::schema::users::dsl::users;
 use crate::diesel::GroupByDsl;
 
-pub fn user_has_active_token(source_user_id: i32, target_channel_user_id: i32) -> bool {
-    let db = establish_connection();
+pub fn user_has_active_token(db: &PgConnection, source_user_id: i32, target_channel_user_id: i32) -> bool {
     let result: QueryResult<ChannelTokenWithUser> = channels_tokens
         .inner_join(tokens)
         .inner_join(users.on(crate::schema::users::id.eq(crate::schema::tokens::user_id)))
@@ -31,7 +35,7 @@ pub fn user_has_active_token(source_user_id: i32, target_channel_user_id: i32) -
             .and(crate::schema::channels_tokens::expires.ge(std::time::SystemTime::now()))
         )
         .group_by((crate::schema::channels_tokens::id, crate::schema::users::id))
-        .first::<ChannelTokenWithUser>(&db);
+        .first::<ChannelTokenWithUser>(db);
 
     return match result {
         Ok(_) => {
@@ -43,86 +47,126 @@ pub fn user_has_active_token(source_user_id: i32, target_channel_user_id: i32) -
     };
 }
 
-pub fn add_tokens(target_user_id: i32, amount: u32) {
-    let db = establish_connection();
-
-    for _ in 0..amount {
-        let new_token = NewToken {
-            user_id: target_user_id
+// Grants the user their tokens for the period. Returns false without granting anything if
+// they have already been granted tokens for it. The rollover policy is applied to them once the
+// period ends, see helpers/expiry.rs.
+pub fn grant_tokens(db: &PgConnection, target_user_id: i32, grant_period: &str, amount: i32, grant_period_end: SystemTime) -> QueryResult<bool> {
+    db.transaction(|| {
+        let grant: Option<TokenGrant> = diesel::insert_into(token_grants)
+            .values(NewTokenGrant {
+                user_id: target_user_id,
+                period: grant_period,
+                token_count: amount,
+                period_end: Some(grant_period_end),
+            })
+            .on_conflict_do_nothing()
+            .get_result(db)
+            .optional()?;
+
+        let grant = match grant {
+            Some(v) => v,
+            None => { return Ok(false); }
         };
 
+        let new_tokens: Vec<NewToken> = (0..amount).map(|_| {
+            NewToken {
+                user_id: target_user_id,
+                grant_id: Some(grant.id),
+            }
+        }).collect();
+
         diesel::insert_into(tokens)
-            .values(&new_token)
-            .execute(&db)
-            .expect("Failed to add token");
-    }
+            .values(&new_tokens)
+            .execute(db)?;
+
+        Ok(true)
+    })
 }
 
-// Returns Result of date_used
-pub fn transfer_token(source_user_id: i32, channel_id: i32) -> Result<std::time::SystemTime, String> {
-    let db = establish_connection();
+// Locks the subscriber until the end of the transaction. Transfers and renewals for them take it
+// first, so they can't both find the same unused token or both find a channel unsupported.
+pub fn lock_token_holder(db: &PgConnection, subscriber: i32) -> QueryResult<()> {
+    users
+        .find(subscriber)
+        .select(crate::schema::users::id)
+        .for_update()
+        .first::<i32>(db)?;
+
+    Ok(())
+}
+
+// Returns date_used, or why the token couldn't be given. Takes the connection so auto renewals
+// can give tokens in the same transaction they were granted in, which is why query errors are
+// returned instead of panicking. Must be called in a transaction.
+pub fn transfer_token(db: &PgConnection, source_user_id: i32, channel_id: i32) -> QueryResult<Result<SystemTime, String>> {
+    lock_token_holder(db, source_user_id)?;
+
     let result: Vec<Token> = tokens
-        .filter(user_id.eq(&source_user_id).and(date_used.is_null()))
-        .load::<Token>(&db)
-        .expect("Query failed");
+        .filter(user_id.eq(&source_user_id).and(date_used.is_null()).and(date_expired.is_null()))
+        .order_by(crate::schema::tokens::id.asc())
+        .for_update()
+        .load::<Token>(db)?;
 
     if result.len() == 0 {
-        return Err(String::from("You do not have any tokens left"));
+        return Ok(Err(String::from("You do not have any tokens left")));
     }
 
-    let channel = get_user_by_id(&db, channel_id);
+    let channel = get_user_by_id(db, channel_id);
     let channel = match channel {
         Some(v) => v,
-        None => { return Err(String::from("Channel does not exist")); }
+        None => { return Ok(Err(String::from("Channel does not exist"))); }
     };
 
     if channel.user_type != "CHANNEL" {
-        return Err(String::from("Target is not a channel"));
+        return Ok(Err(String::from("Target is not a channel")));
     }
 
     // CHeck if the user already has an active token
-    let active = user_has_active_token(source_user_id, channel_id);
+    let active = user_has_active_token(db, source_user_id, channel_id);
 
     if active {
-        return Err(String::from("You already have a token"));
+        return Ok(Err(String::from("You already have a token")));
     }
 
     let token = result.get(0).unwrap();
 
+    let economics = get_current_economics(db);
+
     let new_channel_token = NewChannelToken {
         token_id: token.id,
         channel_user_id: channel_id,
+        expires: get_channel_token_expiry(&economics),
     };
 
     diesel::insert_into(channels_tokens)
         .values(&new_channel_token)
-        .execute(&db)
-        .expect("Couldn't transfer token");
+        .execute(db)?;
 
     let new_date_used = SystemTime::now();
 
     diesel::update(tokens.find(token.id))
         .set(date_used.eq(&new_date_used))
-        .execute(&db)
-        .expect("Couldn't mark token as used");
+        .execute(db)?;
+
+    // Batched, since auto renewals give a channel many tokens at the start of the month
+    let supporter = get_user_by_id(db, source_user_id).unwrap();
+
+    notify_batched(db, channel_id, TOKEN_RECEIVED, TOKEN_RECEIVED, supporter.id, json!({
+        "user_id": supporter.id,
+        "username": supporter.username,
+        "expires": new_channel_token.expires,
+    }))?;
 
-    return Ok(new_date_used);
+    Ok(Ok(new_date_used))
 }
 
+// The channel's withdrawable balance in the current currency, from the ledger
 pub fn get_user_balance(target_user_id: i32) -> i32 {
-    let result = get_user_transactions(target_user_id);
-
-    let mut balance = 0;
+    let db = establish_connection();
 
-    for transaction in result {
-        if transaction.transaction_type == "DEPOSIT" {
-            balance += transaction.amount;
-        } else if transaction.transaction_type == "WITHDRAWAL" {
-            balance -= transaction.amount;
-        }
-    }
+    let economics = get_current_economics(&db);
 
-    return balance;
+    get_channel_balance(&db, target_user_id, &economics.currency) as i32
 }
 
 pub fn get_user_transactions(target_user_id: i32) -> Vec<TokenTransaction> {
@@ -1,8 +1,10 @@
 use diesel::{ExpressionMethods, PgConnection, QueryDsl};
 
 use crate::diesel::RunQueryDsl;
+use crate::helpers::payments::payments;
+use crate::helpers::subscriptions::subscription_created;
 use crate::models::User;
-use crate::schema::users::columns::id;
+use crate::schema::users::columns::{id, stripe_account, stripe_customer};
 use crate::schema::users::dsl::users;
 
 pub fn get_user_by_id(db: &PgConnection, target_user_id: i32) -> Option<User> {
@@ -14,4 +16,59 @@ pub fn get_user_by_id(db: &PgConnection, target_user_id: i32) -> Option<User> {
     let result = result.into_iter().nth(0);
 
     return result;
-}
\ No newline at end of file
+}
+// Only for undoing a registration which couldn't be finished
+pub fn delete_user(db: &PgConnection, target_user_id: i32) {
+    diesel::delete(users.find(target_user_id))
+        .execute(db)
+        .expect("Couldn't delete user");
+}
+
+// Creates the Stripe customer and subscription for a new subscriber. Tokens are granted when the
+// invoice.paid webhook for the first invoice comes in. The error is the message for the user.
+pub async fn set_up_subscriber_billing(db: &PgConnection, target_user_id: i32, target_email: &str, payment_method_id: &str, price_id: &str) -> Result<(), String> {
+    let customer = match payments().create_customer(target_email, payment_method_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Couldn't create customer: {}", e);
+            return Err(String::from("Couldn't set up your payment details. Please try again."));
+        }
+    };
+
+    diesel::update(users.find(target_user_id))
+        .set(stripe_customer.eq(&customer.id))
+        .execute(db)
+        .expect("Couldn't add stripe_customer to user");
+
+    let subscription = match payments().create_subscription(&customer.id, price_id, Some(payment_method_id)).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Couldn't create subscription: {}", e);
+            return Err(String::from("Couldn't start your subscription. Please check your payment details."));
+        }
+    };
+
+    subscription_created(db, target_user_id, &subscription.id)
+        .expect("Couldn't add stripe_subscription to user");
+
+    Ok(())
+}
+
+// Creates the Stripe account a new channel is paid into. Returns its ID, the error is the
+// message for the user.
+pub async fn set_up_channel_account(db: &PgConnection, target_user_id: i32, target_email: &str) -> Result<String, String> {
+    let account = match payments().create_account(target_email, "GB").await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Couldn't create account: {}", e);
+            return Err(String::from("Couldn't set up payouts for your channel. Please try again."));
+        }
+    };
+
+    diesel::update(users.find(target_user_id))
+        .set(stripe_account.eq(&account.id))
+        .execute(db)
+        .expect("Couldn't add stripe_account to user");
+
+    Ok(account.id)
+}
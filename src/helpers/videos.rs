@@ -0,0 +1,95 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, JoinOnDsl, PgConnection, QueryDsl};
+use diesel::dsl::exists;
+use diesel::sql_types::{Integer, Record, VarChar};
+
+use crate::diesel::GroupByDsl;
+use crate::diesel::RunQueryDsl;
+use crate::models::{get_safe_user_fields, VideoWithUser};
+use crate::schema::tags::dsl::tags;
+use crate::schema::users::dsl::users;
+use crate::schema::video_upvotes::dsl::{upvote_type, video_id, video_upvotes};
+use crate::schema::videos::columns::{id, status};
+use crate::schema::videos::dsl::videos;
+use crate::schema::videos_tags::dsl::videos_tags;
+
+// Length of a video file in whole seconds, read with ffprobe. None if ffprobe isn't installed or
+// can't read the file, in which case the processing step has to set it.
+pub fn probe_duration(path: &str) -> Option<i32> {
+    let output = std::process::Command::new("ffprobe")
+        .args(&["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1", path])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|d| d.round() as i32)
+}
+
+// Loads the READY videos with the given IDs as seen by the viewer. The result keeps the
+// order of video_ids, so callers can rank videos however they like before loading them.
+pub fn get_videos_by_ids(db: &PgConnection, viewer: i32, video_ids: &Vec<i32>) -> Vec<VideoWithUser> {
+    if video_ids.len() == 0 {
+        return vec![];
+    }
+
+    let result: Vec<VideoWithUser> = videos
+        .filter(id.eq_any(video_ids).and(status.eq("READY")))
+        .inner_join(users)
+        .left_join(videos_tags.on(crate::schema::videos_tags::video_id.eq(crate::schema::videos::id)))
+        .inner_join(tags.on(crate::schema::videos_tags::tag_id.eq(crate::schema::tags::id)))
+        .select(
+            (
+                crate::schema::videos::id,
+                crate::schema::videos::file_name,
+                get_safe_user_fields(),
+                crate::schema::videos::title,
+                crate::schema::videos::description,
+                crate::schema::videos::upload_date,
+                exists(video_upvotes.
+                    filter(video_id.eq(id)
+                        .and(upvote_type.eq("UP"))
+                        .and(crate::schema::video_upvotes::inactive.eq(false))
+                        .and(crate::schema::video_upvotes::user_id.eq(viewer))
+                    )
+                ),
+                exists(video_upvotes
+                    .filter(video_id.eq(id)
+                        .and(upvote_type.eq("DOWN"))
+                        .and(crate::schema::video_upvotes::inactive.eq(false))
+                        .and(crate::schema::video_upvotes::user_id.eq(viewer))
+                    )
+                ),
+                crate::schema::videos::upvote_count,
+                crate::schema::videos::downvote_count,
+                crate::schema::videos::play_count,
+                diesel::dsl::sql::<diesel::sql_types::Array<Record<(Integer, VarChar)>>>("array_agg(\"tags\".*) as tags")
+            )
+        )
+        .group_by((crate::schema::videos::id, crate::schema::users::id))
+        .load(db)
+        .expect("Query failed.");
+
+    let mut ordered: Vec<VideoWithUser> = vec![];
+    let mut result: Vec<Option<VideoWithUser>> = result.into_iter().map(Some).collect();
+
+    for target in video_ids {
+        let position = result.iter().position(|video| match video {
+            Some(v) => v.id == *target,
+            None => false
+        });
+
+        if let Some(position) = position {
+            if let Some(video) = result[position].take() {
+                ordered.push(video);
+            }
+        }
+    }
+
+    ordered
+}
@@ -0,0 +1,579 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use hmac::{Hmac, Mac, NewMac};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::fake_payments::FakeProvider;
+use crate::helpers::ledger::{CHANNEL_EARNINGS, get_or_create_account, PAYOUTS_PAID, post_transaction};
+use crate::helpers::subscriptions::{get_user_by_customer, is_current_subscription, set_cancel_at_period_end, subscription_canceled, subscription_paid, subscription_payment_failed, subscription_suspended};
+use crate::helpers::withdrawals::{find_withdrawal_for_transfer, mark_withdrawal_paid, mark_withdrawal_reversed};
+use crate::models::{NewStripeEvent, NewTokenTransaction, StripeEvent, TokenTransaction};
+use crate::schema::stripe_events::dsl::{error, processed_at, stripe_events};
+use crate::schema::token_transactions::dsl::{stripe_transfer_id, token_transactions, transaction_type};
+use crate::schema::users::dsl::{channel_onboarded, stripe_account, users};
+
+/*
+ * Stripe webhooks. Events are verified with the Stripe-Signature header, stored in
+ * stripe_events and handled once, inside the same transaction that marks them as processed.
+ * See https://stripe.com/docs/webhooks/signatures
+ */
+
+// How far the signature timestamp may be from now, to limit replays
+pub const SIGNATURE_TOLERANCE_SECONDS: u64 = 300;
+
+fn signer(payload: &[u8], secret: &str, timestamp: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    mac
+}
+
+// Builds a Stripe-Signature header for the payload. Used to send signed fixtures to a local server.
+pub fn sign_payload(payload: &[u8], secret: &str, timestamp: u64) -> String {
+    let signature = signer(payload, secret, timestamp).finalize().into_bytes();
+
+    format!("t={},v1={}", timestamp, hex::encode(signature))
+}
+
+pub fn verify_signature(payload: &[u8], header: &str, secret: &str, now: u64) -> Result<(), String> {
+    let mut timestamp: Option<u64> = None;
+    let mut signatures: Vec<Vec<u8>> = Vec::new();
+
+    for part in header.split(',') {
+        let mut pair = part.trim().splitn(2, '=');
+
+        match (pair.next(), pair.next()) {
+            (Some("t"), Some(v)) => timestamp = v.parse::<u64>().ok(),
+            (Some("v1"), Some(v)) => {
+                if let Ok(signature) = hex::decode(v) {
+                    signatures.push(signature);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let timestamp = match timestamp {
+        Some(v) => v,
+        None => { return Err(String::from("Signature has no timestamp")); }
+    };
+
+    if now > timestamp + SIGNATURE_TOLERANCE_SECONDS || timestamp > now + SIGNATURE_TOLERANCE_SECONDS {
+        return Err(String::from("Signature timestamp is outside the tolerance"));
+    }
+
+    let mac = signer(payload, secret, timestamp);
+
+    // verify compares in constant time
+    if signatures.iter().any(|signature| mac.clone().verify(signature).is_ok()) {
+        Ok(())
+    } else {
+        Err(String::from("No matching signature"))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WebhookEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub data: WebhookEventData,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookEventData {
+    pub object: serde_json::Value,
+}
+
+pub enum WebhookError {
+    Query(diesel::result::Error),
+    InvalidObject(String),
+}
+
+impl From<diesel::result::Error> for WebhookError {
+    fn from(e: diesel::result::Error) -> Self {
+        WebhookError::Query(e)
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Query(e) => write!(f, "Query failed: {}", e),
+            WebhookError::InvalidObject(e) => write!(f, "Invalid event object: {}", e),
+        }
+    }
+}
+
+fn parse_object<T: DeserializeOwned>(event: &WebhookEvent) -> Result<T, WebhookError> {
+    serde_json::from_value(event.data.object.clone())
+        .map_err(|e| WebhookError::InvalidObject(e.to_string()))
+}
+
+// Stores and handles the event. Returns false if it had already been processed.
+pub fn process_event(db: &PgConnection, event: &WebhookEvent, payload: &str) -> Result<bool, WebhookError> {
+    db.transaction(|| {
+        let inserted = diesel::insert_into(stripe_events)
+            .values(NewStripeEvent {
+                id: &event.id,
+                event_type: &event.event_type,
+                payload,
+            })
+            .on_conflict_do_nothing()
+            .execute(db)?;
+
+        if inserted == 0 {
+            // Waits for a concurrent delivery of the same event to finish
+            let existing: StripeEvent = stripe_events
+                .find(&event.id)
+                .for_update()
+                .first(db)?;
+
+            if existing.processed_at.is_some() {
+                return Ok(false);
+            }
+        }
+
+        handle_event(db, event)?;
+
+        diesel::update(stripe_events.find(&event.id))
+            .set((processed_at.eq(SystemTime::now()), error.eq(None::<String>)))
+            .execute(db)?;
+
+        Ok(true)
+    })
+}
+
+// Keeps the reason a handler failed. Stripe retries the event later.
+pub fn record_event_error(db: &PgConnection, event: &WebhookEvent, payload: &str, message: &str) -> QueryResult<()> {
+    diesel::insert_into(stripe_events)
+        .values(NewStripeEvent {
+            id: &event.id,
+            event_type: &event.event_type,
+            payload,
+        })
+        .on_conflict_do_nothing()
+        .execute(db)?;
+
+    diesel::update(stripe_events.find(&event.id))
+        .set(error.eq(message))
+        .execute(db)?;
+
+    Ok(())
+}
+
+// Handles a stored event which hasn't been processed yet, e.g. after Stripe stopped retrying it
+pub fn reprocess_stored_event(db: &PgConnection, event_id: &str) -> Result<bool, WebhookError> {
+    let stored: StripeEvent = stripe_events.find(event_id).first(db)?;
+
+    let event: WebhookEvent = serde_json::from_str(&stored.payload)
+        .map_err(|e| WebhookError::InvalidObject(e.to_string()))?;
+
+    process_event(db, &event, &stored.payload)
+}
+
+// Handles the events queued by the fake payments provider, as if Stripe had sent them. Returns
+// the events which failed.
+pub fn process_fake_events(db: &PgConnection, fake: &FakeProvider) -> Vec<String> {
+    let mut errors: Vec<String> = vec![];
+
+    for payload in fake.take_events() {
+        let event: WebhookEvent = serde_json::from_str(&payload).expect("Fake event is invalid");
+
+        if let Err(e) = process_event(db, &event, &payload) {
+            if let Err(record_error) = record_event_error(db, &event, &payload, &e.to_string()) {
+                println!("Couldn't record the error for Stripe event {}: {}", event.id, record_error);
+            }
+
+            errors.push(format!("{}: {}", event.id, e));
+        }
+    }
+
+    errors
+}
+
+fn handle_event(db: &PgConnection, event: &WebhookEvent) -> Result<(), WebhookError> {
+    match event.event_type.as_str() {
+        "account.updated" => account_updated(db, parse_object(event)?)?,
+        "invoice.paid" => invoice_paid(db, parse_object(event)?)?,
+        "invoice.payment_failed" => invoice_payment_failed(db, parse_object(event)?)?,
+        "customer.subscription.updated" => subscription_updated(db, parse_object(event)?)?,
+        "customer.subscription.deleted" => subscription_deleted(db, parse_object(event)?)?,
+        "transfer.created" => transfer_created(db, parse_object(event)?)?,
+        "transfer.reversed" => transfer_reversed(db, parse_object(event)?)?,
+        // Stored, but nothing to do
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct AccountObject {
+    id: String,
+    payouts_enabled: Option<bool>,
+}
+
+// A channel finished (or lost) onboarding
+fn account_updated(db: &PgConnection, account: AccountObject) -> QueryResult<()> {
+    diesel::update(users.filter(stripe_account.eq(&account.id)))
+        .set(channel_onboarded.eq(account.payouts_enabled.unwrap_or(false)))
+        .execute(db)?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct InvoiceObject {
+    customer: Option<String>,
+    subscription: Option<String>,
+    lines: InvoiceLines,
+}
+
+#[derive(Deserialize)]
+struct InvoiceLines {
+    data: Vec<InvoiceLine>,
+}
+
+#[derive(Deserialize)]
+struct InvoiceLine {
+    #[serde(rename = "type")]
+    line_type: String,
+    period: InvoiceLinePeriod,
+    price: Option<InvoiceLinePrice>,
+}
+
+#[derive(Deserialize)]
+struct InvoiceLinePrice {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct InvoiceLinePeriod {
+    start: i64,
+    end: i64,
+}
+
+fn invoice_paid(db: &PgConnection, invoice: InvoiceObject) -> QueryResult<()> {
+    let (customer, subscription) = match (invoice.customer, invoice.subscription) {
+        (Some(c), Some(s)) => (c, s),
+        // Not a subscription invoice
+        _ => { return Ok(()); }
+    };
+
+    let user = match get_user_by_customer(db, &customer)? {
+        Some(v) => v,
+        None => {
+            println!("Invoice paid for unknown customer {}", customer);
+            return Ok(());
+        }
+    };
+
+    if !is_current_subscription(&user, &subscription) {
+        println!("Invoice paid for subscription {} which user {} no longer has", subscription, user.id);
+        return Ok(());
+    }
+
+    // The billing period and price are on the subscription line, not the invoice
+    let line = match invoice.lines.data.iter().find(|line| line.line_type == "subscription") {
+        Some(v) => v,
+        None => { return Ok(()); }
+    };
+
+    let price_id = line.price.as_ref().map(|price| price.id.as_str());
+
+    subscription_paid(db, &user, &subscription, price_id, line.period.start, UNIX_EPOCH + Duration::from_secs(line.period.end as u64))
+}
+
+fn invoice_payment_failed(db: &PgConnection, invoice: InvoiceObject) -> QueryResult<()> {
+    let (customer, subscription) = match (invoice.customer, invoice.subscription) {
+        (Some(c), Some(s)) => (c, s),
+        _ => { return Ok(()); }
+    };
+
+    match get_user_by_customer(db, &customer)? {
+        Some(user) if is_current_subscription(&user, &subscription) => subscription_payment_failed(db, &user),
+        _ => Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct SubscriptionObject {
+    id: String,
+    customer: String,
+    status: String,
+    cancel_at_period_end: bool,
+}
+
+// Keeps cancel_at_period_end in step, e.g. when a subscription is cancelled from the Stripe
+// dashboard. Payments are handled by the invoice events.
+fn subscription_updated(db: &PgConnection, subscription: SubscriptionObject) -> QueryResult<()> {
+    let user = match get_user_by_customer(db, &subscription.customer)? {
+        Some(v) if is_current_subscription(&v, &subscription.id) => v,
+        _ => { return Ok(()); }
+    };
+
+    set_cancel_at_period_end(db, &user, subscription.cancel_at_period_end)?;
+
+    match subscription.status.as_str() {
+        "unpaid" => subscription_suspended(db, &user),
+        // A first invoice which was never paid
+        "canceled" | "incomplete_expired" => subscription_canceled(db, &user),
+        _ => Ok(())
+    }
+}
+
+fn subscription_deleted(db: &PgConnection, subscription: SubscriptionObject) -> QueryResult<()> {
+    match get_user_by_customer(db, &subscription.customer)? {
+        Some(user) if is_current_subscription(&user, &subscription.id) => subscription_canceled(db, &user),
+        _ => Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct TransferObject {
+    id: String,
+    // Total reversed so far, across every reversal of the transfer
+    amount_reversed: i64,
+    currency: String,
+    transfer_group: Option<String>,
+}
+
+// Confirms the transfer for a withdrawal request
+fn transfer_created(db: &PgConnection, transfer: TransferObject) -> QueryResult<()> {
+    match find_withdrawal_for_transfer(db, &transfer.id, transfer.transfer_group.as_deref())? {
+        Some(request) => {
+            mark_withdrawal_paid(db, request.id, &transfer.id)?;
+        }
+        None => println!("Transfer {} was created but isn't for a withdrawal", transfer.id)
+    }
+
+    Ok(())
+}
+
+// Money from a withdrawal came back from the channel's Stripe account, so it goes back into
+// their earnings. Only the part which hasn't been returned already is posted.
+fn transfer_reversed(db: &PgConnection, transfer: TransferObject) -> QueryResult<()> {
+    let request = find_withdrawal_for_transfer(db, &transfer.id, transfer.transfer_group.as_deref())?;
+
+    // The reversal can get here before transfer.created
+    if let Some(request) = &request {
+        mark_withdrawal_paid(db, request.id, &transfer.id)?;
+    }
+
+    let withdrawal: Option<TokenTransaction> = token_transactions
+        .filter(stripe_transfer_id.eq(&transfer.id).and(transaction_type.eq("WITHDRAWAL")))
+        .first::<TokenTransaction>(db)
+        .optional()?;
+
+    let withdrawal = match withdrawal {
+        Some(v) => v,
+        None => {
+            println!("Transfer {} was reversed but isn't a withdrawal", transfer.id);
+            return Ok(());
+        }
+    };
+
+    let already_reversed: i64 = token_transactions
+        .filter(stripe_transfer_id.eq(&transfer.id).and(transaction_type.eq("REVERSAL")))
+        .select(diesel::dsl::sql::<diesel::sql_types::BigInt>("coalesce(sum(amount), 0)"))
+        .first::<i64>(db)?;
+
+    let amount = transfer.amount_reversed.min(withdrawal.amount as i64) - already_reversed;
+
+    if amount > 0 {
+        let paid = get_or_create_account(db, PAYOUTS_PAID, None, &transfer.currency)?;
+        let earnings = get_or_create_account(db, CHANNEL_EARNINGS, Some(withdrawal.channel_user_id), &transfer.currency)?;
+
+        let ledger_transaction = post_transaction(db, "WITHDRAWAL_REVERSED", &[
+            (paid.id, -amount),
+            (earnings.id, amount),
+        ])?;
+
+        diesel::insert_into(token_transactions)
+            .values(NewTokenTransaction {
+                channel_user_id: withdrawal.channel_user_id,
+                transaction_type: "REVERSAL".to_string(),
+                amount: amount as i32,
+                economics_id: withdrawal.economics_id,
+                token_count: None,
+                period: None,
+                ledger_transaction_id: Some(ledger_transaction.id),
+                stripe_transfer_id: Some(transfer.id.clone()),
+            })
+            .execute(db)?;
+    }
+
+    // Withdrawals made before withdrawal requests existed have no request
+    if let Some(request) = request {
+        if transfer.amount_reversed >= withdrawal.amount as i64 {
+            mark_withdrawal_reversed(db, request.id)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{ExpressionMethods, QueryDsl};
+
+    use crate::diesel::RunQueryDsl;
+    use crate::schema::stripe_events::dsl::{error, stripe_events};
+    use crate::helpers::subscriptions::subscription_created;
+    use crate::helpers::users::get_user_by_id;
+    use crate::schema::users::dsl::{channel_onboarded, stripe_subscription, users};
+    use crate::test_helpers::with_fixture;
+
+    use super::{process_event, record_event_error, sign_payload, SIGNATURE_TOLERANCE_SECONDS, verify_signature, WebhookEvent};
+
+    const SECRET: &str = "whsec_fixture";
+    const NOW: u64 = 1624099200;
+    const PAYLOAD: &str = include_str!("../../fixtures/stripe/account.updated.json");
+
+    fn fixture_event() -> WebhookEvent {
+        serde_json::from_str(PAYLOAD).unwrap()
+    }
+
+    fn process_fixture(db: &diesel::PgConnection, payload: &str) {
+        let event: WebhookEvent = serde_json::from_str(payload).unwrap();
+
+        assert!(matches!(process_event(db, &event, payload), Ok(true)));
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let header = sign_payload(PAYLOAD.as_bytes(), SECRET, NOW);
+
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW), Ok(()));
+    }
+
+    #[test]
+    fn accepts_any_matching_signature() {
+        // Stripe sends one per secret while a secret is being rolled
+        let header = format!("{},v1={}", sign_payload(PAYLOAD.as_bytes(), SECRET, NOW), "00".repeat(32));
+
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_the_wrong_secret() {
+        let header = sign_payload(PAYLOAD.as_bytes(), "whsec_other", NOW);
+
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW), Err(String::from("No matching signature")));
+    }
+
+    #[test]
+    fn rejects_a_changed_payload() {
+        let header = sign_payload(PAYLOAD.as_bytes(), SECRET, NOW);
+        let changed = PAYLOAD.replace("acct_fixture", "acct_other");
+
+        assert!(verify_signature(changed.as_bytes(), &header, SECRET, NOW).is_err());
+    }
+
+    #[test]
+    fn checks_the_timestamp_tolerance() {
+        let header = sign_payload(PAYLOAD.as_bytes(), SECRET, NOW);
+        let outside = Err(String::from("Signature timestamp is outside the tolerance"));
+
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW + SIGNATURE_TOLERANCE_SECONDS), Ok(()));
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW - SIGNATURE_TOLERANCE_SECONDS), Ok(()));
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW + SIGNATURE_TOLERANCE_SECONDS + 1), outside);
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), &header, SECRET, NOW - SIGNATURE_TOLERANCE_SECONDS - 1), outside);
+    }
+
+    #[test]
+    fn rejects_a_signature_without_a_timestamp() {
+        let header = sign_payload(PAYLOAD.as_bytes(), SECRET, NOW);
+        let without_timestamp = header.splitn(2, ',').nth(1).unwrap();
+
+        assert_eq!(verify_signature(PAYLOAD.as_bytes(), without_timestamp, SECRET, NOW), Err(String::from("Signature has no timestamp")));
+    }
+
+    #[test]
+    fn processes_an_event_once() {
+        with_fixture(include_str!("../../fixtures/stripe/events.sql"), |db| {
+            let event = fixture_event();
+
+            assert!(matches!(process_event(db, &event, PAYLOAD), Ok(true)));
+
+            let onboarded: bool = users.find(900001).select(channel_onboarded).first(db).unwrap();
+            assert!(onboarded);
+
+            // Stripe delivers events at least once
+            assert!(matches!(process_event(db, &event, PAYLOAD), Ok(false)));
+        });
+    }
+
+    #[test]
+    fn records_handler_errors() {
+        with_fixture(include_str!("../../fixtures/stripe/events.sql"), |db| {
+            let event = fixture_event();
+
+            record_event_error(db, &event, PAYLOAD, "Handler failed").unwrap();
+
+            let stored: Option<String> = stripe_events.find(&event.id).select(error).first(db).unwrap();
+            assert_eq!(stored.as_deref(), Some("Handler failed"));
+
+            // The stored event is still handled when Stripe retries it
+            assert!(matches!(process_event(db, &event, PAYLOAD), Ok(true)));
+        });
+    }
+
+    #[test]
+    fn ignores_events_for_a_replaced_subscription() {
+        with_fixture(include_str!("../../fixtures/stripe/events.sql"), |db| {
+            // Stripe can send events for the old subscription after the new one has started
+            process_fixture(db, include_str!("../../fixtures/stripe/customer.subscription.updated.json"));
+            process_fixture(db, include_str!("../../fixtures/stripe/customer.subscription.deleted.json"));
+            process_fixture(db, include_str!("../../fixtures/stripe/invoice.payment_failed.json"));
+
+            let subscriber = get_user_by_id(db, 900002).unwrap();
+            assert_eq!(subscriber.subscription_status, "ACTIVE");
+            assert!(subscriber.subscribed);
+            assert!(!subscriber.cancel_at_period_end);
+            assert_eq!(subscriber.stripe_subscription.as_deref(), Some("sub_resumed"));
+        });
+    }
+
+    #[test]
+    fn handles_events_for_the_current_subscription() {
+        with_fixture(include_str!("../../fixtures/stripe/events.sql"), |db| {
+            diesel::update(users.find(900002))
+                .set(stripe_subscription.eq("sub_fixture"))
+                .execute(db)
+                .unwrap();
+
+            process_fixture(db, include_str!("../../fixtures/stripe/customer.subscription.deleted.json"));
+
+            let subscriber = get_user_by_id(db, 900002).unwrap();
+            assert_eq!(subscriber.subscription_status, "CANCELED");
+            assert!(!subscriber.subscribed);
+        });
+    }
+
+    #[test]
+    fn keeps_a_first_invoice_paid_before_the_subscription_is_stored() {
+        with_fixture(include_str!("../../fixtures/stripe/events.sql"), |db| {
+            diesel::update(users.find(900002))
+                .set(stripe_subscription.eq(None::<String>))
+                .execute(db)
+                .unwrap();
+
+            // Stripe charges the first invoice while the subscription is being created
+            process_fixture(db, include_str!("../../fixtures/stripe/invoice.paid.json"));
+            subscription_created(db, 900002, "sub_fixture").unwrap();
+
+            let subscriber = get_user_by_id(db, 900002).unwrap();
+            assert_eq!(subscriber.subscription_status, "ACTIVE");
+            assert_eq!(subscriber.stripe_subscription.as_deref(), Some("sub_fixture"));
+        });
+    }
+}
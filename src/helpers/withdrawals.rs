@@ -0,0 +1,279 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
+use serde_json::json;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::economics::get_economics_at;
+use crate::helpers::ledger::{CHANNEL_EARNINGS, get_account_balance, get_or_create_account, lock_account, PAYOUTS_IN_FLIGHT, PAYOUTS_PAID, post_transaction};
+use crate::helpers::notifications::{notify, PAYOUT_FAILED, PAYOUT_PAID, PAYOUT_REVERSED};
+use crate::helpers::payments::{payments, PaymentsError};
+use crate::models::{NewTokenTransaction, NewWithdrawalRequest, WithdrawalRequest};
+use crate::schema::token_transactions::dsl::token_transactions;
+use crate::schema::withdrawal_requests::dsl::{channel_user_id, created, failure_reason, id, idempotency_key, status, stripe_transfer_id, updated, withdrawal_requests};
+
+/*
+ * Withdrawals are recorded before any money moves, so a retried request or a crash part way
+ * through can't pay a channel twice or lose track of a transfer.
+ *
+ *   REQUESTED  the amount is held in the channel's PAYOUTS_IN_FLIGHT account, no transfer yet
+ *   SUBMITTED  Stripe accepted the transfer
+ *   PAID       the transfer.created event confirmed it and the money left the ledger
+ *   FAILED     Stripe refused the transfer, the hold went back to the channel's earnings
+ *   REVERSED   the whole transfer came back, see transfer_reversed in helpers/webhooks.rs
+ *
+ * The transfer for a request always uses the same Stripe idempotency key, so submitting it
+ * again can only return the transfer made the first time.
+ */
+
+pub enum WithdrawalError {
+    Query(diesel::result::Error),
+    InsufficientBalance,
+    // The idempotency key was already used for a different withdrawal
+    KeyReused,
+}
+
+impl From<diesel::result::Error> for WithdrawalError {
+    fn from(e: diesel::result::Error) -> Self {
+        WithdrawalError::Query(e)
+    }
+}
+
+impl fmt::Display for WithdrawalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WithdrawalError::Query(e) => write!(f, "Query failed: {}", e),
+            WithdrawalError::InsufficientBalance => write!(f, "You are withdrawing more than your current balance."),
+            WithdrawalError::KeyReused => write!(f, "The idempotency key was already used for a different withdrawal."),
+        }
+    }
+}
+
+// Shows up on the transfer's webhook events, so they can be matched to the request even when
+// the transfer ID was never stored
+pub fn get_transfer_group(request_id: i32) -> String {
+    format!("withdrawal_{}", request_id)
+}
+
+fn parse_transfer_group(group: &str) -> Option<i32> {
+    group.strip_prefix("withdrawal_")?.parse::<i32>().ok()
+}
+
+// Records the withdrawal and holds the amount. Using the same key again gives back the request
+// made the first time instead of making another.
+pub fn request_withdrawal(db: &PgConnection, channel_id: i32, key: &str, amount: i32, currency: &str) -> Result<WithdrawalRequest, WithdrawalError> {
+    db.transaction(|| {
+        let inserted: Option<WithdrawalRequest> = diesel::insert_into(withdrawal_requests)
+            .values(NewWithdrawalRequest {
+                channel_user_id: channel_id,
+                idempotency_key: key,
+                amount,
+                currency,
+            })
+            .on_conflict_do_nothing()
+            .get_result(db)
+            .optional()?;
+
+        let request = match inserted {
+            Some(v) => v,
+            None => {
+                let existing: WithdrawalRequest = withdrawal_requests
+                    .filter(channel_user_id.eq(channel_id).and(idempotency_key.eq(key)))
+                    .first(db)?;
+
+                if existing.amount != amount || existing.currency != currency {
+                    return Err(WithdrawalError::KeyReused);
+                }
+
+                return Ok(existing);
+            }
+        };
+
+        let earnings = get_or_create_account(db, CHANNEL_EARNINGS, Some(channel_id), currency)?;
+        let in_flight = get_or_create_account(db, PAYOUTS_IN_FLIGHT, Some(channel_id), currency)?;
+
+        // The earnings account stays locked until the transaction commits, so two withdrawals
+        // can't both pass the balance check. Failing here rolls back the request as well.
+        lock_account(db, earnings.id)?;
+
+        if get_account_balance(db, earnings.id)? < amount as i64 {
+            return Err(WithdrawalError::InsufficientBalance);
+        }
+
+        post_transaction(db, "WITHDRAWAL", &[
+            (earnings.id, -amount as i64),
+            (in_flight.id, amount as i64),
+        ])?;
+
+        Ok(request)
+    })
+}
+
+// Sends the transfer for a REQUESTED withdrawal. A refused transfer fails the request. When
+// Stripe can't be reached the request stays REQUESTED and the "Reconcile withdrawals" job
+// submits it again later.
+pub async fn submit_withdrawal(db: &PgConnection, request: &WithdrawalRequest, account_id: &str) -> QueryResult<WithdrawalRequest> {
+    let transfer = payments().create_transfer(
+        account_id,
+        request.amount as i64,
+        &request.currency,
+        &get_transfer_group(request.id),
+        &format!("withdrawal-{}", request.id),
+    ).await;
+
+    match transfer {
+        Ok(transfer) => {
+            // Does nothing if the transfer.created event got here first
+            diesel::update(withdrawal_requests.filter(id.eq(request.id).and(status.eq("REQUESTED"))))
+                .set((
+                    status.eq("SUBMITTED"),
+                    stripe_transfer_id.eq(&transfer.id),
+                    updated.eq(SystemTime::now()),
+                ))
+                .execute(db)?;
+        }
+        Err(PaymentsError::Declined(e)) => {
+            fail_withdrawal(db, request.id, &e)?;
+        }
+        Err(PaymentsError::Unavailable(e)) => {
+            println!("Couldn't submit withdrawal {}: {}", request.id, e);
+        }
+    }
+
+    withdrawal_requests.find(request.id).first(db)
+}
+
+// Moves the request to the status if it is in one of the from statuses. Returns None if it
+// had already moved on.
+fn transition(db: &PgConnection, request_id: i32, from: &[&str], to: &str) -> QueryResult<Option<WithdrawalRequest>> {
+    diesel::update(withdrawal_requests.filter(id.eq(request_id).and(status.eq_any(from.to_vec()))))
+        .set((status.eq(to), updated.eq(SystemTime::now())))
+        .get_result(db)
+        .optional()
+}
+
+// The transfer went through, so the held money leaves the ledger. A REQUESTED withdrawal can be
+// paid too, when the transfer was made but the response never got back to us. Returns false
+// if the request had already been paid.
+pub fn mark_withdrawal_paid(db: &PgConnection, request_id: i32, transfer_id: &str) -> QueryResult<bool> {
+    db.transaction(|| {
+        let request = match transition(db, request_id, &["REQUESTED", "SUBMITTED"], "PAID")? {
+            Some(v) => v,
+            None => { return Ok(false); }
+        };
+
+        diesel::update(withdrawal_requests.find(request.id))
+            .set(stripe_transfer_id.eq(transfer_id))
+            .execute(db)?;
+
+        let in_flight = get_or_create_account(db, PAYOUTS_IN_FLIGHT, Some(request.channel_user_id), &request.currency)?;
+        let paid = get_or_create_account(db, PAYOUTS_PAID, None, &request.currency)?;
+
+        let ledger_transaction = post_transaction(db, "WITHDRAWAL_PAID", &[
+            (in_flight.id, -request.amount as i64),
+            (paid.id, request.amount as i64),
+        ])?;
+
+        diesel::insert_into(token_transactions)
+            .values(NewTokenTransaction {
+                channel_user_id: request.channel_user_id,
+                transaction_type: "WITHDRAWAL".to_string(),
+                amount: request.amount,
+                economics_id: Some(get_economics_at(db, request.created).id),
+                token_count: None,
+                period: None,
+                ledger_transaction_id: Some(ledger_transaction.id),
+                stripe_transfer_id: Some(transfer_id.to_string()),
+            })
+            .execute(db)?;
+
+        notify(db, request.channel_user_id, PAYOUT_PAID, json!({
+            "withdrawal_id": request.id,
+            "amount": request.amount,
+            "currency": request.currency,
+        }))?;
+
+        Ok(true)
+    })
+}
+
+// Gives the held money back to the channel
+pub fn fail_withdrawal(db: &PgConnection, request_id: i32, reason: &str) -> QueryResult<bool> {
+    db.transaction(|| {
+        let request = match transition(db, request_id, &["REQUESTED"], "FAILED")? {
+            Some(v) => v,
+            None => { return Ok(false); }
+        };
+
+        diesel::update(withdrawal_requests.find(request.id))
+            .set(failure_reason.eq(reason))
+            .execute(db)?;
+
+        let earnings = get_or_create_account(db, CHANNEL_EARNINGS, Some(request.channel_user_id), &request.currency)?;
+        let in_flight = get_or_create_account(db, PAYOUTS_IN_FLIGHT, Some(request.channel_user_id), &request.currency)?;
+
+        post_transaction(db, "WITHDRAWAL_FAILED", &[
+            (in_flight.id, -request.amount as i64),
+            (earnings.id, request.amount as i64),
+        ])?;
+
+        notify(db, request.channel_user_id, PAYOUT_FAILED, json!({
+            "withdrawal_id": request.id,
+            "amount": request.amount,
+            "currency": request.currency,
+            "reason": reason,
+        }))?;
+
+        Ok(true)
+    })
+}
+
+// The ledger side of a reversal is posted by the webhook, this only records that all of it came back
+pub fn mark_withdrawal_reversed(db: &PgConnection, request_id: i32) -> QueryResult<bool> {
+    let request = match transition(db, request_id, &["PAID"], "REVERSED")? {
+        Some(v) => v,
+        None => { return Ok(false); }
+    };
+
+    notify(db, request.channel_user_id, PAYOUT_REVERSED, json!({
+        "withdrawal_id": request.id,
+        "amount": request.amount,
+        "currency": request.currency,
+    }))?;
+
+    Ok(true)
+}
+
+// Matches a transfer to its request, by the transfer ID or else by the transfer group
+pub fn find_withdrawal_for_transfer(db: &PgConnection, transfer_id: &str, transfer_group: Option<&str>) -> QueryResult<Option<WithdrawalRequest>> {
+    let request: Option<WithdrawalRequest> = withdrawal_requests
+        .filter(stripe_transfer_id.eq(transfer_id))
+        .first(db)
+        .optional()?;
+
+    if request.is_some() {
+        return Ok(request);
+    }
+
+    match transfer_group.and_then(parse_transfer_group) {
+        Some(request_id) => withdrawal_requests.find(request_id).first(db).optional(),
+        None => Ok(None)
+    }
+}
+
+pub fn get_withdrawal_by_key(db: &PgConnection, channel_id: i32, key: &str) -> Option<WithdrawalRequest> {
+    withdrawal_requests
+        .filter(channel_user_id.eq(channel_id).and(idempotency_key.eq(key)))
+        .first::<WithdrawalRequest>(db)
+        .optional()
+        .expect("Query failed")
+}
+
+pub fn get_withdrawals(db: &PgConnection, channel_id: i32) -> Vec<WithdrawalRequest> {
+    withdrawal_requests
+        .filter(channel_user_id.eq(channel_id))
+        .order_by(created.desc())
+        .load::<WithdrawalRequest>(db)
+        .expect("Query failed")
+}
@@ -0,0 +1,13 @@
+use diesel::{PgConnection, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::JobRun;
+
+// Rebuilds the analytics rollups for yesterday and today (and the weeks they are in), see the
+// refresh_analytics SQL function. Yesterday is included for events which arrive around midnight.
+pub fn refresh_analytics(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    diesel::sql_query("select refresh_analytics(current_date - 1)")
+        .execute(db)?;
+
+    Ok(())
+}
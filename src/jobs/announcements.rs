@@ -0,0 +1,55 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+use diesel::dsl::not;
+use serde_json::json;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::jobs::add_job_progress;
+use crate::helpers::notifications::{notify, VIDEO_UPLOADED};
+use crate::models::{JobRun, NewVideoAnnouncement};
+use crate::schema::video_announcements::dsl::video_announcements;
+use crate::schema::videos::dsl::{id, status, title, user_id, videos};
+
+// Tells the subscribers supporting a channel about its new videos. Videos are only announced
+// once they are READY, which happens after the upload has been processed.
+pub fn announce_uploads(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let ready: Vec<(i32, i32, String)> = videos
+        .filter(status.eq("READY"))
+        .filter(not(id.eq_any(video_announcements.select(crate::schema::video_announcements::video_id))))
+        .select((id, user_id, title))
+        .load(db)?;
+
+    for (video, channel, video_title) in &ready {
+        db.transaction(|| {
+            let supporters: Vec<i32> = crate::schema::channels_tokens::table
+                .inner_join(crate::schema::tokens::table)
+                .filter(crate::schema::channels_tokens::channel_user_id.eq(channel)
+                    .and(crate::schema::channels_tokens::expires.ge(SystemTime::now())))
+                .select(crate::schema::tokens::user_id)
+                .distinct()
+                .load(db)?;
+
+            for supporter in &supporters {
+                notify(db, *supporter, VIDEO_UPLOADED, json!({
+                    "video_id": video,
+                    "channel_user_id": channel,
+                    "title": video_title,
+                }))?;
+            }
+
+            diesel::insert_into(video_announcements)
+                .values(NewVideoAnnouncement {
+                    video_id: *video,
+                    supporter_count: supporters.len() as i32,
+                })
+                .execute(db)?;
+
+            add_job_progress(db, run.id)
+        })?;
+    }
+
+    println!("Announced {} videos", ready.len());
+
+    Ok(())
+}
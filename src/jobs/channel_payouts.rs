@@ -1,54 +1,97 @@
-use diesel::{ExpressionMethods, QueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult};
 
-use crate::diesel::GroupByDsl;
 use crate::diesel::RunQueryDsl;
-use crate::establish_connection;
-use crate::models::{ChannelTokenAmount, NewTokenTransaction};
-use crate::schema::channels_tokens::columns::{channel_user_id, converted};
+use crate::helpers::economics::get_current_economics;
+use crate::helpers::jobs::add_job_progress;
+use crate::helpers::ledger::{CHANNEL_EARNINGS, get_or_create_account, PLATFORM_REVENUE, post_transaction};
+use crate::models::{JobRun, NewTokenTransaction, TokenTransaction};
+use crate::schema::channels_tokens::columns::{channel_user_id, converted, id, transaction_id};
 use crate::schema::channels_tokens::dsl::channels_tokens;
-use crate::schema::token_transactions::dsl::token_transactions;
+use crate::schema::token_transactions::dsl::{ledger_transaction_id, token_transactions};
 
 // For each channel count the unconverted tokens they have
 // Multiply the amount by the token value
 // Insert into token_transactions the result as a DEPOSIT
-pub fn convert_tokens(name: &str) {
-    println!("CRON JOB STARTED: {}", name);
-
-    let db = establish_connection();
-
-    println!("Retrieving unconverted tokens");
-    // Retrieve unconverted tokens for each channel
-    let result: Vec<ChannelTokenAmount> = channels_tokens.filter(converted.eq(false))
-        .select(
-            (
-                channel_user_id,
-                diesel::dsl::sql::<diesel::sql_types::BigInt>("count(*)"),
-            )
-        )
-        .group_by(channel_user_id)
-        .load::<ChannelTokenAmount>(&db)
-        .expect("Query failed");
-
-    // Map the result into a insertable db models
-    let transactions: Vec<NewTokenTransaction> = result.iter().map(|ct| {
-        NewTokenTransaction {
-            channel_user_id: ct.channel_user_id,
-            transaction_type: "DEPOSIT".to_string(),
-            amount: (ct.token_count * 180) as i32,
-        }
-    }).collect();
+// Post it to the ledger, moving the money from platform revenue to the channel's earnings
+//
+// Each channel is converted in its own transaction, and a channel gets at most one deposit per
+// period, so a crash part way through or a second run for the period can't pay anyone twice.
+pub fn convert_tokens(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let economics = get_current_economics(db);
+
+    println!("Retrieving channels with unconverted tokens");
+    let channel_ids: Vec<i32> = channels_tokens
+        .filter(converted.eq(false))
+        .select(channel_user_id)
+        .distinct()
+        .order_by(channel_user_id.asc())
+        .load::<i32>(db)?;
+
+    let mut deposits = 0;
 
     println!("Depositing money to users");
-    // Insert into db
-    diesel::insert_into(token_transactions)
-        .values(transactions)
-        .execute(&db)
-        .expect("Query failed");
-
-    diesel::update(channels_tokens.filter(converted.eq(false)))
-        .set(converted.eq(true))
-        .execute(&db)
-        .expect("Query failed");
-
-    println!("CRON JOB FINISHED: {}", name);
-}
\ No newline at end of file
+    for channel_id in channel_ids {
+        let deposited = db.transaction(|| {
+            // Lock the tokens so they can't be converted by anything else at the same time
+            let token_ids: Vec<i32> = channels_tokens
+                .filter(channel_user_id.eq(channel_id).and(converted.eq(false)))
+                .select(id)
+                .for_update()
+                .load::<i32>(db)?;
+
+            if token_ids.len() == 0 {
+                return Ok(false);
+            }
+
+            let transaction: Option<TokenTransaction> = diesel::insert_into(token_transactions)
+                .values(NewTokenTransaction {
+                    channel_user_id: channel_id,
+                    transaction_type: "DEPOSIT".to_string(),
+                    amount: token_ids.len() as i32 * economics.token_value,
+                    economics_id: Some(economics.id),
+                    token_count: Some(token_ids.len() as i32),
+                    period: Some(run.period.clone()),
+                    ledger_transaction_id: None,
+                    stripe_transfer_id: None,
+                })
+                .on_conflict_do_nothing()
+                .get_result(db)
+                .optional()?;
+
+            // The channel has already been paid for this period. Tokens given to it since are
+            // left for the next one.
+            let transaction = match transaction {
+                Some(v) => v,
+                None => { return Ok(false); }
+            };
+
+            let platform = get_or_create_account(db, PLATFORM_REVENUE, None, &economics.currency)?;
+            let earnings = get_or_create_account(db, CHANNEL_EARNINGS, Some(channel_id), &economics.currency)?;
+
+            let ledger_transaction = post_transaction(db, "TOKEN_CONVERSION", &[
+                (platform.id, -(transaction.amount as i64)),
+                (earnings.id, transaction.amount as i64),
+            ])?;
+
+            diesel::update(token_transactions.find(transaction.id))
+                .set(ledger_transaction_id.eq(ledger_transaction.id))
+                .execute(db)?;
+
+            diesel::update(channels_tokens.filter(id.eq_any(&token_ids)))
+                .set((converted.eq(true), transaction_id.eq(transaction.id)))
+                .execute(db)?;
+
+            add_job_progress(db, run.id)?;
+
+            Ok(true)
+        })?;
+
+        if deposited {
+            deposits += 1;
+        }
+    }
+
+    println!("Deposited money to {} channels", deposits);
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use diesel::{Connection, PgConnection, QueryResult};
+
+use crate::helpers::expiry::{expire_grant, get_due_grants};
+use crate::helpers::jobs::add_job_progress;
+use crate::models::JobRun;
+
+// Applies the rollover policy for every billing period which has ended, oldest first so a
+// rollover limit is applied to what was actually left at the end of each period
+pub fn expire_tokens(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let grants = get_due_grants(db)?;
+
+    for grant in &grants {
+        db.transaction(|| {
+            if expire_grant(db, grant)? {
+                add_job_progress(db, run.id)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    println!("Applied the rollover policy to {} grants", grants.len());
+
+    Ok(())
+}
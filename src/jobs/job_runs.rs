@@ -0,0 +1,23 @@
+use diesel::{PgConnection, QueryResult};
+
+use crate::helpers::jobs::delete_completed_job_runs;
+use crate::jobs::scheduler::{JobPeriod, JOBS};
+use crate::models::JobRun;
+
+// How long completed runs are kept for in the run history
+const KEEP_COMPLETED_RUNS_DAYS: i32 = 30;
+
+// Jobs which run every few minutes add hundreds of runs a day. Monthly runs are never deleted,
+// the completed run is what stops the job running twice in the same month.
+pub fn prune_job_runs(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    let names: Vec<&str> = JOBS.iter()
+        .filter(|job| matches!(job.period, JobPeriod::Scheduled))
+        .map(|job| job.name)
+        .collect();
+
+    let deleted = delete_completed_job_runs(db, &names, KEEP_COMPLETED_RUNS_DAYS)?;
+
+    println!("Pruned {} completed job runs", deleted);
+
+    Ok(())
+}
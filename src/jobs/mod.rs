@@ -1,2 +1,14 @@
 pub mod channel_payouts;
-pub mod assign_tokens;
\ No newline at end of file
+pub mod reconcile_counters;
+pub mod trending;
+pub mod recommendations;
+pub mod scheduler;
+pub mod subscriptions;
+pub mod withdrawals;
+pub mod payouts;
+pub mod analytics;
+pub mod retention;
+pub mod renewals;
+pub mod expiry;
+pub mod announcements;
+pub mod job_runs;
\ No newline at end of file
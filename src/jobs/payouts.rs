@@ -0,0 +1,68 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::economics::get_current_economics;
+use crate::helpers::jobs::{add_job_progress, get_current_period, get_current_week};
+use crate::helpers::ledger::get_channel_balance;
+use crate::helpers::withdrawals::{get_withdrawal_by_key, request_withdrawal, submit_withdrawal, WithdrawalError};
+use crate::models::{JobRun, PayoutPreference, User};
+use crate::schema::payout_preferences::dsl::{payout_preferences, schedule};
+use crate::schema::users::dsl::{channel_onboarded, stripe_account};
+
+// Pays out channels on a weekly or monthly schedule. Each channel gets at most one automatic
+// withdrawal per week or month, made the first time the job sees their balance at or above
+// their minimum. The idempotency key is the period, so running the job again never pays twice.
+pub fn create_scheduled_payouts(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let channels: Vec<(PayoutPreference, User)> = payout_preferences
+        .inner_join(crate::schema::users::table)
+        .filter(schedule.ne("MANUAL")
+            .and(channel_onboarded.eq(true))
+            .and(stripe_account.is_not_null()))
+        .load::<(PayoutPreference, User)>(db)?;
+
+    let currency = get_current_economics(db).currency;
+    let week = get_current_week(db);
+    let month = get_current_period(db);
+
+    let mut paid = 0;
+
+    for (preference, channel) in &channels {
+        let key = match preference.schedule.as_str() {
+            "WEEKLY" => format!("auto-{}", week),
+            _ => format!("auto-{}", month)
+        };
+
+        if get_withdrawal_by_key(db, channel.id, &key).is_some() {
+            continue;
+        }
+
+        let balance = get_channel_balance(db, channel.id, &currency);
+
+        if balance < preference.minimum_amount as i64 {
+            continue;
+        }
+
+        let amount = balance.min(i32::MAX as i64) as i32;
+
+        let request = match request_withdrawal(db, channel.id, &key, amount, &currency) {
+            Ok(v) => v,
+            Err(WithdrawalError::Query(e)) => { return Err(e); }
+            // Spent or paid out in the meantime
+            Err(_) => { continue; }
+        };
+
+        if request.status == "REQUESTED" {
+            // Checked in the filter above
+            let account = channel.stripe_account.as_ref().unwrap();
+
+            futures::executor::block_on(submit_withdrawal(db, &request, account))?;
+        }
+
+        add_job_progress(db, run.id)?;
+        paid += 1;
+    }
+
+    println!("Created {} scheduled payouts", paid);
+
+    Ok(())
+}
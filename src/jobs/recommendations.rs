@@ -0,0 +1,20 @@
+use diesel::{Connection, PgConnection, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::JobRun;
+
+// Rebuilds the item to item similarity model and every user's recommendations from it.
+// Both are replaced in one transaction, so readers never see a half built model.
+pub fn refresh_recommendations(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    db.transaction(|| {
+        println!("Refreshing video similarities");
+        diesel::sql_query("select refresh_video_similarities()")
+            .execute(db)?;
+
+        println!("Refreshing user recommendations");
+        diesel::sql_query("select refresh_user_recommendations()")
+            .execute(db)?;
+
+        Ok(())
+    })
+}
@@ -0,0 +1,22 @@
+use diesel::{PgConnection, QueryResult};
+use diesel::sql_types::Integer;
+
+use crate::diesel::RunQueryDsl;
+use crate::models::JobRun;
+
+#[derive(QueryableByName)]
+struct ReconcileResult {
+    #[sql_type = "Integer"]
+    fixed: i32,
+}
+
+// The counter columns on videos, comments and users are kept up to date by triggers.
+// This recalculates all of them from the source tables in case anything has drifted.
+pub fn reconcile_counters(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    let result: ReconcileResult = diesel::sql_query("select reconcile_counters() as fixed")
+        .get_result(db)?;
+
+    println!("Reconciled counters, {} rows were out of date", result.fixed);
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::jobs::add_job_progress;
+use crate::helpers::renewals::renew_channels;
+use crate::models::{JobRun, User};
+use crate::schema::auto_renewals::dsl::{auto_renewals, user_id};
+use crate::schema::users::dsl::{id, subscribed, users};
+
+// Renews listed channels whose support ran out since the subscriber's tokens were granted
+pub fn renew_supported_channels(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let subscribers: Vec<User> = users
+        .filter(subscribed.eq(true))
+        .filter(id.eq_any(auto_renewals.select(user_id)))
+        .load::<User>(db)?;
+
+    let mut renewed = 0;
+
+    for subscriber in &subscribers {
+        db.transaction(|| {
+            renewed += renew_channels(db, subscriber)?.len();
+            add_job_progress(db, run.id)
+        })?;
+    }
+
+    println!("Renewed {} channels for {} subscribers", renewed, subscribers.len());
+
+    Ok(())
+}
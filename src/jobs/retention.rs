@@ -0,0 +1,36 @@
+use diesel::{PgConnection, QueryResult};
+use diesel::sql_types::Integer;
+
+use crate::diesel::RunQueryDsl;
+use crate::models::JobRun;
+
+// Progress events added to the retention curves per call, each call is its own transaction
+const BATCH_SIZE: i32 = 10000;
+
+#[derive(QueryableByName)]
+struct Processed {
+    #[sql_type = "Integer"]
+    processed: i32,
+}
+
+// Adds the progress events since the last run to the retention curves, see the
+// update_retention SQL function
+pub fn update_retention(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    let mut total = 0;
+
+    loop {
+        let result: Processed = diesel::sql_query("select update_retention($1) as processed")
+            .bind::<Integer, _>(BATCH_SIZE)
+            .get_result(db)?;
+
+        total += result.processed;
+
+        if result.processed < BATCH_SIZE {
+            break;
+        }
+    }
+
+    println!("Added {} progress events to retention curves", total);
+
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use std::panic;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::establish_connection;
+use crate::helpers::jobs::{complete_job_run, ensure_scheduled_job, fail_job_run, get_current_period, get_scheduled_job, start_job_run, try_lock_job, unlock_job};
+use crate::jobs::analytics::refresh_analytics;
+use crate::jobs::announcements::announce_uploads;
+use crate::jobs::channel_payouts::convert_tokens;
+use crate::jobs::expiry::expire_tokens;
+use crate::jobs::job_runs::prune_job_runs;
+use crate::jobs::payouts::create_scheduled_payouts;
+use crate::jobs::recommendations::refresh_recommendations;
+use crate::jobs::reconcile_counters::reconcile_counters;
+use crate::jobs::renewals::renew_supported_channels;
+use crate::jobs::retention::update_retention;
+use crate::jobs::subscriptions::suspend_lapsed_subscriptions;
+use crate::jobs::trending::refresh_trending;
+use crate::jobs::withdrawals::reconcile_withdrawals;
+use crate::models::{JobRun, ScheduledJob};
+use crate::schema::scheduled_jobs::dsl::{name, next_run_at, run_requested, scheduled_jobs};
+
+// How often each job checks whether it is due
+const TICK_SECONDS: u64 = 10;
+
+pub enum JobPeriod {
+    // At most one completed run per month, see get_current_period
+    Monthly,
+    // One run per time the job is due, keyed to the second so schedules which fire more than
+    // once a minute get a run each time
+    Scheduled,
+}
+
+pub struct Job {
+    pub name: &'static str,
+    // Cron expression with seconds, used until an admin changes it
+    pub default_schedule: &'static str,
+    pub period: JobPeriod,
+    pub run: fn(&PgConnection, &JobRun) -> QueryResult<()>,
+}
+
+pub static JOBS: [Job; 13] = [
+    Job {
+        name: "Convert tokens",
+        default_schedule: "0 0 12 1 * *", // First of every month
+        period: JobPeriod::Monthly,
+        run: convert_tokens,
+    },
+    Job {
+        name: "Reconcile counters",
+        default_schedule: "0 0 3 * * *",
+        period: JobPeriod::Scheduled,
+        run: reconcile_counters,
+    },
+    Job {
+        name: "Refresh trending",
+        default_schedule: "0 */15 * * * *",
+        period: JobPeriod::Scheduled,
+        run: refresh_trending,
+    },
+    Job {
+        name: "Refresh recommendations",
+        default_schedule: "0 0 4 * * *",
+        period: JobPeriod::Scheduled,
+        run: refresh_recommendations,
+    },
+    Job {
+        name: "Suspend lapsed subscriptions",
+        default_schedule: "0 0 * * * *",
+        period: JobPeriod::Scheduled,
+        run: suspend_lapsed_subscriptions,
+    },
+    Job {
+        name: "Reconcile withdrawals",
+        default_schedule: "0 */10 * * * *",
+        period: JobPeriod::Scheduled,
+        run: reconcile_withdrawals,
+    },
+    Job {
+        name: "Scheduled payouts",
+        default_schedule: "0 0 9 * * *",
+        period: JobPeriod::Scheduled,
+        run: create_scheduled_payouts,
+    },
+    Job {
+        name: "Refresh analytics",
+        default_schedule: "0 */15 * * * *",
+        period: JobPeriod::Scheduled,
+        run: refresh_analytics,
+    },
+    Job {
+        name: "Update retention",
+        default_schedule: "0 */5 * * * *",
+        period: JobPeriod::Scheduled,
+        run: update_retention,
+    },
+    Job {
+        name: "Renew supported channels",
+        default_schedule: "0 30 * * * *",
+        period: JobPeriod::Scheduled,
+        run: renew_supported_channels,
+    },
+    Job {
+        name: "Expire tokens",
+        default_schedule: "0 15 * * * *",
+        period: JobPeriod::Scheduled,
+        run: expire_tokens,
+    },
+    Job {
+        name: "Announce uploads",
+        default_schedule: "0 */5 * * * *",
+        period: JobPeriod::Scheduled,
+        run: announce_uploads,
+    },
+    Job {
+        name: "Prune job runs",
+        default_schedule: "0 45 3 * * *",
+        period: JobPeriod::Scheduled,
+        run: prune_job_runs,
+    },
+];
+
+pub fn get_job(target_name: &str) -> Option<&'static Job> {
+    JOBS.iter().find(|job| job.name == target_name)
+}
+
+pub fn is_valid_schedule(schedule: &str) -> bool {
+    Schedule::from_str(schedule).is_ok()
+}
+
+fn next_after(schedule: &Schedule, after: SystemTime) -> Option<SystemTime> {
+    schedule.after(&DateTime::<Utc>::from(after)).next().map(SystemTime::from)
+}
+
+fn set_next_run(db: &PgConnection, job: &Job, next: Option<SystemTime>) {
+    diesel::update(scheduled_jobs.filter(name.eq(job.name)))
+        .set((next_run_at.eq(next), run_requested.eq(false)))
+        .execute(db)
+        .expect("Query failed");
+}
+
+// Starts a thread per job. Jobs are due when their next_run_at has passed or an admin asked
+// for a run, and the advisory lock makes sure only one instance runs each job.
+pub fn start_scheduler() {
+    let db = establish_connection();
+
+    for job in JOBS.iter() {
+        ensure_scheduled_job(&db, job.name, job.default_schedule);
+    }
+
+    for job in JOBS.iter() {
+        thread::spawn(move || loop {
+            // A panicking job mustn't stop it from being scheduled again
+            if panic::catch_unwind(|| tick(job)).is_err() {
+                println!("CRON JOB PANICKED: {}", job.name);
+            }
+
+            thread::sleep(Duration::from_secs(TICK_SECONDS));
+        });
+    }
+}
+
+fn is_due(scheduled: &ScheduledJob, now: SystemTime) -> bool {
+    if scheduled.run_requested {
+        return true;
+    }
+
+    match scheduled.next_run_at {
+        Some(v) => !scheduled.paused && v <= now,
+        None => false
+    }
+}
+
+fn tick(job: &Job) {
+    let db = establish_connection();
+
+    let scheduled = match get_scheduled_job(&db, job.name) {
+        Some(v) => v,
+        None => { return; }
+    };
+
+    let schedule = match Schedule::from_str(&scheduled.schedule) {
+        Ok(v) => v,
+        Err(_) => {
+            println!("Invalid schedule for {}: {}", job.name, scheduled.schedule);
+            return;
+        }
+    };
+
+    let now = SystemTime::now();
+
+    if scheduled.next_run_at.is_none() && !scheduled.run_requested {
+        set_next_run(&db, job, next_after(&schedule, now));
+        return;
+    }
+
+    if !is_due(&scheduled, now) || !try_lock_job(&db, job.name) {
+        return;
+    }
+
+    // Another instance may have run the job between reading the row and taking the lock
+    let scheduled = get_scheduled_job(&db, job.name).unwrap();
+
+    if is_due(&scheduled, now) {
+        let due_at = match scheduled.next_run_at {
+            Some(v) if v <= now => v,
+            _ => now
+        };
+
+        run_job(&db, job, &scheduled, due_at);
+
+        // A manual run doesn't move the next scheduled run
+        let next = match scheduled.next_run_at {
+            Some(v) if v > now => Some(v),
+            _ => next_after(&schedule, SystemTime::now())
+        };
+
+        set_next_run(&db, job, next);
+    }
+
+    unlock_job(&db, job.name);
+}
+
+// Runs the job for the period it is due in, retrying failures with exponential backoff.
+// Must be called while holding the job's lock.
+fn run_job(db: &PgConnection, job: &Job, scheduled: &ScheduledJob, due_at: SystemTime) {
+    let period_key = match job.period {
+        JobPeriod::Monthly => get_current_period(db),
+        JobPeriod::Scheduled => DateTime::<Utc>::from(due_at).format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+
+    println!("CRON JOB STARTED: {}", job.name);
+
+    let mut attempt = 0;
+
+    loop {
+        let run = match start_job_run(db, job.name, &period_key) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                println!("{} has already run for {}", job.name, period_key);
+                break;
+            }
+            Err(e) => {
+                println!("Couldn't start {}: {}", job.name, e);
+                break;
+            }
+        };
+
+        attempt += 1;
+
+        match (job.run)(db, &run) {
+            Ok(_) => {
+                complete_job_run(db, run.id);
+                break;
+            }
+            Err(e) => {
+                fail_job_run(db, run.id, &e.to_string());
+
+                if attempt >= scheduled.max_attempts {
+                    println!("{} failed after {} attempts: {}", job.name, attempt, e);
+                    break;
+                }
+
+                let delay = scheduled.retry_delay_seconds.max(0) as u64 * 2u64.pow(attempt as u32 - 1);
+
+                println!("{} failed, retrying in {} seconds: {}", job.name, delay, e);
+                thread::sleep(Duration::from_secs(delay));
+            }
+        }
+    }
+
+    println!("CRON JOB FINISHED: {}", job.name);
+}
+
+// Runs a job straight away from the command line, e.g. to resume a failed monthly run
+pub fn run_job_now(target_name: &str) {
+    let job = match get_job(target_name) {
+        Some(v) => v,
+        None => {
+            println!("Unknown job: {}", target_name);
+            return;
+        }
+    };
+
+    let db = establish_connection();
+
+    ensure_scheduled_job(&db, job.name, job.default_schedule);
+
+    if !try_lock_job(&db, job.name) {
+        println!("{} is already running", job.name);
+        return;
+    }
+
+    let scheduled = get_scheduled_job(&db, job.name).unwrap();
+
+    run_job(&db, job, &scheduled, SystemTime::now());
+
+    unlock_job(&db, job.name);
+}
@@ -0,0 +1,27 @@
+use std::time::SystemTime;
+
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::jobs::add_job_progress;
+use crate::helpers::subscriptions::subscription_suspended;
+use crate::models::{JobRun, User};
+use crate::schema::users::dsl::{grace_period_ends, subscription_status, users};
+
+// Suspends subscribers whose grace period after a failed payment is over
+pub fn suspend_lapsed_subscriptions(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let lapsed: Vec<User> = users
+        .filter(subscription_status.eq("PAST_DUE").and(grace_period_ends.le(SystemTime::now())))
+        .load::<User>(db)?;
+
+    for user in &lapsed {
+        db.transaction(|| {
+            subscription_suspended(db, user)?;
+            add_job_progress(db, run.id)
+        })?;
+    }
+
+    println!("Suspended {} subscriptions", lapsed.len());
+
+    Ok(())
+}
@@ -0,0 +1,12 @@
+use diesel::{PgConnection, QueryResult};
+
+use crate::diesel::RunQueryDsl;
+use crate::models::JobRun;
+
+// Recalculates videos.trending_score, see the refresh_trending_scores SQL function
+pub fn refresh_trending(db: &PgConnection, _run: &JobRun) -> QueryResult<()> {
+    diesel::sql_query("select refresh_trending_scores()")
+        .execute(db)?;
+
+    Ok(())
+}
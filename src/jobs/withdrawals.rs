@@ -0,0 +1,88 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, QueryResult};
+use diesel::sql_types::VarChar;
+
+use crate::diesel::RunQueryDsl;
+use crate::helpers::jobs::add_job_progress;
+use crate::helpers::users::get_user_by_id;
+use crate::helpers::webhooks::reprocess_stored_event;
+use crate::helpers::withdrawals::{fail_withdrawal, submit_withdrawal};
+use crate::models::{JobRun, WithdrawalRequest};
+use crate::schema::withdrawal_requests::dsl::{status, updated, withdrawal_requests};
+
+// Requests which haven't moved for this long were left behind by a failed or interrupted
+// submit, anything newer may still be in the middle of one
+const STALE_SECONDS: u64 = 5 * 60;
+
+// Transfers are confirmed by Stripe within seconds, a request still waiting after this needs
+// looking at by hand
+const OVERDUE_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(QueryableByName)]
+struct StoredEvent {
+    #[sql_type = "VarChar"]
+    id: String,
+}
+
+// Matches withdrawal requests against the transfer events Stripe sent. Transfer events for a
+// request whose handler failed are handled again, requests whose submit never got an answer
+// are submitted again with the same idempotency key, and requests Stripe never confirmed
+// are logged.
+pub fn reconcile_withdrawals(db: &PgConnection, run: &JobRun) -> QueryResult<()> {
+    let events: Vec<StoredEvent> = diesel::sql_query("
+        select e.id
+        from stripe_events e
+        where e.event_type in ('transfer.created', 'transfer.reversed')
+          and e.processed_at is null
+          and exists(
+            select 1
+            from withdrawal_requests r
+            where r.stripe_transfer_id = e.payload::json #>> '{data,object,id}'
+               or 'withdrawal_' || r.id = e.payload::json #>> '{data,object,transfer_group}'
+          )
+        order by e.received
+    ")
+        .load(db)?;
+
+    for event in &events {
+        match reprocess_stored_event(db, &event.id) {
+            Ok(_) => add_job_progress(db, run.id)?,
+            Err(e) => println!("Couldn't handle Stripe event {}: {}", event.id, e)
+        }
+    }
+
+    let stale: Vec<WithdrawalRequest> = withdrawal_requests
+        .filter(status.eq("REQUESTED").and(updated.lt(SystemTime::now() - Duration::from_secs(STALE_SECONDS))))
+        .load::<WithdrawalRequest>(db)?;
+
+    for request in &stale {
+        let account = get_user_by_id(db, request.channel_user_id).and_then(|channel| channel.stripe_account);
+
+        match account {
+            Some(account) => {
+                futures::executor::block_on(submit_withdrawal(db, request, &account))?;
+            }
+            None => {
+                fail_withdrawal(db, request.id, "The channel has no Stripe account")?;
+            }
+        }
+
+        add_job_progress(db, run.id)?;
+    }
+
+    let overdue: Vec<WithdrawalRequest> = withdrawal_requests
+        .filter(status.eq("SUBMITTED").and(updated.lt(SystemTime::now() - Duration::from_secs(OVERDUE_SECONDS))))
+        .load::<WithdrawalRequest>(db)?;
+
+    for request in &overdue {
+        println!("Withdrawal {} was submitted as transfer {} but Stripe never confirmed it",
+                 request.id,
+                 request.stripe_transfer_id.as_deref().unwrap_or("-"));
+    }
+
+    println!("Reconciled withdrawals, {} events handled, {} requests resubmitted, {} overdue",
+             events.len(), stale.len(), overdue.len());
+
+    Ok(())
+}
@@ -6,16 +6,19 @@ extern crate dotenv;
 
 use std::env;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, web};
-use cronjob::CronJob;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 
 use crate::claims::user::UserClaim;
-use crate::jobs::channel_payouts::convert_tokens;
-use crate::jobs::assign_tokens::assign_tokens;
+use crate::helpers::ledger::check_ledger;
+use crate::helpers::payments::init_payments_provider;
+use crate::helpers::subscriptions::backfill_subscriptions;
+use crate::helpers::webhooks::sign_payload;
+use crate::jobs::scheduler::{run_job_now, start_scheduler};
 
 // END Diesel imports
 
@@ -26,6 +29,8 @@ mod helpers;
 mod schema;
 mod models;
 mod jobs;
+#[cfg(test)]
+mod test_helpers;
 
 pub fn establish_connection() -> PgConnection {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -40,23 +45,54 @@ pub struct AppState {
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
 
-    // TOOD: fix this
-    let mut convert_tokens_cron = CronJob::new("Convert tokens", convert_tokens);
-    convert_tokens_cron.day_of_month("1"); // First of every month
-    convert_tokens_cron.hours("12");
-    convert_tokens_cron.minutes("00");
-    convert_tokens_cron.seconds("0");
-    convert_tokens_cron.offset(0);
+    // Maintenance commands, e.g. `cargo run -- reconcile-counters`
+    // Convert tokens can be run by hand to resume a failed run for the current month.
+    if let Some(command) = env::args().nth(1) {
+        match command.as_str() {
+            "reconcile-counters" => run_job_now("Reconcile counters"),
+            "convert-tokens" => run_job_now("Convert tokens"),
+            "check-ledger" => {
+                let problems = check_ledger(&establish_connection());
 
-    let mut assign_tokens_cron = CronJob::new("Assign tokens", assign_tokens);
-    assign_tokens_cron.day_of_month("1");
-    assign_tokens_cron.hours("12");
-    assign_tokens_cron.minutes("30");
-    assign_tokens_cron.seconds("0");
-    assign_tokens_cron.offset(0);
+                for problem in &problems {
+                    println!("{}", problem);
+                }
 
-    CronJob::start_job_threaded(convert_tokens_cron);
-    CronJob::start_job_threaded(assign_tokens_cron);
+                if problems.len() > 0 {
+                    std::process::exit(1);
+                }
+
+                println!("Ledger reconciles");
+            }
+            // Finds the Stripe subscriptions of subscribers from before the subscription lifecycle
+            "backfill-subscriptions" => {
+                if let Err(e) = init_payments_provider() {
+                    panic!("Couldn't set up payments: {}", e);
+                }
+
+                backfill_subscriptions(&establish_connection()).await
+                    .expect("Query failed");
+            }
+            // Prints a Stripe-Signature header for a fixture, e.g. `cargo run -- sign-webhook fixtures/stripe/invoice.paid.json`
+            "sign-webhook" => {
+                let path = env::args().nth(2).expect("Usage: sign-webhook <payload file>");
+                let payload = std::fs::read(&path).expect("Couldn't read payload file");
+                let secret = env::var("STRIPE_WEBHOOK_SECRET").expect("Missing STRIPE_WEBHOOK_SECRET in env");
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+                println!("{}", sign_payload(&payload, &secret, now));
+            }
+            _ => println!("Unknown command: {}", command)
+        }
+
+        return Ok(());
+    }
+
+    if let Err(e) = init_payments_provider() {
+        panic!("Couldn't set up payments: {}", e);
+    }
+
+    start_scheduler();
 
     let state = web::Data::new(Mutex::new(AppState {
         user: None
@@ -74,12 +110,16 @@ async fn main() -> std::io::Result<()> {
                     .service(routes::auth::register)
                     .service(routes::auth::request_password_reset)
                     .service(routes::auth::reset_password)
-                    .service(routes::auth::stripe_account_updated_hook)
                 // .wrap(middleware::auth::CheckLogin {
                 //     state: state.clone()
                 // })
                 // .service(routes::auth::is_channel_onboarded)
             )
+            .service(
+                web::scope("/webhooks")
+                    .service(routes::webhooks::stripe_webhook)
+                    .service(routes::webhooks::deliver_fake_events)
+            )
             .service(
                 web::scope("/video")
                     .wrap(middleware::auth::CheckLogin {
@@ -88,9 +128,13 @@ async fn main() -> std::io::Result<()> {
                     .service(routes::video::update_video)
                     .service(routes::video::get_available_tags)
                     .service(routes::video::get_popular_tags)
+                    .service(routes::video::get_continue_watching)
+                    .service(routes::video::get_trending)
                     .service(routes::video::get_videos)
                     .service(routes::video::get_video)
+                    .service(routes::video::get_related)
                     .service(routes::video::record_play)
+                    .service(routes::video::record_progress)
             )
             .service(
                 web::scope("/upload")
@@ -105,14 +149,55 @@ async fn main() -> std::io::Result<()> {
                         state: state.clone()
                     })
                     .service(routes::tokens::get_my_tokens)
+                    .service(routes::tokens::get_my_token_expirations)
                     .service(routes::tokens::transfer_token_to_channel)
                     .service(routes::tokens::get_active_tokens)
                     .service(routes::tokens::has_active_token)
                     .service(routes::tokens::get_my_balance)
                     .service(routes::tokens::get_my_transaction_history)
                     .service(routes::tokens::generate_withdrawal)
+                    .service(routes::tokens::get_my_withdrawals)
+                    .service(routes::tokens::get_my_statement_periods)
+                    .service(routes::tokens::get_my_statement)
+                    .service(routes::tokens::get_my_payout_preference)
+                    .service(routes::tokens::update_payout_preference)
+                    .service(routes::tokens::get_my_auto_renewals)
+                    .service(routes::tokens::add_my_auto_renewal)
+                    .service(routes::tokens::remove_my_auto_renewal)
+                    .service(routes::tokens::reorder_my_auto_renewals)
                     .service(routes::tokens::generate_account_link)
             )
+            .service(
+                web::scope("/billing")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::billing::get_billing)
+                    .service(routes::billing::cancel_subscription)
+                    .service(routes::billing::resume_subscription)
+                    .service(routes::billing::update_payment_method)
+            )
+            .service(
+                web::scope("/analytics")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::analytics::get_channel_analytics)
+                    .service(routes::analytics::get_channel_video_analytics)
+                    .service(routes::analytics::get_video_analytics)
+                    .service(routes::analytics::get_video_retention)
+            )
+            .service(
+                web::scope("/notifications")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::notifications::get_my_notifications)
+                    .service(routes::notifications::mark_notification_read)
+                    .service(routes::notifications::mark_all_notifications_read)
+                    .service(routes::notifications::get_my_notification_preferences)
+                    .service(routes::notifications::update_notification_preference)
+            )
             .service(
                 web::scope("/comments")
                     .wrap(middleware::auth::CheckLogin {
@@ -138,10 +223,57 @@ async fn main() -> std::io::Result<()> {
                         state: state.clone()
                     })
                     .service(routes::users::get_top_channels)
+                    .service(routes::users::get_me)
                     .service(routes::users::get_users)
                     .service(routes::users::get_user)
                     .service(routes::users::update_user)
             )
+            .service(
+                web::scope("/recommendations")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::recommendations::get_my_feedback)
+                    .service(routes::recommendations::not_interested)
+                    .service(routes::recommendations::see_less)
+                    .service(routes::recommendations::delete_feedback)
+            )
+            .service(
+                web::scope("/playlists")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::playlists::get_my_playlists)
+                    .service(routes::playlists::get_watch_later)
+                    .service(routes::playlists::get_user_playlists)
+                    .service(routes::playlists::get_playlist_videos)
+                    .service(routes::playlists::create_playlist)
+                    .service(routes::playlists::update_playlist)
+                    .service(routes::playlists::delete_playlist)
+                    .service(routes::playlists::add_playlist_video)
+                    .service(routes::playlists::remove_playlist_video)
+                    .service(routes::playlists::reorder_playlist)
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(middleware::auth::CheckLogin {
+                        state: state.clone()
+                    })
+                    .service(routes::tags::create_tag)
+                    .service(routes::tags::rename_tag)
+                    .service(routes::tags::merge_tags)
+                    .service(routes::tags::delete_tag)
+                    .service(routes::experiments::list_experiments)
+                    .service(routes::experiments::create_experiment)
+                    .service(routes::experiments::set_experiment_active)
+                    .service(routes::economics::list_economics)
+                    .service(routes::economics::create_economics)
+                    .service(routes::jobs::list_jobs)
+                    .service(routes::jobs::get_job_runs)
+                    .service(routes::jobs::run_job)
+                    .service(routes::jobs::pause_job)
+                    .service(routes::jobs::set_job_schedule)
+            )
     })
         .bind("127.0.0.1:5000")?
         .run()
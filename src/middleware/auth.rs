@@ -14,6 +14,12 @@ use crate::claims::user;
  * This middleware is for protecting routes which require the user to be logged in.
  * It checks for the existence of an 'Authorization' header and the validity of the
  * JWT token supplied in this header.
+ *
+ * It locks the shared AppState on every request, blocking the worker's thread while it waits.
+ * Handlers must copy what they need out of the state and drop the guard before any .await
+ * which really suspends, e.g. a Stripe request or web::block. Otherwise the next request on
+ * the same worker blocks it, the suspended handler never resumes to release the lock, and
+ * every other worker ends up waiting for it too.
  */
 
 pub struct CheckLogin {
@@ -1,22 +1,47 @@
 use serde::Serialize;
 
+use crate::schema::auto_renewals;
 use crate::schema::channels_tokens;
 use crate::schema::comment_upvotes;
 use crate::schema::comments;
+use crate::schema::experiment_variants;
+use crate::schema::experiments;
+use crate::schema::job_runs;
+use crate::schema::ledger_accounts;
+use crate::schema::ledger_entries;
+use crate::schema::ledger_transactions;
+use crate::schema::notification_preferences;
+use crate::schema::notifications;
+use crate::schema::payout_preferences;
+use crate::schema::play_events;
+use crate::schema::play_flags;
 use crate::schema::token_transactions;
+use crate::schema::playlists;
+use crate::schema::playlists_videos;
+use crate::schema::progress_events;
+use crate::schema::recommendation_feedback;
+use crate::schema::scheduled_jobs;
+use crate::schema::stripe_events;
+use crate::schema::tags;
+use crate::schema::token_economics;
+use crate::schema::token_expirations;
+use crate::schema::token_grants;
+use crate::schema::token_plans;
 use crate::schema::tokens;
 use crate::schema::users;
+use crate::schema::video_announcements;
 use crate::schema::video_plays;
+use crate::schema::video_progress;
 use crate::schema::video_upvotes;
 use crate::schema::videos;
 use crate::schema::videos_tags;
-use crate::schema::users::columns::{user_type, id, username, avatar_filename, cover_filename, subscriptions_enabled, display_name, bio, channel_onboarded};
+use crate::schema::withdrawal_requests;
+use crate::schema::users::columns::{user_type, id, username, avatar_filename, cover_filename, subscriptions_enabled, display_name, bio, subscriber_count, channel_onboarded};
 use diesel::types::{FromSql};
 use diesel::backend::{Backend};
 use diesel::pg::Pg;
 use diesel::deserialize;
-use diesel::sql_types::{Record, VarChar, Integer, BigInt};
-use diesel::expression::SqlLiteral;
+use diesel::sql_types::{Record, VarChar, Integer};
 
 #[derive(Queryable, Serialize)]
 pub struct User {
@@ -34,7 +59,14 @@ pub struct User {
     pub cover_filename: Option<String>,
     pub subscriptions_enabled: bool,
     pub display_name: Option<String>,
-    pub bio: Option<String>
+    pub bio: Option<String>,
+    pub subscriber_count: i32,
+    pub plan: String,
+    pub subscription_status: String,
+    pub stripe_subscription: Option<String>,
+    pub cancel_at_period_end: bool,
+    pub current_period_end: Option<std::time::SystemTime>,
+    pub grace_period_ends: Option<std::time::SystemTime>,
 }
 
 #[derive(Insertable)]
@@ -44,6 +76,7 @@ pub struct NewUser<'a> {
     pub password: &'a str,
     pub email: &'a str,
     pub user_type: &'a str,
+    pub plan: &'a str,
 }
 
 #[derive(Queryable, Serialize)]
@@ -55,6 +88,11 @@ pub struct Video {
     pub description: Option<String>,
     pub upload_date: std::time::SystemTime,
     pub status: String,
+    pub duration: Option<i32>,
+    pub upvote_count: i32,
+    pub downvote_count: i32,
+    pub play_count: i32,
+    pub trending_score: f64,
 }
 
 #[derive(Queryable, Serialize)]
@@ -67,12 +105,12 @@ pub struct SafeUser {
     pub subscriptions_enabled: bool,
     pub display_name: Option<String>,
     pub bio: Option<String>,
-    pub subscribers: i64,
+    pub subscribers: i32,
     pub channel_onboarded: bool
 }
 
 // TODO: is there a nicer way to do this?
-pub fn get_safe_user_fields() -> (id, username, user_type, avatar_filename, cover_filename, subscriptions_enabled, display_name, bio, SqlLiteral<BigInt, ()>, channel_onboarded) {
+pub fn get_safe_user_fields() -> (id, username, user_type, avatar_filename, cover_filename, subscriptions_enabled, display_name, bio, subscriber_count, channel_onboarded) {
     (
         crate::schema::users::id,
         crate::schema::users::username,
@@ -82,7 +120,7 @@ pub fn get_safe_user_fields() -> (id, username, user_type, avatar_filename, cove
         crate::schema::users::subscriptions_enabled,
         crate::schema::users::display_name,
         crate::schema::users::bio,
-        diesel::dsl::sql::<diesel::sql_types::BigInt>("count(\"channels_tokens\".*)"),
+        crate::schema::users::subscriber_count,
         crate::schema::users::channel_onboarded
     )
 }
@@ -110,6 +148,7 @@ pub struct NewVideo<'a> {
     pub user_id: i32,
     pub title: &'a str,
     pub description: Option<&'a str>,
+    pub duration: Option<i32>,
 }
 
 #[derive(Queryable, Serialize)]
@@ -132,6 +171,12 @@ pub struct Tag {
     pub name: String,
 }
 
+#[derive(Insertable)]
+#[table_name = "tags"]
+pub struct NewTag<'a> {
+    pub name: &'a str,
+}
+
 #[derive(Queryable, Serialize)]
 pub struct Token {
     pub id: i32,
@@ -139,12 +184,15 @@ pub struct Token {
     pub used: bool,
     pub date_granted: std::time::SystemTime,
     pub date_used: Option<std::time::SystemTime>,
+    pub grant_id: Option<i32>,
+    pub date_expired: Option<std::time::SystemTime>,
 }
 
 #[derive(Insertable)]
 #[table_name = "tokens"]
 pub struct NewToken {
-    pub user_id: i32
+    pub user_id: i32,
+    pub grant_id: Option<i32>,
 }
 
 #[derive(Queryable, Serialize)]
@@ -154,12 +202,8 @@ pub struct ChannelToken {
     pub channel_user_id: i32,
     pub expires: std::time::SystemTime,
     pub converted: bool,
-}
-
-#[derive(Queryable, Serialize)]
-pub struct ChannelTokenAmount {
-    pub channel_user_id: i32,
-    pub token_count: i64,
+    pub transaction_id: Option<i32>,
+    pub created: std::time::SystemTime,
 }
 
 #[derive(Queryable, Serialize)]
@@ -176,6 +220,7 @@ pub struct ChannelTokenWithUser {
 pub struct NewChannelToken {
     pub token_id: i32,
     pub channel_user_id: i32,
+    pub expires: std::time::SystemTime,
 }
 
 #[derive(Queryable)]
@@ -186,6 +231,8 @@ pub struct Comment {
     pub inactive: bool,
     pub date: std::time::SystemTime,
     pub video_id: i32,
+    pub upvote_count: i32,
+    pub downvote_count: i32,
 }
 
 #[derive(Queryable, Serialize)]
@@ -251,6 +298,7 @@ pub struct VideoPlay {
     pub user_id: i32,
     pub video_id: i32,
     pub date: std::time::SystemTime,
+    pub play_event_id: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -258,6 +306,135 @@ pub struct VideoPlay {
 pub struct NewVideoPlay {
     pub user_id: i32,
     pub video_id: i32,
+    pub play_event_id: Option<i32>,
+}
+
+#[derive(Queryable)]
+pub struct PlayEvent {
+    pub id: i32,
+    pub user_id: i32,
+    pub video_id: i32,
+    pub ip: Option<String>,
+    pub date: std::time::SystemTime,
+    pub counted: bool,
+    pub flagged: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "play_events"]
+pub struct NewPlayEvent<'a> {
+    pub user_id: i32,
+    pub video_id: i32,
+    pub ip: Option<&'a str>,
+    pub flagged: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "play_flags"]
+pub struct NewPlayFlag<'a> {
+    pub user_id: Option<i32>,
+    pub ip: Option<&'a str>,
+    pub reason: &'a str,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct VideoProgress {
+    pub id: i32,
+    pub user_id: i32,
+    pub video_id: i32,
+    pub position: i32,
+    pub watched_seconds: i32,
+    pub duration: Option<i32>,
+    pub last_updated: std::time::SystemTime,
+    pub session_seconds: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "video_progress"]
+pub struct NewVideoProgress {
+    pub user_id: i32,
+    pub video_id: i32,
+    pub position: i32,
+    pub watched_seconds: i32,
+    pub duration: Option<i32>,
+    pub last_updated: std::time::SystemTime,
+    pub session_seconds: i32,
+}
+
+#[derive(Serialize)]
+pub struct VideoWithProgress {
+    #[serde(flatten)]
+    pub video: VideoWithUser,
+    pub resume_position: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct RecommendedVideo {
+    #[serde(flatten)]
+    pub video: VideoWithUser,
+    pub reason_type: String,
+    pub reason: String,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct RecommendationFeedback {
+    pub id: i32,
+    pub user_id: i32,
+    pub feedback_type: String,
+    pub target_id: i32,
+    pub date: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "recommendation_feedback"]
+pub struct NewRecommendationFeedback<'a> {
+    pub user_id: i32,
+    pub feedback_type: &'a str,
+    pub target_id: i32,
+}
+
+#[derive(Queryable, Serialize, Clone)]
+pub struct Experiment {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub active: bool,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "experiments"]
+pub struct NewExperiment<'a> {
+    pub name: &'a str,
+    pub description: Option<&'a str>,
+}
+
+#[derive(Queryable, Serialize, Clone)]
+pub struct ExperimentVariant {
+    pub id: i32,
+    pub experiment_id: i32,
+    pub name: String,
+    pub weight: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "experiment_variants"]
+pub struct NewExperimentVariant<'a> {
+    pub experiment_id: i32,
+    pub name: &'a str,
+    pub weight: i32,
+}
+
+#[derive(Serialize)]
+pub struct ExperimentWithVariants {
+    pub experiment: Experiment,
+    pub variants: Vec<ExperimentVariant>,
+}
+
+#[derive(Serialize)]
+pub struct ExperimentAssignment {
+    pub experiment: String,
+    pub variant: String,
 }
 
 #[derive(Queryable, Serialize)]
@@ -267,6 +444,11 @@ pub struct TokenTransaction {
     pub transaction_type: String,
     pub amount: i32,
     pub date: std::time::SystemTime,
+    pub economics_id: Option<i32>,
+    pub token_count: Option<i32>,
+    pub period: Option<String>,
+    pub ledger_transaction_id: Option<i32>,
+    pub stripe_transfer_id: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -275,6 +457,225 @@ pub struct NewTokenTransaction {
     pub channel_user_id: i32,
     pub transaction_type: String,
     pub amount: i32,
+    pub economics_id: Option<i32>,
+    pub token_count: Option<i32>,
+    pub period: Option<String>,
+    pub ledger_transaction_id: Option<i32>,
+    pub stripe_transfer_id: Option<String>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct LedgerAccount {
+    pub id: i32,
+    pub account_type: String,
+    pub user_id: Option<i32>,
+    pub currency: String,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "ledger_accounts"]
+pub struct NewLedgerAccount<'a> {
+    pub account_type: &'a str,
+    pub user_id: Option<i32>,
+    pub currency: &'a str,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct LedgerTransaction {
+    pub id: i32,
+    pub transaction_type: String,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "ledger_transactions"]
+pub struct NewLedgerTransaction<'a> {
+    pub transaction_type: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "ledger_entries"]
+pub struct NewLedgerEntry {
+    pub transaction_id: i32,
+    pub account_id: i32,
+    pub amount: i64,
+}
+
+#[derive(Queryable)]
+pub struct StripeEvent {
+    pub id: String,
+    pub event_type: String,
+    pub payload: String,
+    pub received: std::time::SystemTime,
+    pub processed_at: Option<std::time::SystemTime>,
+    pub error: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "stripe_events"]
+pub struct NewStripeEvent<'a> {
+    pub id: &'a str,
+    pub event_type: &'a str,
+    pub payload: &'a str,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct TokenEconomics {
+    pub id: i32,
+    pub effective_from: std::time::SystemTime,
+    pub token_value: i32,
+    pub currency: String,
+    pub channel_token_expiry_days: i32,
+    pub created: std::time::SystemTime,
+    pub token_rollover: String,
+    pub rollover_limit: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "token_economics"]
+pub struct NewTokenEconomics<'a> {
+    pub effective_from: std::time::SystemTime,
+    pub token_value: i32,
+    pub currency: &'a str,
+    pub channel_token_expiry_days: i32,
+    pub token_rollover: &'a str,
+    pub rollover_limit: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct TokenPlan {
+    pub id: i32,
+    pub economics_id: i32,
+    pub plan: String,
+    pub stripe_price_id: String,
+    pub monthly_tokens: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "token_plans"]
+pub struct NewTokenPlan<'a> {
+    pub economics_id: i32,
+    pub plan: &'a str,
+    pub stripe_price_id: &'a str,
+    pub monthly_tokens: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct JobRun {
+    pub id: i32,
+    pub job_name: String,
+    pub period: String,
+    pub status: String,
+    pub processed: i32,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub started_at: std::time::SystemTime,
+    pub finished_at: Option<std::time::SystemTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "job_runs"]
+pub struct NewJobRun<'a> {
+    pub job_name: &'a str,
+    pub period: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct JobRunWithDuration {
+    #[serde(flatten)]
+    pub run: JobRun,
+    pub duration_seconds: Option<u64>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct ScheduledJob {
+    pub id: i32,
+    pub name: String,
+    pub schedule: String,
+    pub paused: bool,
+    pub run_requested: bool,
+    pub max_attempts: i32,
+    pub retry_delay_seconds: i32,
+    pub next_run_at: Option<std::time::SystemTime>,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "scheduled_jobs"]
+pub struct NewScheduledJob<'a> {
+    pub name: &'a str,
+    pub schedule: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct ScheduledJobWithLastRun {
+    #[serde(flatten)]
+    pub job: ScheduledJob,
+    pub last_run: Option<JobRunWithDuration>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct TokenGrant {
+    pub id: i32,
+    pub user_id: i32,
+    pub period: String,
+    pub token_count: i32,
+    pub created: std::time::SystemTime,
+    pub period_end: Option<std::time::SystemTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "token_grants"]
+pub struct NewTokenGrant<'a> {
+    pub user_id: i32,
+    pub period: &'a str,
+    pub token_count: i32,
+    pub period_end: Option<std::time::SystemTime>,
+}
+
+#[derive(Serialize)]
+pub struct TokenEconomicsWithPlans {
+    pub economics: TokenEconomics,
+    pub plans: Vec<TokenPlan>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct Playlist {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub visibility: String,
+    pub watch_later: bool,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "playlists"]
+pub struct NewPlaylist<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub visibility: &'a str,
+    pub watch_later: bool,
+}
+
+#[derive(Serialize)]
+pub struct PlaylistWithVideos {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub visibility: String,
+    pub watch_later: bool,
+    pub created: std::time::SystemTime,
+    pub videos: Vec<VideoWithUser>,
+}
+
+#[derive(Insertable)]
+#[table_name = "playlists_videos"]
+pub struct NewPlaylistVideo {
+    pub playlist_id: i32,
+    pub video_id: i32,
+    pub position: i32,
 }
 
 #[derive(Serialize, Queryable)]
@@ -317,3 +718,135 @@ impl FromSql<Record<(Integer, VarChar)>, diesel::pg::Pg> for Tag {
     }
 }
 
+#[derive(Queryable, Serialize, Clone)]
+pub struct WithdrawalRequest {
+    pub id: i32,
+    pub channel_user_id: i32,
+    pub idempotency_key: String,
+    pub amount: i32,
+    pub currency: String,
+    pub status: String,
+    pub stripe_transfer_id: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created: std::time::SystemTime,
+    pub updated: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "withdrawal_requests"]
+pub struct NewWithdrawalRequest<'a> {
+    pub channel_user_id: i32,
+    pub idempotency_key: &'a str,
+    pub amount: i32,
+    pub currency: &'a str,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct PayoutPreference {
+    pub user_id: i32,
+    pub schedule: String,
+    pub minimum_amount: i32,
+    pub updated: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "payout_preferences"]
+pub struct NewPayoutPreference<'a> {
+    pub user_id: i32,
+    pub schedule: &'a str,
+    pub minimum_amount: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct Notification {
+    pub id: i32,
+    pub user_id: i32,
+    pub notification_type: String,
+    pub data: String,
+    pub created: std::time::SystemTime,
+    pub read_at: Option<std::time::SystemTime>,
+    pub group_key: Option<String>,
+    pub event_count: i32,
+    pub updated: std::time::SystemTime,
+    pub actor_ids: Vec<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "notifications"]
+pub struct NewNotification<'a> {
+    pub user_id: i32,
+    pub notification_type: &'a str,
+    pub data: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "progress_events"]
+pub struct NewProgressEvent {
+    pub user_id: i32,
+    pub video_id: i32,
+    pub from_position: i32,
+    pub to_position: i32,
+    pub watched_seconds: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct AutoRenewal {
+    pub id: i32,
+    pub user_id: i32,
+    pub channel_user_id: i32,
+    pub priority: i32,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "auto_renewals"]
+pub struct NewAutoRenewal {
+    pub user_id: i32,
+    pub channel_user_id: i32,
+    pub priority: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct TokenExpiration {
+    pub id: i32,
+    pub user_id: i32,
+    pub grant_id: i32,
+    pub economics_id: i32,
+    pub token_rollover: String,
+    pub expired_count: i32,
+    pub kept_count: i32,
+    pub created: std::time::SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "token_expirations"]
+pub struct NewTokenExpiration<'a> {
+    pub user_id: i32,
+    pub grant_id: i32,
+    pub economics_id: i32,
+    pub token_rollover: &'a str,
+    pub expired_count: i32,
+    pub kept_count: i32,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct NotificationPreference {
+    pub user_id: i32,
+    pub notification_type: String,
+    pub enabled: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "notification_preferences"]
+pub struct NewNotificationPreference<'a> {
+    pub user_id: i32,
+    pub notification_type: &'a str,
+    pub enabled: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "video_announcements"]
+pub struct NewVideoAnnouncement {
+    pub video_id: i32,
+    pub supporter_count: i32,
+}
@@ -0,0 +1,126 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, Responder, web};
+use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::{AppState, establish_connection};
+use crate::helpers::analytics::{get_channel_series, get_retention_curve, get_video_series, get_video_totals, parse_range};
+use crate::schema::videos::dsl::{user_id, videos};
+
+#[derive(Deserialize)]
+pub struct AnalyticsQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub granularity: Option<String>,
+}
+
+// Plays, unique viewers, watch time, new supporters, votes, comments and earnings for each day
+// or week, e.g. /analytics/channel?from=2021-06-01&to=2021-06-30&granularity=week
+#[get("/channel")]
+pub async fn get_channel_analytics(params: web::Query<AnalyticsQuery>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "CHANNEL" {
+        return HttpResponse::Forbidden().json("Only channels have analytics.");
+    }
+
+    let range = match parse_range(params.from.as_deref(), params.to.as_deref(), params.granularity.as_deref()) {
+        Ok(v) => v,
+        Err(e) => { return HttpResponse::BadRequest().json(e); }
+    };
+
+    let db = establish_connection();
+
+    let result = get_channel_series(&db, user.id, &range)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+#[get("/channel/videos")]
+pub async fn get_channel_video_analytics(params: web::Query<AnalyticsQuery>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "CHANNEL" {
+        return HttpResponse::Forbidden().json("Only channels have analytics.");
+    }
+
+    let range = match parse_range(params.from.as_deref(), params.to.as_deref(), params.granularity.as_deref()) {
+        Ok(v) => v,
+        Err(e) => { return HttpResponse::BadRequest().json(e); }
+    };
+
+    let db = establish_connection();
+
+    let result = get_video_totals(&db, user.id, &range)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+#[derive(Deserialize)]
+pub struct VideoAnalyticsParams {
+    video_id: i32
+}
+
+#[get("/channel/videos/{video_id}")]
+pub async fn get_video_analytics(path: web::Path<VideoAnalyticsParams>, params: web::Query<AnalyticsQuery>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let range = match parse_range(params.from.as_deref(), params.to.as_deref(), params.granularity.as_deref()) {
+        Ok(v) => v,
+        Err(e) => { return HttpResponse::BadRequest().json(e); }
+    };
+
+    let db = establish_connection();
+
+    let owner: Option<i32> = videos
+        .find(path.video_id)
+        .select(user_id)
+        .first::<i32>(&db)
+        .optional()
+        .expect("Query failed");
+
+    match owner {
+        Some(owner) if owner == user.id => {}
+        Some(_) => { return HttpResponse::Forbidden().json("You can only see analytics for your own videos."); }
+        None => { return HttpResponse::NotFound().json("Video not found"); }
+    }
+
+    let result = get_video_series(&db, path.video_id, &range)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+// How much of the video viewers watched, next to the median for the channel's other videos
+#[get("/channel/videos/{video_id}/retention")]
+pub async fn get_video_retention(path: web::Path<VideoAnalyticsParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let owner: Option<i32> = videos
+        .find(path.video_id)
+        .select(user_id)
+        .first::<i32>(&db)
+        .optional()
+        .expect("Query failed");
+
+    match owner {
+        Some(owner) if owner == user.id => {}
+        Some(_) => { return HttpResponse::Forbidden().json("You can only see analytics for your own videos."); }
+        None => { return HttpResponse::NotFound().json("Video not found"); }
+    }
+
+    let result = get_retention_curve(&db, user.id, path.video_id)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
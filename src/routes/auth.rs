@@ -8,16 +8,16 @@ use jsonwebtoken::{encode, EncodingKey, Header};
 use rand::{Rng, thread_rng};
 use rand::distributions::Alphanumeric;
 use serde::Deserialize;
-use stripe::{AccountType, CollectionMethod, RequestedCapability};
 use validator::Validate;
 
 use crate::{AppState, establish_connection};
 use crate::claims::user;
 use crate::diesel::RunQueryDsl;
-use crate::helpers::stripe::create_account_link;
-use crate::helpers::tokens::add_tokens;
+use crate::helpers::payments::create_onboarding_link;
+use crate::helpers::users::{delete_user, set_up_channel_account, set_up_subscriber_billing};
+use crate::helpers::economics::{DEFAULT_PLAN, get_current_economics, get_plan};
 use crate::models::{NewUser, User};
-use crate::schema::users::columns::{channel_onboarded, email, id, password, password_reset_token, stripe_account, stripe_customer, username};
+use crate::schema::users::columns::{email, id, password, password_reset_token, username};
 use crate::schema::users::dsl::users;
 
 #[derive(Deserialize)]
@@ -71,6 +71,9 @@ pub async fn login(data: web::Json<LoginInfo>) -> impl Responder {
     HttpResponse::BadRequest().json("Password incorrect!")
 }
 
+// The user types anyone can sign up as
+const REGISTRATION_USER_TYPES: [&str; 2] = ["SUBSCRIBER", "CHANNEL"];
+
 #[derive(Deserialize, Validate)]
 pub struct RegisterInfo {
     #[validate(length(min = 1))]
@@ -79,9 +82,10 @@ pub struct RegisterInfo {
     email: String,
     #[validate(length(min = 1))]
     password: String,
+    // SUBSCRIBER or CHANNEL, admins are created directly in the database
     user_type: String,
-    // TODO: validate this is either channel or subscriber
     payment_method_id: Option<String>,
+    plan: Option<String>,
 }
 
 #[post("/register")]
@@ -93,6 +97,10 @@ pub async fn register(data: web::Json<RegisterInfo>) -> impl Responder {
         }
     }
 
+    if !REGISTRATION_USER_TYPES.contains(&data.user_type.as_str()) {
+        return HttpResponse::BadRequest().body("User type must be SUBSCRIBER or CHANNEL");
+    }
+
     let hashed_password = hash(&data.password, 4);
     let hashed_password = match hashed_password {
         Ok(v) => v,
@@ -103,11 +111,20 @@ pub async fn register(data: web::Json<RegisterInfo>) -> impl Responder {
 
     let db = establish_connection();
 
+    let economics = get_current_economics(&db);
+    let plan_name = data.plan.as_deref().unwrap_or(DEFAULT_PLAN);
+
+    let plan = match get_plan(&db, economics.id, plan_name) {
+        Some(v) => v,
+        None => { return HttpResponse::BadRequest().body("Unknown plan"); }
+    };
+
     let new_user = NewUser {
         username: &data.username,
         password: &hashed_password,
         email: &data.email,
         user_type: &data.user_type,
+        plan: &plan.plan,
     };
 
     let result: QueryResult<Vec<i32>> = diesel::insert_into(users)
@@ -122,77 +139,41 @@ pub async fn register(data: web::Json<RegisterInfo>) -> impl Responder {
         }
     };
 
-    let secret_key = std::env::var("STRIPE_SECRET").expect("Missing STRIPE_SECRET_KEY in env");
-    let client = stripe::Client::new(secret_key);
-
     let user_ids = result.unwrap();
     let user_id = user_ids.get(0).unwrap();
 
     if &data.user_type == "SUBSCRIBER" {
-        // Create a new client
-
-        let payment_method_id = data.payment_method_id.as_ref().unwrap();
-
-        // Create the customer
-        let mut params = stripe::CreateCustomer::new();
-        params.email = Some(&data.email);
-        params.payment_method = Some(payment_method_id.parse().unwrap()); // TODO: possibly not needed
-        let customer = stripe::Customer::create(&client, params).await.unwrap();
-
-
-        diesel::update(users.find(user_id))
-            .set(stripe_customer.eq(customer.id.as_str()))
-            .execute(&db)
-            .expect("Couldn't add stripe_customer to user");
-
-        // Subscription item
-        let mut item = stripe::CreateSubscriptionItems::new();
-        item.quantity = Some(1);
-        item.price = Some(String::from("price_1IQztpIahEIGROhzWnYhQv1I")); // TODO: .env file
-
-        // Params for the subscription
-        let mut sub_params = stripe::CreateSubscription::new(customer.id);
-        sub_params.collection_method = Some(CollectionMethod::ChargeAutomatically);
-        sub_params.items = Some(vec![item]);
-
-        let payment_method_id_copy = payment_method_id.clone();
-
-        sub_params.default_payment_method = Some(payment_method_id_copy.as_str());
-
-        stripe::Subscription::create(&client, sub_params)
-            .await
-            .expect("Failed to create subscription.");
+        let payment_method_id = match &data.payment_method_id {
+            Some(v) => v,
+            None => {
+                delete_user(&db, *user_id);
+                return HttpResponse::BadRequest().body("A payment method is needed to subscribe");
+            }
+        };
 
-        // TODO: assign tokens
-        add_tokens(*user_id, 5);
+        if let Err(e) = set_up_subscriber_billing(&db, *user_id, &data.email, payment_method_id, &plan.stripe_price_id).await {
+            delete_user(&db, *user_id);
+            return HttpResponse::BadRequest().body(e);
+        }
     } else {
         // Channel signup
-
-        // Create stripe account
-
-        let mut params = stripe::CreateAccount::new();
-        params.type_ = Option::from(AccountType::Express);
-        params.country = Option::from("GB");
-        params.email = Option::from(data.email.as_str());
-        params.requested_capabilities = Some(vec![RequestedCapability::CardPayments, RequestedCapability::Transfers]);
-
-        let account = stripe::Account::create(&client, params).await.unwrap();
-
-        diesel::update(users.find(user_id))
-            .set(stripe_account.eq(account.id.as_str()))
-            .execute(&db)
-            .expect("Couldn't add stripe_customer to user");
+        let account_id = match set_up_channel_account(&db, *user_id, &data.email).await {
+            Ok(v) => v,
+            Err(e) => {
+                delete_user(&db, *user_id);
+                return HttpResponse::BadRequest().body(e);
+            }
+        };
 
         // Create onboard link
-
-        let result = create_account_link(&*account.id).await;
+        let result = create_onboarding_link(&account_id).await;
 
         return match result {
             Ok(url) => {
                 HttpResponse::Ok().json(url)
             }
             Err(e) => {
-                HttpResponse::BadRequest().json(e)
+                HttpResponse::BadRequest().json(e.to_string())
             }
         };
     }
@@ -326,40 +307,6 @@ pub async fn reset_password(data: web::Json<ResetPasswordInfo>) -> impl Responde
     };
 }
 
-#[derive(Deserialize)]
-pub struct Object {
-    pub object: stripe::Account
-}
-
-#[derive(Deserialize)]
-pub struct OnBoardCompleteWebhookBody {
-    pub data: Object
-}
-
-#[post("/account-updated")]
-pub async fn stripe_account_updated_hook(data: web::Json<OnBoardCompleteWebhookBody>) -> impl Responder {
-    let email_ = data.data.object.email.as_ref().unwrap();
-    let payout_enabled = data.data.object.payouts_enabled.unwrap();
-    let db = establish_connection();
-
-    if payout_enabled {
-        let update_result = diesel::update(users.filter(email.eq(email_)))
-            .set(channel_onboarded.eq(true))
-            .execute(&db);
-
-        return match update_result {
-            Ok(_) => {
-                HttpResponse::Ok().json("Success")
-            }
-            Err(_) => {
-                HttpResponse::NotFound().json("User does not exist.")
-            }
-        };
-    }
-
-    HttpResponse::Ok().json("Payouts not enabled")
-}
-
 #[get("/onboarded")]
 pub async fn is_channel_onboarded(state: web::Data<Mutex<AppState>>) -> impl Responder {
     let db = establish_connection();
@@ -0,0 +1,207 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, establish_connection};
+use crate::helpers::economics::{get_current_economics, get_plan};
+use crate::helpers::payments::{payments, PaymentsError};
+use crate::helpers::subscriptions::{backfill_subscription, clear_ended_subscription, set_cancel_at_period_end, subscription_created};
+use crate::helpers::users::get_user_by_id;
+use crate::models::User;
+
+#[derive(Serialize)]
+pub struct BillingStatus {
+    plan: String,
+    subscription_status: String,
+    subscribed: bool,
+    cancel_at_period_end: bool,
+    current_period_end: Option<std::time::SystemTime>,
+    grace_period_ends: Option<std::time::SystemTime>,
+}
+
+#[get("/")]
+pub async fn get_billing(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let user = get_user_by_id(&db, user.id).unwrap();
+
+    HttpResponse::Ok().json(BillingStatus {
+        plan: user.plan,
+        subscription_status: user.subscription_status,
+        subscribed: user.subscribed,
+        cancel_at_period_end: user.cancel_at_period_end,
+        current_period_end: user.current_period_end,
+        grace_period_ends: user.grace_period_ends,
+    })
+}
+
+// Subscribers from before the subscription lifecycle are ACTIVE without a stripe_subscription,
+// so it is looked up before anything is done with it. Returns the user as it is afterwards.
+async fn with_backfilled_subscription(db: &PgConnection, user: User) -> Result<User, PaymentsError> {
+    let subscribed_status = user.subscription_status == "ACTIVE" || user.subscription_status == "PAST_DUE";
+
+    if user.stripe_subscription.is_some() || !subscribed_status || user.stripe_customer == "" {
+        return Ok(user);
+    }
+
+    let subscription = payments().get_customer_subscription(&user.stripe_customer).await?;
+
+    backfill_subscription(db, &user, subscription.as_ref())
+        .expect("Query failed");
+
+    Ok(get_user_by_id(db, user.id).unwrap())
+}
+
+// The subscription carries on until the end of the period which has been paid for
+#[post("/cancel")]
+pub async fn cancel_subscription(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    // The guard mustn't be held across the Stripe calls below, see CheckLogin
+    let user_id = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        user.id
+    };
+
+    let db = establish_connection();
+
+    let user = match with_backfilled_subscription(&db, get_user_by_id(&db, user_id).unwrap()).await {
+        Ok(v) => v,
+        Err(_) => { return HttpResponse::ServiceUnavailable().json("Couldn't check your subscription, please try again."); }
+    };
+
+    let subscription_id = match &user.stripe_subscription {
+        Some(v) if user.subscribed => v,
+        _ => { return HttpResponse::BadRequest().json("You don't have an active subscription."); }
+    };
+
+    match payments().set_subscription_cancel_at_period_end(subscription_id, true).await {
+        Ok(subscription) => {
+            set_cancel_at_period_end(&db, &user, subscription.cancel_at_period_end)
+                .expect("Query failed");
+
+            HttpResponse::Ok().json("Subscription will be cancelled at the end of the billing period")
+        }
+        Err(_) => HttpResponse::BadRequest().json("Couldn't cancel the subscription")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResumeSubscriptionBody {
+    // Needed if the old payment method shouldn't be used
+    pub payment_method_id: Option<String>
+}
+
+// Undoes a cancellation which hasn't taken effect yet, or starts a new subscription if the old
+// one has ended
+#[post("/resume")]
+pub async fn resume_subscription(data: web::Json<ResumeSubscriptionBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    // The guard mustn't be held across the Stripe calls below, see CheckLogin
+    let user_id = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        user.id
+    };
+
+    let db = establish_connection();
+
+    let user = match with_backfilled_subscription(&db, get_user_by_id(&db, user_id).unwrap()).await {
+        Ok(v) => v,
+        Err(_) => { return HttpResponse::ServiceUnavailable().json("Couldn't check your subscription, please try again."); }
+    };
+
+    if user.stripe_customer == "" {
+        return HttpResponse::BadRequest().json("Only subscribers can resume a subscription.");
+    }
+
+    if let Some(payment_method_id) = &data.payment_method_id {
+        let updated = payments().set_default_payment_method(&user.stripe_customer, None, payment_method_id).await;
+
+        if updated.is_err() {
+            return HttpResponse::BadRequest().json("Couldn't use that payment method");
+        }
+    }
+
+    match (&user.stripe_subscription, user.subscription_status.as_str()) {
+        (Some(subscription_id), "ACTIVE") | (Some(subscription_id), "PAST_DUE") => {
+            if !user.cancel_at_period_end {
+                return HttpResponse::BadRequest().json("Your subscription isn't cancelled.");
+            }
+
+            match payments().set_subscription_cancel_at_period_end(subscription_id, false).await {
+                Ok(subscription) => {
+                    set_cancel_at_period_end(&db, &user, subscription.cancel_at_period_end)
+                        .expect("Query failed");
+
+                    HttpResponse::Ok().json("Subscription resumed")
+                }
+                Err(_) => HttpResponse::BadRequest().json("Couldn't resume the subscription")
+            }
+        }
+        // Starting another subscription would bill the subscriber twice
+        (None, "ACTIVE") | (None, "PAST_DUE") => HttpResponse::Conflict().json("Your subscription can't be resumed right now, please contact support."),
+        (_, "CANCELED") | (None, _) => {
+            let economics = get_current_economics(&db);
+
+            let plan = match get_plan(&db, economics.id, &user.plan) {
+                Some(v) => v,
+                None => { return HttpResponse::BadRequest().json("Your plan is no longer available."); }
+            };
+
+            clear_ended_subscription(&db, &user)
+                .expect("Query failed");
+
+            match payments().create_subscription(&user.stripe_customer, &plan.stripe_price_id, None).await {
+                Ok(subscription) => {
+                    // Marked as active, and tokens granted, once the first invoice is paid
+                    subscription_created(&db, user.id, &subscription.id)
+                        .expect("Query failed");
+
+                    HttpResponse::Ok().json(subscription.status)
+                }
+                Err(_) => HttpResponse::BadRequest().json("Couldn't start a subscription. Check your payment method.")
+            }
+        }
+        _ => HttpResponse::BadRequest().json("Update your payment method to resume your subscription.")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PaymentMethodBody {
+    pub payment_method_id: String
+}
+
+// Stripe retries failed invoices with the new payment method, which ends a grace period or a
+// suspension through the invoice.paid webhook
+#[post("/payment-method")]
+pub async fn update_payment_method(data: web::Json<PaymentMethodBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    // The guard mustn't be held across the Stripe call below, see CheckLogin
+    let user_id = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        user.id
+    };
+
+    let db = establish_connection();
+
+    let user = get_user_by_id(&db, user_id).unwrap();
+
+    if user.stripe_customer == "" {
+        return HttpResponse::BadRequest().json("Only subscribers have a payment method.");
+    }
+
+    let subscription_id = match user.subscription_status.as_str() {
+        "CANCELED" => None,
+        _ => user.stripe_subscription.as_deref()
+    };
+
+    match payments().set_default_payment_method(&user.stripe_customer, subscription_id, &data.payment_method_id).await {
+        Ok(_) => HttpResponse::Ok().json("Payment method updated"),
+        Err(_) => HttpResponse::BadRequest().json("Couldn't use that payment method")
+    }
+}
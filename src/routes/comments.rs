@@ -2,19 +2,19 @@ use std::borrow::Borrow;
 use std::sync::Mutex;
 
 use actix_web::{get, HttpResponse, post, Responder, web};
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, JoinOnDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
 use diesel::dsl::exists;
-use crate::diesel::GroupByDsl;
 use serde::Deserialize;
+use serde_json::json;
 use validator::Validate;
 
 use crate::{AppState, establish_connection};
-use crate::models::{CommentWithUser, NewComment, get_safe_user_fields};
+use crate::helpers::notifications::{COMMENT_CREATED, notify};
+use crate::models::{Comment, CommentWithUser, NewComment, get_safe_user_fields};
 use crate::schema::comment_upvotes::dsl::{comment_id, comment_upvotes, upvote_type};
 use crate::schema::comments::columns::{id, inactive, text, user_id, video_id};
 use crate::schema::comments::dsl::comments;
 use crate::schema::users::dsl::users;
-use crate::schema::channels_tokens::dsl::channels_tokens;
 
 #[derive(Deserialize, Validate)]
 pub struct CreateCommentInfo {
@@ -36,9 +36,27 @@ pub async fn create_comment(data: web::Json<CreateCommentInfo>, state: web::Data
         video_id: data.video,
     };
 
-    let result = diesel::insert_into(comments)
-        .values(new_comment)
-        .execute(&db);
+    let result: QueryResult<Comment> = db.transaction(|| {
+        let comment: Comment = diesel::insert_into(comments)
+            .values(new_comment)
+            .get_result(&db)?;
+
+        let owner: i32 = crate::schema::videos::table
+            .find(comment.video_id)
+            .select(crate::schema::videos::user_id)
+            .first(&db)?;
+
+        if owner != user.id {
+            notify(&db, owner, COMMENT_CREATED, json!({
+                "video_id": comment.video_id,
+                "comment_id": comment.id,
+                "user_id": user.id,
+                "username": user.username,
+            }))?;
+        }
+
+        Ok(comment)
+    });
 
     return match result {
         Ok(_) => HttpResponse::Ok().json("Comment added"),
@@ -112,7 +130,6 @@ pub async fn get_comments(params: web::Path<GetCommentsParams>, state: web::Data
 
     let result: Vec<CommentWithUser> = comments
         .inner_join(users)
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
         .select(
             (
                 crate::schema::comments::id,
@@ -134,13 +151,11 @@ pub async fn get_comments(params: web::Path<GetCommentsParams>, state: web::Data
                         .and(crate::schema::comment_upvotes::user_id.eq(user.id))
                     )
                 ),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_comment_upvotes(comments.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_comment_downvotes(comments.id)"),
+                crate::schema::comments::upvote_count,
+                crate::schema::comments::downvote_count,
             )
         )
-        .distinct()
         .filter(inactive.eq(false).and(video_id.eq(params.video_id)))
-        .group_by((crate::schema::channels_tokens::id, crate::schema::comments::id, crate::schema::users::id))
         .load::<CommentWithUser>(&db)
         .expect("Query failed.");
 
@@ -0,0 +1,141 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::{Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{AppState, establish_connection};
+use crate::helpers::economics::get_plans;
+use crate::helpers::expiry::ROLLOVER_POLICIES;
+use crate::models::{NewTokenEconomics, NewTokenPlan, TokenEconomics, TokenEconomicsWithPlans};
+use crate::schema::token_economics::dsl::{effective_from, token_economics};
+use crate::schema::token_plans::dsl::token_plans;
+
+#[get("/economics")]
+pub async fn list_economics(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage token economics.");
+    }
+
+    let db = establish_connection();
+
+    let result: Vec<TokenEconomics> = token_economics
+        .order_by(effective_from.desc())
+        .load::<TokenEconomics>(&db)
+        .expect("Query failed");
+
+    let result: Vec<TokenEconomicsWithPlans> = result.into_iter().map(|economics| {
+        let plans = get_plans(&db, economics.id);
+
+        TokenEconomicsWithPlans {
+            economics,
+            plans,
+        }
+    }).collect();
+
+    HttpResponse::Ok().json(result)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateTokenPlanBody {
+    #[validate(length(min = 1, max = 32))]
+    pub plan: String,
+    #[validate(length(min = 1, max = 64))]
+    pub stripe_price_id: String,
+    #[validate(range(min = 0))]
+    pub monthly_tokens: i32
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateTokenEconomicsBody {
+    // Seconds since the unix epoch. Defaults to now.
+    pub effective_from: Option<u64>,
+    #[validate(range(min = 0))]
+    pub token_value: i32,
+    #[validate(length(equal = 3))]
+    pub currency: String,
+    #[validate(range(min = 1))]
+    pub channel_token_expiry_days: i32,
+    // EXPIRE, ROLLOVER or NEVER, defaults to NEVER
+    pub token_rollover: Option<String>,
+    #[validate(range(min = 0))]
+    pub rollover_limit: Option<i32>,
+    #[validate]
+    pub plans: Vec<CreateTokenPlanBody>
+}
+
+// Adds a new version of the token economics. Versions can't start in the past, so
+// conversions which have already happened keep the rates they were made with.
+#[post("/economics")]
+pub async fn create_economics(data: web::Json<CreateTokenEconomicsBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage token economics.");
+    }
+
+    if data.validate().is_err() || data.plans.len() == 0 {
+        return HttpResponse::BadRequest().json("Invalid token economics");
+    }
+
+    let rollover = data.token_rollover.as_deref().unwrap_or("NEVER");
+
+    if !ROLLOVER_POLICIES.contains(&rollover) {
+        return HttpResponse::BadRequest().json("Token rollover must be EXPIRE, ROLLOVER or NEVER");
+    }
+
+    let now = SystemTime::now();
+
+    let starts = match data.effective_from {
+        Some(v) => UNIX_EPOCH + Duration::from_secs(v),
+        None => now
+    };
+
+    if starts < now - Duration::from_secs(60) {
+        return HttpResponse::BadRequest().json("Token economics can't take effect in the past");
+    }
+
+    let db = establish_connection();
+
+    let currency = data.currency.to_lowercase();
+
+    let result: QueryResult<TokenEconomics> = db.transaction(|| {
+        let economics: TokenEconomics = diesel::insert_into(token_economics)
+            .values(NewTokenEconomics {
+                effective_from: starts,
+                token_value: data.token_value,
+                currency: &currency,
+                channel_token_expiry_days: data.channel_token_expiry_days,
+                token_rollover: rollover,
+                rollover_limit: data.rollover_limit.unwrap_or(0),
+            })
+            .get_result(&db)?;
+
+        let new_plans: Vec<NewTokenPlan> = data.plans.iter().map(|p| {
+            NewTokenPlan {
+                economics_id: economics.id,
+                plan: &p.plan,
+                stripe_price_id: &p.stripe_price_id,
+                monthly_tokens: p.monthly_tokens,
+            }
+        }).collect();
+
+        diesel::insert_into(token_plans)
+            .values(&new_plans)
+            .execute(&db)?;
+
+        Ok(economics)
+    });
+
+    match result {
+        Ok(economics) => HttpResponse::Ok().json(economics),
+        Err(_) => HttpResponse::BadRequest().json("Couldn't create token economics. Plan names must be unique.")
+    }
+}
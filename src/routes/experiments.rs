@@ -0,0 +1,123 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::{Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{AppState, establish_connection};
+use crate::helpers::experiments::get_experiments;
+use crate::models::{Experiment, NewExperiment, NewExperimentVariant};
+use crate::schema::experiment_variants::dsl::experiment_variants;
+use crate::schema::experiments::dsl::{active, experiments};
+
+/*
+ * Experiment definitions for admins. Variants can't be changed once an experiment has been
+ * created, as that would move users between variants. Create a new experiment instead.
+ */
+
+#[get("/experiments")]
+pub async fn list_experiments(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage experiments.");
+    }
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_experiments(&db, false))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateExperimentVariant {
+    #[validate(length(min = 1, max = 128))]
+    pub name: String,
+    #[validate(range(min = 1))]
+    pub weight: i32
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateExperimentBody {
+    #[validate(length(min = 1, max = 128))]
+    pub name: String,
+    #[validate(length(max = 1024))]
+    pub description: Option<String>,
+    #[validate]
+    pub variants: Vec<CreateExperimentVariant>
+}
+
+#[post("/experiments")]
+pub async fn create_experiment(data: web::Json<CreateExperimentBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage experiments.");
+    }
+
+    if data.validate().is_err() || data.variants.len() < 2 {
+        return HttpResponse::BadRequest().json("An experiment needs a name and at least two variants with positive weights");
+    }
+
+    let db = establish_connection();
+
+    // Experiments start inactive so they can be checked before users are exposed
+    let result: QueryResult<Experiment> = db.transaction(|| {
+        let experiment: Experiment = diesel::insert_into(experiments)
+            .values(NewExperiment {
+                name: &data.name,
+                description: data.description.as_deref(),
+            })
+            .get_result(&db)?;
+
+        let new_variants: Vec<NewExperimentVariant> = data.variants.iter().map(|variant| {
+            NewExperimentVariant {
+                experiment_id: experiment.id,
+                name: &variant.name,
+                weight: variant.weight,
+            }
+        }).collect();
+
+        diesel::insert_into(experiment_variants)
+            .values(&new_variants)
+            .execute(&db)?;
+
+        Ok(experiment)
+    });
+
+    match result {
+        Ok(experiment) => HttpResponse::Ok().json(experiment),
+        Err(_) => HttpResponse::BadRequest().json("Experiment and variant names must be unique")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetExperimentActiveBody {
+    pub experiment: i32,
+    pub active: bool
+}
+
+#[post("/experiments/active")]
+pub async fn set_experiment_active(data: web::Json<SetExperimentActiveBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage experiments.");
+    }
+
+    let db = establish_connection();
+
+    let result = diesel::update(experiments.find(data.experiment))
+        .set(active.eq(data.active))
+        .execute(&db);
+
+    match result {
+        Ok(0) => HttpResponse::NotFound().json("Experiment does not exist"),
+        Ok(_) => HttpResponse::Ok().json("Experiment updated"),
+        Err(_) => HttpResponse::InternalServerError().json("Couldn't update experiment")
+    }
+}
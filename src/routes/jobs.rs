@@ -0,0 +1,158 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{AppState, establish_connection};
+use crate::helpers::jobs::{get_job_runs as get_runs, get_scheduled_job, get_scheduled_jobs};
+use crate::jobs::scheduler::is_valid_schedule;
+use crate::models::ScheduledJobWithLastRun;
+use crate::schema::scheduled_jobs::dsl::{name, next_run_at, paused, run_requested, schedule, scheduled_jobs};
+
+/*
+ * Background job controls for admins. The scheduler picks up changes on its next tick,
+ * see jobs::scheduler.
+ */
+
+#[get("/jobs")]
+pub async fn list_jobs(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage jobs.");
+    }
+
+    let db = establish_connection();
+
+    let result: Vec<ScheduledJobWithLastRun> = get_scheduled_jobs(&db).into_iter().map(|job| {
+        let last_run = get_runs(&db, &job.name, 1).into_iter().next();
+
+        ScheduledJobWithLastRun {
+            job,
+            last_run,
+        }
+    }).collect();
+
+    HttpResponse::Ok().json(result)
+}
+
+#[derive(Deserialize)]
+pub struct GetJobRunsParams {
+    job_name: String
+}
+
+#[get("/jobs/{job_name}/runs")]
+pub async fn get_job_runs(params: web::Path<GetJobRunsParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage jobs.");
+    }
+
+    let db = establish_connection();
+
+    if get_scheduled_job(&db, &params.job_name).is_none() {
+        return HttpResponse::NotFound().json("Job not found");
+    }
+
+    HttpResponse::Ok().json(get_runs(&db, &params.job_name, 50))
+}
+
+#[derive(Deserialize)]
+pub struct JobBody {
+    pub name: String
+}
+
+// Runs the job on the next tick, even if it is paused
+#[post("/jobs/run")]
+pub async fn run_job(data: web::Json<JobBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage jobs.");
+    }
+
+    let db = establish_connection();
+
+    let updated = diesel::update(scheduled_jobs.filter(name.eq(&data.name)))
+        .set(run_requested.eq(true))
+        .execute(&db)
+        .expect("Query failed");
+
+    if updated == 0 {
+        return HttpResponse::NotFound().json("Job not found");
+    }
+
+    HttpResponse::Ok().json("Job will run shortly")
+}
+
+#[derive(Deserialize)]
+pub struct PauseJobBody {
+    pub name: String,
+    pub paused: bool
+}
+
+#[post("/jobs/pause")]
+pub async fn pause_job(data: web::Json<PauseJobBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage jobs.");
+    }
+
+    let db = establish_connection();
+
+    // Clearing next_run_at on resume stops the runs missed while paused from firing straight away
+    let updated = diesel::update(scheduled_jobs.filter(name.eq(&data.name)))
+        .set((paused.eq(data.paused), next_run_at.eq(None::<SystemTime>)))
+        .execute(&db)
+        .expect("Query failed");
+
+    if updated == 0 {
+        return HttpResponse::NotFound().json("Job not found");
+    }
+
+    HttpResponse::Ok().json("Job updated")
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SetJobScheduleBody {
+    pub name: String,
+    #[validate(length(min = 1, max = 64))]
+    pub schedule: String
+}
+
+#[post("/jobs/schedule")]
+pub async fn set_job_schedule(data: web::Json<SetJobScheduleBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage jobs.");
+    }
+
+    if data.validate().is_err() || !is_valid_schedule(&data.schedule) {
+        return HttpResponse::BadRequest().json("Invalid schedule. Use a cron expression with seconds, e.g. 0 0 3 * * *");
+    }
+
+    let db = establish_connection();
+
+    let updated = diesel::update(scheduled_jobs.filter(name.eq(&data.name)))
+        .set((schedule.eq(&data.schedule), next_run_at.eq(None::<SystemTime>)))
+        .execute(&db)
+        .expect("Query failed");
+
+    if updated == 0 {
+        return HttpResponse::NotFound().json("Job not found");
+    }
+
+    HttpResponse::Ok().json("Job updated")
+}
@@ -4,4 +4,14 @@ pub mod upload;
 pub mod tokens;
 pub mod comments;
 pub mod upvotes;
-pub mod users;
\ No newline at end of file
+pub mod users;
+pub mod tags;
+pub mod playlists;
+pub mod recommendations;
+pub mod experiments;
+pub mod economics;
+pub mod jobs;
+pub mod webhooks;
+pub mod billing;
+pub mod notifications;
+pub mod analytics;
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, establish_connection};
+use crate::helpers::notifications::{get_notification_preferences, get_notifications, get_unread_count, is_notification_type, mark_all_read, mark_read, NotificationWithData, set_notification_preference};
+
+const NOTIFICATIONS_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct GetNotificationsQuery {
+    pub unread: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct GetMyNotificationsResponse {
+    notifications: Vec<NotificationWithData>,
+    unread_count: i64,
+}
+
+// e.g. /notifications/?unread=true for only the unread ones
+#[get("/")]
+pub async fn get_my_notifications(params: web::Query<GetNotificationsQuery>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(GetMyNotificationsResponse {
+        notifications: get_notifications(&db, user.id, params.unread.unwrap_or(false), NOTIFICATIONS_LIMIT),
+        unread_count: get_unread_count(&db, user.id),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct MarkReadBody {
+    notification: i32
+}
+
+#[post("/read")]
+pub async fn mark_notification_read(data: web::Json<MarkReadBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let found = mark_read(&db, user.id, data.notification)
+        .expect("Query failed");
+
+    if !found {
+        return HttpResponse::NotFound().json("Notification not found");
+    }
+
+    HttpResponse::Ok().json("Marked as read")
+}
+
+#[post("/read-all")]
+pub async fn mark_all_notifications_read(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let marked = mark_all_read(&db, user.id)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(marked)
+}
+
+#[get("/preferences")]
+pub async fn get_my_notification_preferences(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_notification_preferences(&db, user.id))
+}
+
+#[derive(Deserialize)]
+pub struct NotificationPreferenceBody {
+    notification_type: String,
+    enabled: bool,
+}
+
+#[post("/preferences")]
+pub async fn update_notification_preference(data: web::Json<NotificationPreferenceBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if !is_notification_type(&data.notification_type) {
+        return HttpResponse::BadRequest().json("Unknown notification type");
+    }
+
+    let db = establish_connection();
+
+    set_notification_preference(&db, user.id, &data.notification_type, data.enabled)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(get_notification_preferences(&db, user.id))
+}
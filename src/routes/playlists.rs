@@ -0,0 +1,320 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use diesel::dsl::max;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{AppState, establish_connection};
+use crate::helpers::playlists::{can_view_playlist, get_or_create_watch_later, get_owned_playlist, get_playlist, get_playlist_video_ids, is_valid_visibility, load_playlist_with_videos, set_playlist_order};
+use crate::models::{NewPlaylist, NewPlaylistVideo, Playlist};
+use crate::schema::playlists::dsl::{name, playlists, user_id, visibility};
+use crate::schema::playlists_videos::dsl::{playlist_id, playlists_videos, position, video_id};
+use crate::schema::videos::dsl::videos;
+
+#[get("/")]
+pub async fn get_my_playlists(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    // Make sure the watch later playlist shows up in the list
+    get_or_create_watch_later(&db, user.id);
+
+    let result: Vec<Playlist> = playlists
+        .filter(user_id.eq(user.id))
+        .order_by((crate::schema::playlists::watch_later.desc(), crate::schema::playlists::created.asc()))
+        .load::<Playlist>(&db)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+#[get("/watch-later")]
+pub async fn get_watch_later(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = get_or_create_watch_later(&db, user.id);
+
+    HttpResponse::Ok().json(load_playlist_with_videos(&db, playlist, user.id))
+}
+
+#[derive(Deserialize)]
+pub struct GetUserPlaylistsParams {
+    pub user_id: i32
+}
+
+// Only public playlists are listed on a user's profile
+#[get("/user/{user_id}")]
+pub async fn get_user_playlists(params: web::Path<GetUserPlaylistsParams>) -> impl Responder {
+    let db = establish_connection();
+
+    let result: Vec<Playlist> = playlists
+        .filter(user_id.eq(params.user_id).and(visibility.eq("PUBLIC")))
+        .order_by(crate::schema::playlists::created.asc())
+        .load::<Playlist>(&db)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+#[derive(Deserialize)]
+pub struct GetPlaylistParams {
+    pub playlist_id: i32
+}
+
+#[get("/{playlist_id}")]
+pub async fn get_playlist_videos(params: web::Path<GetPlaylistParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = match get_playlist(&db, params.playlist_id) {
+        Some(v) if can_view_playlist(&v, user.id) => v,
+        _ => { return HttpResponse::NotFound().json("Not found"); }
+    };
+
+    HttpResponse::Ok().json(load_playlist_with_videos(&db, playlist, user.id))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreatePlaylistBody {
+    #[validate(length(min = 1, max = 128))]
+    pub name: String,
+    pub visibility: Option<String>
+}
+
+#[post("/")]
+pub async fn create_playlist(data: web::Json<CreatePlaylistBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if data.validate().is_err() {
+        return HttpResponse::BadRequest().json("Invalid playlist name");
+    }
+
+    let new_visibility = match &data.visibility {
+        Some(v) => v.as_str(),
+        None => "PRIVATE"
+    };
+
+    if !is_valid_visibility(new_visibility) {
+        return HttpResponse::BadRequest().json("Visibility must be PUBLIC, UNLISTED or PRIVATE");
+    }
+
+    let db = establish_connection();
+
+    let new_playlist = NewPlaylist {
+        user_id: user.id,
+        name: &data.name,
+        visibility: new_visibility,
+        watch_later: false,
+    };
+
+    let result: QueryResult<Playlist> = diesel::insert_into(playlists)
+        .values(&new_playlist)
+        .get_result(&db);
+
+    match result {
+        Ok(playlist) => HttpResponse::Ok().json(playlist),
+        Err(_) => HttpResponse::BadRequest().json("Couldn't create playlist")
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UpdatePlaylistBody {
+    pub playlist: i32,
+    #[validate(length(min = 1, max = 128))]
+    pub name: Option<String>,
+    pub visibility: Option<String>
+}
+
+#[post("/update")]
+pub async fn update_playlist(data: web::Json<UpdatePlaylistBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if data.validate().is_err() {
+        return HttpResponse::BadRequest().json("Invalid playlist name");
+    }
+
+    let db = establish_connection();
+
+    let playlist = match get_owned_playlist(&db, data.playlist, user.id) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Playlist not found"); }
+    };
+
+    if playlist.watch_later {
+        return HttpResponse::BadRequest().json("The watch later playlist can't be changed");
+    }
+
+    if let Some(v) = &data.visibility {
+        if !is_valid_visibility(v) {
+            return HttpResponse::BadRequest().json("Visibility must be PUBLIC, UNLISTED or PRIVATE");
+        }
+
+        diesel::update(playlists.find(playlist.id))
+            .set(visibility.eq(v))
+            .execute(&db)
+            .expect("Query failed");
+    }
+
+    if let Some(n) = &data.name {
+        diesel::update(playlists.find(playlist.id))
+            .set(name.eq(n))
+            .execute(&db)
+            .expect("Query failed");
+    }
+
+    HttpResponse::Ok().json("Playlist updated")
+}
+
+#[derive(Deserialize)]
+pub struct DeletePlaylistBody {
+    pub playlist: i32
+}
+
+#[post("/delete")]
+pub async fn delete_playlist(data: web::Json<DeletePlaylistBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = match get_owned_playlist(&db, data.playlist, user.id) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Playlist not found"); }
+    };
+
+    if playlist.watch_later {
+        return HttpResponse::BadRequest().json("The watch later playlist can't be deleted");
+    }
+
+    // Videos in the playlist are removed by the cascading foreign key
+    diesel::delete(playlists.find(playlist.id))
+        .execute(&db)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json("Playlist deleted")
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistVideoBody {
+    pub playlist: i32,
+    pub video: i32
+}
+
+#[post("/add")]
+pub async fn add_playlist_video(data: web::Json<PlaylistVideoBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = match get_owned_playlist(&db, data.playlist, user.id) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Playlist not found"); }
+    };
+
+    let ready: i64 = videos
+        .filter(crate::schema::videos::id.eq(data.video).and(crate::schema::videos::status.eq("READY")))
+        .count()
+        .get_result(&db)
+        .expect("Query failed");
+
+    if ready == 0 {
+        return HttpResponse::NotFound().json("Video not found");
+    }
+
+    // New videos go to the end of the playlist
+    let last_position: Option<i32> = playlists_videos
+        .filter(playlist_id.eq(playlist.id))
+        .select(max(position))
+        .first(&db)
+        .expect("Query failed");
+
+    let new_playlist_video = NewPlaylistVideo {
+        playlist_id: playlist.id,
+        video_id: data.video,
+        position: last_position.map_or(0, |p| p + 1),
+    };
+
+    let result = diesel::insert_into(playlists_videos)
+        .values(&new_playlist_video)
+        .execute(&db);
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("Video added"),
+        Err(_) => HttpResponse::BadRequest().json("Video is already in this playlist")
+    }
+}
+
+#[post("/remove")]
+pub async fn remove_playlist_video(data: web::Json<PlaylistVideoBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = match get_owned_playlist(&db, data.playlist, user.id) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Playlist not found"); }
+    };
+
+    let removed = diesel::delete(playlists_videos.filter(playlist_id.eq(playlist.id).and(video_id.eq(data.video))))
+        .execute(&db)
+        .expect("Query failed");
+
+    if removed == 0 {
+        return HttpResponse::NotFound().json("Video is not in this playlist");
+    }
+
+    // Close the gap left by the removed video
+    let remaining = get_playlist_video_ids(&db, playlist.id);
+    set_playlist_order(&db, playlist.id, &remaining)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json("Video removed")
+}
+
+#[derive(Deserialize)]
+pub struct ReorderPlaylistBody {
+    pub playlist: i32,
+    pub videos: Vec<i32>
+}
+
+// The body has to contain every video in the playlist exactly once, in the new order
+#[post("/reorder")]
+pub async fn reorder_playlist(data: web::Json<ReorderPlaylistBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let playlist = match get_owned_playlist(&db, data.playlist, user.id) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Playlist not found"); }
+    };
+
+    let mut current = get_playlist_video_ids(&db, playlist.id);
+    let mut requested = data.videos.clone();
+    current.sort();
+    requested.sort();
+
+    if current != requested {
+        return HttpResponse::BadRequest().json("Videos must match the videos in the playlist");
+    }
+
+    match set_playlist_order(&db, playlist.id, &data.videos) {
+        Ok(_) => HttpResponse::Ok().json("Playlist reordered"),
+        Err(_) => HttpResponse::InternalServerError().json("Couldn't reorder playlist")
+    }
+}
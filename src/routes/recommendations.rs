@@ -0,0 +1,134 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{get, HttpResponse, post, Responder, web};
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::{AppState, establish_connection};
+use crate::models::{NewRecommendationFeedback, RecommendationFeedback};
+use crate::schema::recommendation_feedback::dsl::{date, recommendation_feedback, user_id};
+use crate::schema::tags::dsl::tags;
+use crate::schema::users::dsl::users;
+use crate::schema::videos::dsl::videos;
+
+fn store_feedback(db: &PgConnection, user: i32, feedback_type: &str, target_id: i32) -> HttpResponse {
+    let new_feedback = NewRecommendationFeedback {
+        user_id: user,
+        feedback_type,
+        target_id,
+    };
+
+    // Giving the same feedback twice is fine
+    let result = diesel::insert_into(recommendation_feedback)
+        .values(&new_feedback)
+        .on_conflict_do_nothing()
+        .execute(db);
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("Feedback recorded"),
+        Err(_) => HttpResponse::BadRequest().json("Couldn't record feedback")
+    }
+}
+
+#[get("/feedback")]
+pub async fn get_my_feedback(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let result: Vec<RecommendationFeedback> = recommendation_feedback
+        .filter(user_id.eq(user.id))
+        .order_by(date.desc())
+        .load::<RecommendationFeedback>(&db)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(result)
+}
+
+#[derive(Deserialize)]
+pub struct NotInterestedBody {
+    pub video: Option<i32>,
+    pub channel: Option<i32>
+}
+
+#[post("/not-interested")]
+pub async fn not_interested(data: web::Json<NotInterestedBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    match (data.video, data.channel) {
+        (Some(v), None) => {
+            let found: i64 = videos.find(v).count().get_result(&db).expect("Query failed");
+
+            if found == 0 {
+                return HttpResponse::NotFound().json("Video not found");
+            }
+
+            store_feedback(&db, user.id, "NOT_INTERESTED_VIDEO", v)
+        }
+        (None, Some(c)) => {
+            let found: i64 = users
+                .filter(crate::schema::users::id.eq(c).and(crate::schema::users::user_type.eq("CHANNEL")))
+                .count()
+                .get_result(&db)
+                .expect("Query failed");
+
+            if found == 0 {
+                return HttpResponse::NotFound().json("Channel not found");
+            }
+
+            store_feedback(&db, user.id, "NOT_INTERESTED_CHANNEL", c)
+        }
+        _ => HttpResponse::BadRequest().json("Supply either a video or a channel")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SeeLessBody {
+    pub tag: i32
+}
+
+#[post("/see-less")]
+pub async fn see_less(data: web::Json<SeeLessBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let found: i64 = tags.find(data.tag).count().get_result(&db).expect("Query failed");
+
+    if found == 0 {
+        return HttpResponse::NotFound().json("Tag not found");
+    }
+
+    store_feedback(&db, user.id, "SEE_LESS_TAG", data.tag)
+}
+
+#[derive(Deserialize)]
+pub struct DeleteFeedbackBody {
+    pub feedback: i32
+}
+
+// Undoes a piece of feedback
+#[post("/feedback/delete")]
+pub async fn delete_feedback(data: web::Json<DeleteFeedbackBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let result = diesel::delete(recommendation_feedback.filter(
+        crate::schema::recommendation_feedback::id.eq(data.feedback).and(user_id.eq(user.id))
+    ))
+        .execute(&db);
+
+    match result {
+        Ok(0) => HttpResponse::NotFound().json("Feedback not found"),
+        Ok(_) => HttpResponse::Ok().json("Feedback removed"),
+        Err(_) => HttpResponse::BadRequest().json("Couldn't remove feedback")
+    }
+}
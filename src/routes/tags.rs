@@ -0,0 +1,190 @@
+use std::borrow::Borrow;
+use std::sync::Mutex;
+
+use actix_web::{HttpResponse, post, Responder, web};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use diesel::sql_types::Integer;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{AppState, establish_connection};
+use crate::models::{NewTag, Tag};
+use crate::schema::tags::columns::{id, name};
+use crate::schema::tags::dsl::tags;
+use crate::schema::videos_tags::dsl::videos_tags;
+
+/*
+ * Tag management for admins. Every endpoint here checks the user is an ADMIN
+ * before touching the tags table.
+ */
+
+#[derive(Deserialize, Validate)]
+pub struct CreateTagBody {
+    #[validate(length(min = 1, max = 128))]
+    pub name: String
+}
+
+#[post("/tags")]
+pub async fn create_tag(data: web::Json<CreateTagBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage tags.");
+    }
+
+    if data.validate().is_err() {
+        return HttpResponse::BadRequest().json("Invalid tag name");
+    }
+
+    let db = establish_connection();
+
+    let result: QueryResult<Tag> = diesel::insert_into(tags)
+        .values(NewTag { name: data.name.trim() })
+        .get_result(&db);
+
+    match result {
+        Ok(tag) => HttpResponse::Ok().json(tag),
+        Err(_) => HttpResponse::BadRequest().json("Tag already exists")
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RenameTagBody {
+    pub tag: i32,
+    #[validate(length(min = 1, max = 128))]
+    pub name: String
+}
+
+#[post("/tags/rename")]
+pub async fn rename_tag(data: web::Json<RenameTagBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage tags.");
+    }
+
+    if data.validate().is_err() {
+        return HttpResponse::BadRequest().json("Invalid tag name");
+    }
+
+    let db = establish_connection();
+
+    let result = diesel::update(tags.find(data.tag))
+        .set(name.eq(data.name.trim()))
+        .execute(&db);
+
+    match result {
+        Ok(0) => HttpResponse::NotFound().json("Tag does not exist"),
+        Ok(_) => HttpResponse::Ok().json("Tag renamed"),
+        Err(_) => HttpResponse::BadRequest().json("A tag with that name already exists")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MergeTagsBody {
+    pub source: i32,
+    pub target: i32
+}
+
+// Moves every video from the source tag onto the target tag and removes the source tag.
+// Videos which already have the target tag just lose the source tag.
+#[post("/tags/merge")]
+pub async fn merge_tags(data: web::Json<MergeTagsBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage tags.");
+    }
+
+    if data.source == data.target {
+        return HttpResponse::BadRequest().json("Can't merge a tag into itself");
+    }
+
+    let db = establish_connection();
+
+    let found: i64 = tags
+        .filter(id.eq_any(vec![data.source, data.target]))
+        .count()
+        .get_result(&db)
+        .expect("Query failed");
+
+    if found != 2 {
+        return HttpResponse::NotFound().json("Tag does not exist");
+    }
+
+    let result: QueryResult<()> = db.transaction(|| {
+        diesel::sql_query("
+            update videos_tags set tag_id = $2
+            where tag_id = $1
+              and not exists (
+                select * from videos_tags existing
+                where existing.video_id = videos_tags.video_id and existing.tag_id = $2
+              )
+        ")
+            .bind::<Integer, _>(data.source)
+            .bind::<Integer, _>(data.target)
+            .execute(&db)?;
+
+        diesel::delete(videos_tags.filter(crate::schema::videos_tags::tag_id.eq(data.source)))
+            .execute(&db)?;
+
+        diesel::delete(tags.find(data.source))
+            .execute(&db)?;
+
+        Ok(())
+    });
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("Tags merged"),
+        Err(_) => HttpResponse::InternalServerError().json("Couldn't merge tags")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteTagBody {
+    pub tag: i32
+}
+
+// Deleting a tag isn't allowed when it is the only tag on a video, as that would leave the
+// video untagged. Those tags should be merged into another tag instead.
+#[post("/tags/delete")]
+pub async fn delete_tag(data: web::Json<DeleteTagBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if user.user_type != "ADMIN" {
+        return HttpResponse::Forbidden().json("Only admins can manage tags.");
+    }
+
+    let db = establish_connection();
+
+    let only_tag: i64 = videos_tags
+        .filter(crate::schema::videos_tags::tag_id.eq(data.tag))
+        .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "not exists (select * from videos_tags other where other.video_id = videos_tags.video_id and other.tag_id <> videos_tags.tag_id)"
+        ))
+        .count()
+        .get_result(&db)
+        .expect("Query failed");
+
+    if only_tag > 0 {
+        return HttpResponse::BadRequest().json("Some videos only have this tag. Merge it into another tag instead.");
+    }
+
+    let result: QueryResult<usize> = db.transaction(|| {
+        diesel::delete(videos_tags.filter(crate::schema::videos_tags::tag_id.eq(data.tag)))
+            .execute(&db)?;
+
+        diesel::delete(tags.find(data.tag))
+            .execute(&db)
+    });
+
+    match result {
+        Ok(0) => HttpResponse::NotFound().json("Tag does not exist"),
+        Ok(_) => HttpResponse::Ok().json("Tag deleted"),
+        Err(_) => HttpResponse::InternalServerError().json("Couldn't delete tag")
+    }
+}
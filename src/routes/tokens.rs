@@ -1,24 +1,37 @@
 use std::borrow::Borrow;
 use std::sync::Mutex;
 
-use actix_web::{HttpResponse, Responder, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use actix_web::{get, post};
-use diesel::{BoolExpressionMethods, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
 use serde::Deserialize;
 use serde::Serialize;
 use crate::diesel::GroupByDsl;
 
 use crate::{AppState, establish_connection};
-use crate::helpers::stripe::{create_transfer, create_account_link};
+use crate::helpers::experiments::record_outcome;
+use crate::helpers::expiry::{get_token_expirations, get_token_expiry, TokenExpiry};
+use crate::helpers::payments::create_onboarding_link;
 use crate::helpers::tokens::{get_user_balance, get_user_transactions, transfer_token, user_has_active_token};
 use crate::helpers::users::get_user_by_id;
-use crate::models::{ChannelTokenWithUser, NewTokenTransaction, Token, get_safe_user_fields};
+use crate::helpers::economics::get_current_economics;
+use crate::helpers::statements::{get_statement, get_statement_currency, get_statement_periods, is_valid_period, statement_to_csv, statement_to_pdf};
+use crate::helpers::renewals::{add_auto_renewal, get_auto_renewal_list, get_renewal_channel_ids, remove_auto_renewal, set_auto_renewal_order};
+use crate::helpers::payouts::{DEFAULT_MINIMUM_AMOUNT, get_payout_preference, PAYOUT_SCHEDULES, set_payout_preference};
+use crate::helpers::withdrawals::{get_withdrawals, request_withdrawal, submit_withdrawal, WithdrawalError};
+use crate::models::{ChannelTokenWithUser, Token, get_safe_user_fields};
 use crate::schema::channels_tokens::columns::expires;
 use crate::schema::channels_tokens::dsl::{channel_user_id, channels_tokens, converted};
-use crate::schema::token_transactions::dsl::token_transactions;
 use crate::schema::tokens::dsl::{tokens, user_id};
 use crate::schema::users::dsl::users;
 
+#[derive(Serialize)]
+pub struct GetMyTokensResponse {
+    tokens: Vec<Token>,
+    expiry: TokenExpiry,
+}
+
+// The user's tokens, and how many of the unused ones expire at the end of the current period
 #[get("/")]
 pub async fn get_my_tokens(state: web::Data<Mutex<AppState>>) -> impl Responder {
     let state = state.lock().unwrap();
@@ -30,7 +43,21 @@ pub async fn get_my_tokens(state: web::Data<Mutex<AppState>>) -> impl Responder
         .load::<Token>(&db)
         .expect("Query failed");
 
-    HttpResponse::Ok().json(result)
+    HttpResponse::Ok().json(GetMyTokensResponse {
+        tokens: result,
+        expiry: get_token_expiry(&db, user.id),
+    })
+}
+
+// What the rollover policy did at the end of each period
+#[get("/expirations")]
+pub async fn get_my_token_expirations(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_token_expirations(&db, user.id))
 }
 
 #[derive(Deserialize)]
@@ -43,15 +70,32 @@ pub async fn transfer_token_to_channel(data: web::Json<TransferInfo>, state: web
     let state = state.lock().unwrap();
     let user = state.user.borrow().as_ref().unwrap();
 
+    let db = establish_connection();
+
+    // Tokens can't be given away while the subscription is suspended or cancelled
+    let sender = get_user_by_id(&db, user.id).unwrap();
+
+    if sender.user_type == "SUBSCRIBER" && !sender.subscribed {
+        return HttpResponse::Forbidden().json("Your subscription isn't active.");
+    }
+
     // Check if the user is already subscribed
-    let subscribed = user_has_active_token(user.id, data.channel_user_id);
+    let subscribed = user_has_active_token(&db, user.id, data.channel_user_id);
 
     if subscribed {
         return HttpResponse::BadRequest().json("Already subscribed");
     }
 
-    return match transfer_token(user.id, data.channel_user_id) {
-        Ok(_) => HttpResponse::Ok().json("Done"),
+    let transferred = db.transaction(|| transfer_token(&db, user.id, data.channel_user_id))
+        .expect("Query failed");
+
+    return match transferred {
+        Ok(_) => {
+            record_outcome(&db, user.id, "TOKEN_TRANSFER")
+                .expect("Query failed");
+
+            HttpResponse::Ok().json("Done")
+        }
         Err(e) => HttpResponse::BadRequest().body(e)
     };
 }
@@ -97,7 +141,9 @@ pub async fn has_active_token(data: web::Json<HasActiveTokenInfo>, state: web::D
         return HttpResponse::Ok().json(true);
     }
 
-    let active = user_has_active_token(user.id, data.channel_id);
+    let db = establish_connection();
+
+    let active = user_has_active_token(&db, user.id, data.channel_id);
 
     HttpResponse::Ok().json(active)
 }
@@ -143,68 +189,283 @@ pub async fn get_my_transaction_history(state: web::Data<Mutex<AppState>>) -> im
 
 #[derive(Deserialize)]
 pub struct GenerateWithdrawalBody {
-    pub amount: i32
+    pub amount: i32,
+    pub idempotency_key: Option<String>,
 }
 
+// The client has to send an idempotency key, in the Idempotency-Key header or the body, and
+// reuse it when retrying. Requests with the same key make a single withdrawal.
 #[post("/generate-withdrawal")]
-pub async fn generate_withdrawal(data: web::Json<GenerateWithdrawalBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
-    let state = state.lock().unwrap();
-    let user = state.user.borrow().as_ref().unwrap();
+pub async fn generate_withdrawal(req: HttpRequest, data: web::Json<GenerateWithdrawalBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    // The guard mustn't be held across the Stripe transfer below, see CheckLogin
+    let user_id = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        user.id
+    };
 
-    let db = establish_connection();
+    if data.amount <= 0 {
+        return HttpResponse::BadRequest().json("Invalid amount");
+    }
 
-    // Check balance is >= amount to withdraw
-    let balance = get_user_balance(user.id);
+    // Without a key a retried request would make a second withdrawal
+    let key = match req.headers().get("Idempotency-Key").and_then(|v| v.to_str().ok()) {
+        Some(v) => v.to_string(),
+        None => match &data.idempotency_key {
+            Some(v) => v.clone(),
+            None => { return HttpResponse::BadRequest().json("Missing Idempotency-Key header"); }
+        }
+    };
 
-    if balance < data.amount {
-        return HttpResponse::BadRequest().json("You are withdrawing more than your current balance.");
+    if key.is_empty() || key.len() > 64 {
+        return HttpResponse::BadRequest().json("Invalid idempotency key");
     }
 
+    let db = establish_connection();
+
     // Check if Stripe Account is set up for withdrawals
     // If it isn't return error
+    let channel = get_user_by_id(&db, user_id).unwrap();
+
+    let stripe_account_id = match channel.stripe_account {
+        Some(v) => v,
+        None => { return HttpResponse::BadRequest().json("You have not completed the on boarding process yet."); }
+    };
 
-    // Generate payout on Stripe
-    let channel = get_user_by_id(&db, user.id).unwrap();
+    let economics = get_current_economics(&db);
 
-    match channel.stripe_account {
-        None => {
-            HttpResponse::BadRequest().json("You have not completed the on boarding process yet.")
+    let request = match request_withdrawal(&db, user_id, &key, data.amount, &economics.currency) {
+        Ok(v) => v,
+        Err(WithdrawalError::Query(e)) => {
+            println!("Couldn't request a withdrawal for channel {}: {}", user_id, e);
+            return HttpResponse::InternalServerError().json("Sorry, something went wrong on our end. Please try again.");
         }
-        Some(stripe_account_id) => {
-            let transfer = create_transfer(stripe_account_id.as_str(), data.amount).await;
-
-            match transfer {
-                Ok(_) => {
-                    // Insert WITHDRAWAL transaction into db
-                    let new_token_transaction = NewTokenTransaction {
-                        channel_user_id: user.id,
-                        transaction_type: "WITHDRAWAL".to_string(),
-                        amount: data.amount,
-                    };
-
-                    diesel::insert_into(token_transactions)
-                        .values(new_token_transaction)
-                        .execute(&db)
-                        .expect("Query failed");
-
-                    HttpResponse::Ok().json("Payout successful")
-                }
-                Err(_) => {
-                    HttpResponse::BadRequest().json("Payout failed. Make sure you have completed the stripe onboarding process!")
-                }
-            }
+        Err(e) => { return HttpResponse::BadRequest().json(e.to_string()); }
+    };
+
+    // A retry of a request which was already submitted just gets its current state
+    if request.status != "REQUESTED" {
+        return HttpResponse::Ok().json(request);
+    }
+
+    // The request is REQUESTED, so the "Reconcile withdrawals" job submits it if this fails
+    let request = match submit_withdrawal(&db, &request, &stripe_account_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Couldn't submit withdrawal {}: {}", request.id, e);
+            return HttpResponse::InternalServerError().json("Sorry, something went wrong on our end. Please try again.");
         }
+    };
+
+    if request.status == "FAILED" {
+        return HttpResponse::BadRequest().json("Payout failed. Make sure you have completed the stripe onboarding process!");
     }
+
+    HttpResponse::Ok().json(request)
 }
 
-#[get("/account-link")]
-pub async fn generate_account_link(state: web::Data<Mutex<AppState>>) -> impl Responder {
+#[get("/withdrawals")]
+pub async fn get_my_withdrawals(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_withdrawals(&db, user.id))
+}
+
+#[get("/statements")]
+pub async fn get_my_statement_periods(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_statement_periods(&db, user.id))
+}
+
+#[derive(Deserialize)]
+pub struct GetStatementParams {
+    period: String,
+    format: String,
+}
+
+// Monthly earnings statement as CSV or PDF, e.g. /statements/2021-06/pdf
+#[get("/statements/{period}/{format}")]
+pub async fn get_my_statement(params: web::Path<GetStatementParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    if !is_valid_period(&params.period) {
+        return HttpResponse::BadRequest().json("Period must be a month, e.g. 2021-06");
+    }
+
+    let db = establish_connection();
+
+    let currency = get_statement_currency(&db, &params.period)
+        .expect("Query failed");
+
+    let statement = get_statement(&db, user.id, &params.period, &currency)
+        .expect("Query failed");
+
+    let file_name = format!("statement-{}.{}", params.period, params.format);
+    let content_disposition = format!("attachment; filename=\"{}\"", file_name);
+
+    match params.format.as_str() {
+        "csv" => HttpResponse::Ok()
+            .content_type("text/csv")
+            .header("Content-Disposition", content_disposition)
+            .body(statement_to_csv(&statement)),
+        "pdf" => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .header("Content-Disposition", content_disposition)
+            .body(statement_to_pdf(&statement)),
+        _ => HttpResponse::BadRequest().json("Format must be csv or pdf")
+    }
+}
+
+#[get("/payout-preferences")]
+pub async fn get_my_payout_preference(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    HttpResponse::Ok().json(get_payout_preference(&db, user.id))
+}
+
+#[derive(Deserialize)]
+pub struct PayoutPreferenceBody {
+    pub schedule: String,
+    pub minimum_amount: Option<i32>,
+}
+
+#[post("/payout-preferences")]
+pub async fn update_payout_preference(data: web::Json<PayoutPreferenceBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
     let state = state.lock().unwrap();
     let user = state.user.borrow().as_ref().unwrap();
 
+    if user.user_type != "CHANNEL" {
+        return HttpResponse::Forbidden().json("Only channels can be paid out.");
+    }
+
+    if !PAYOUT_SCHEDULES.contains(&data.schedule.as_str()) {
+        return HttpResponse::BadRequest().json("Schedule must be MANUAL, WEEKLY or MONTHLY");
+    }
+
+    let minimum_amount = data.minimum_amount.unwrap_or(DEFAULT_MINIMUM_AMOUNT);
+
+    if minimum_amount <= 0 {
+        return HttpResponse::BadRequest().json("Invalid minimum amount");
+    }
+
+    let db = establish_connection();
+
+    let preference = set_payout_preference(&db, user.id, &data.schedule, minimum_amount)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(preference)
+}
+
+// The channels renewed automatically, in priority order, and the tokens left over for them
+#[get("/auto-renewals")]
+pub async fn get_my_auto_renewals(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let subscriber = get_user_by_id(&db, user.id).unwrap();
+
+    HttpResponse::Ok().json(get_auto_renewal_list(&db, &subscriber))
+}
+
+#[derive(Deserialize)]
+pub struct AutoRenewalBody {
+    channel_user_id: i32
+}
+
+#[post("/auto-renewals")]
+pub async fn add_my_auto_renewal(data: web::Json<AutoRenewalBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    match get_user_by_id(&db, data.channel_user_id) {
+        Some(channel) if channel.user_type == "CHANNEL" && channel.id != user.id => {}
+        Some(_) => { return HttpResponse::BadRequest().json("Target is not a channel"); }
+        None => { return HttpResponse::NotFound().json("Channel does not exist"); }
+    }
+
+    add_auto_renewal(&db, user.id, data.channel_user_id)
+        .expect("Query failed");
+
+    let subscriber = get_user_by_id(&db, user.id).unwrap();
+
+    HttpResponse::Ok().json(get_auto_renewal_list(&db, &subscriber))
+}
+
+#[post("/auto-renewals/remove")]
+pub async fn remove_my_auto_renewal(data: web::Json<AutoRenewalBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let removed = remove_auto_renewal(&db, user.id, data.channel_user_id)
+        .expect("Query failed");
+
+    if !removed {
+        return HttpResponse::NotFound().json("Channel isn't renewed automatically");
+    }
+
+    let subscriber = get_user_by_id(&db, user.id).unwrap();
+
+    HttpResponse::Ok().json(get_auto_renewal_list(&db, &subscriber))
+}
+
+#[derive(Deserialize)]
+pub struct ReorderAutoRenewalsBody {
+    channels: Vec<i32>
+}
+
+#[post("/auto-renewals/reorder")]
+pub async fn reorder_my_auto_renewals(data: web::Json<ReorderAutoRenewalsBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let mut current = get_renewal_channel_ids(&db, user.id);
+    let mut requested = data.channels.clone();
+    current.sort();
+    requested.sort();
+
+    if current != requested {
+        return HttpResponse::BadRequest().json("Channels must match the channels renewed automatically");
+    }
+
+    set_auto_renewal_order(&db, user.id, &data.channels)
+        .expect("Query failed");
+
+    let subscriber = get_user_by_id(&db, user.id).unwrap();
+
+    HttpResponse::Ok().json(get_auto_renewal_list(&db, &subscriber))
+}
+
+#[get("/account-link")]
+pub async fn generate_account_link(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    // The guard mustn't be held across the Stripe call below, see CheckLogin
+    let user_id = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        user.id
+    };
+
     let db = establish_connection();
 
-    let user = get_user_by_id(&db, user.id).unwrap();
+    let user = get_user_by_id(&db, user_id).unwrap();
 
     let stripe_account = match user.stripe_account {
         None => { return HttpResponse::BadRequest().json("Stripe account not created"); }
@@ -213,7 +474,7 @@ pub async fn generate_account_link(state: web::Data<Mutex<AppState>>) -> impl Re
 
 
 
-    let account_link = create_account_link(&*stripe_account).await;
+    let account_link = create_onboarding_link(&stripe_account).await;
 
     match account_link {
         Ok(url) => {
@@ -9,10 +9,11 @@ use uuid::Uuid;
 
 use crate::{AppState, establish_connection};
 use crate::helpers::multipart_parsing::attempt_parse_multipart;
-use crate::models::{NewVideo, NewVideoTag};
+use crate::helpers::tags::{set_video_tags, validate_video_tags};
+use crate::helpers::videos::probe_duration;
+use crate::models::NewVideo;
 use crate::schema::videos::columns::id;
 use crate::schema::videos::dsl::videos;
-use crate::schema::videos_tags::dsl::videos_tags;
 
 #[derive(Deserialize)]
 pub struct UploadVideoData {
@@ -21,12 +22,15 @@ pub struct UploadVideoData {
     video_tags: Vec<i32>,
 }
 
-// TODO: force user to supply at least one tag
 pub async fn upload_video(payload: Multipart, state: web::Data<Mutex<AppState>>) -> impl Responder {
-    let state = state.lock().unwrap();
-    let user = state.user.borrow().as_ref().unwrap();
+    // The guard mustn't be held across the awaits below, see CheckLogin
+    let (user_id, user_type) = {
+        let state = state.lock().unwrap();
+        let user = state.user.borrow().as_ref().unwrap();
+        (user.id, user.user_type.clone())
+    };
 
-    if user.user_type != "CHANNEL" {
+    if user_type != "CHANNEL" {
         return HttpResponse::Forbidden().body("Only channels can upload.");
     }
 
@@ -38,6 +42,17 @@ pub async fn upload_video(payload: Multipart, state: web::Data<Mutex<AppState>>)
         Err(_) => { return HttpResponse::BadRequest().body("Couldn't parse multipart"); }
     };
 
+    let data = match result.data {
+        Some(v) => v,
+        None => { return HttpResponse::BadRequest().body("No video data found."); }
+    };
+
+    let db = establish_connection();
+
+    if let Err(e) = validate_video_tags(&db, &data.video_tags) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
     let video = match result.files.get("video") {
         Some(v) => v,
         None => { return HttpResponse::BadRequest().body("No video found."); }
@@ -51,24 +66,28 @@ pub async fn upload_video(payload: Multipart, state: web::Data<Mutex<AppState>>)
     let uuid = Uuid::new_v4();
     std::fs::create_dir(format!("./uploads/{}", uuid)).unwrap();
 
-    std::fs::rename(&video.path, format!("./uploads/{}/source.{}", uuid, video.ext))
-        .unwrap();
+    let source_path = format!("./uploads/{}/source.{}", uuid, video.ext);
 
-    std::fs::rename(&thumbnail.path, format!("./uploads/{}/thumbnail.{}", uuid, thumbnail.ext))
+    std::fs::rename(&video.path, &source_path)
         .unwrap();
 
-    let db = establish_connection();
+    // Players only report positions, the duration the play and progress rules use comes from here
+    let duration = web::block(move || Ok::<_, ()>(probe_duration(&source_path)))
+        .await
+        .unwrap_or(None);
 
-    let data = result.data.unwrap();
+    std::fs::rename(&thumbnail.path, format!("./uploads/{}/thumbnail.{}", uuid, thumbnail.ext))
+        .unwrap();
 
     let new_video = NewVideo {
         file_name: &uuid.to_string(),
-        user_id: user.id,
+        user_id,
         title: &data.video_title,
         description: match &data.video_description {
             Some(v) => Some(v),
             None => None
         },
+        duration,
     };
 
     let pk: QueryResult<i32> = diesel::insert_into(videos)
@@ -81,17 +100,8 @@ pub async fn upload_video(payload: Multipart, state: web::Data<Mutex<AppState>>)
         Err(_) => { return HttpResponse::InternalServerError().body("Couldn't upload video"); }
     };
 
-    for tag in data.video_tags {
-        let new_video_tag = NewVideoTag {
-            video_id: &pk,
-            tag_id: &tag,
-        };
-
-        diesel::insert_into(videos_tags)
-            .values(&new_video_tag)
-            .execute(&db)
-            .expect("Failed to add tag.");
-    }
+    set_video_tags(&db, pk, &data.video_tags)
+        .expect("Failed to add tags.");
 
     HttpResponse::Ok().body("Uploaded")
 }
\ No newline at end of file
@@ -2,12 +2,16 @@ use std::borrow::Borrow;
 use std::sync::Mutex;
 
 use actix_web::{get, HttpResponse, post, Responder, web};
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
 use diesel::dsl::count_star;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
 
 use crate::{AppState, establish_connection};
+use crate::claims::user::UserClaim;
+use crate::helpers::experiments::record_outcome;
+use crate::helpers::notifications::{notify_batched, VIDEO_UPVOTED};
 use crate::models::{CommentUpvote, NewCommentUpvote, NewVideoUpvote, VideoUpvote};
 use crate::schema::comment_upvotes::dsl::comment_upvotes;
 use crate::schema::video_upvotes::dsl::video_upvotes;
@@ -80,6 +84,27 @@ pub async fn toggle_comment_upvote(data: web::Json<ToggleCommentUpvoteInfo>, sta
     HttpResponse::Ok().json("Toggled upvote")
 }
 
+// Upvotes are batched per video until the channel reads the notification, counting each voter once
+fn notify_video_upvote(db: &PgConnection, target_video_id: i32, voter: &UserClaim) -> QueryResult<()> {
+    let video: Option<(i32, String)> = crate::schema::videos::table
+        .find(target_video_id)
+        .select((crate::schema::videos::user_id, crate::schema::videos::title))
+        .first(db)
+        .optional()?;
+
+    match video {
+        Some((owner, title)) if owner != voter.id => {
+            notify_batched(db, owner, VIDEO_UPVOTED, &format!("{}:{}", VIDEO_UPVOTED, target_video_id), voter.id, json!({
+                "video_id": target_video_id,
+                "title": title,
+                "user_id": voter.id,
+                "username": voter.username,
+            }))
+        }
+        _ => Ok(())
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ToggleVideoUpvoteInfo {
     pub video: i32,
@@ -122,6 +147,14 @@ pub async fn toggle_video_upvote(data: web::Json<ToggleVideoUpvoteInfo>, state:
                 )
                 .execute(&db)
                 .expect("Query failed.");
+
+            if !inactive && data.upvote_type == "UP" {
+                record_outcome(&db, user.id, "UPVOTE")
+                    .expect("Query failed.");
+
+                notify_video_upvote(&db, data.video, user)
+                    .expect("Query failed.");
+            }
         }
         None => {
             let new_video_upvote = NewVideoUpvote {
@@ -136,6 +169,14 @@ pub async fn toggle_video_upvote(data: web::Json<ToggleVideoUpvoteInfo>, state:
 
             return match result {
                 Ok(_) => {
+                    if data.upvote_type == "UP" {
+                        record_outcome(&db, user.id, "UPVOTE")
+                            .expect("Query failed.");
+
+                        notify_video_upvote(&db, data.video, user)
+                            .expect("Query failed.");
+                    }
+
                     HttpResponse::Ok().json("Upvoted")
                 }
                 Err(_) => {
@@ -1,11 +1,11 @@
 use actix_web::{get, HttpResponse, Responder, web, post};
-use diesel::{ExpressionMethods, QueryDsl, JoinOnDsl, TextExpressionMethods, BoolExpressionMethods};
-use serde::Deserialize;
+use diesel::{ExpressionMethods, QueryDsl, TextExpressionMethods, BoolExpressionMethods};
+use serde::{Deserialize, Serialize};
 
 use crate::diesel::RunQueryDsl;
 use crate::diesel::GroupByDsl;
 use crate::{establish_connection, AppState};
-use crate::models::{SafeUser, get_safe_user_fields, TopChannel};
+use crate::models::{SafeUser, get_safe_user_fields, TopChannel, ExperimentAssignment};
 use crate::schema::users::dsl::{id, avatar_filename, cover_filename, subscriptions_enabled, display_name, bio, password, user_type};
 use actix_multipart::Multipart;
 use std::sync::Mutex;
@@ -16,12 +16,40 @@ use s3::{Bucket, Region};
 use std::{fs, env};
 use uuid::Uuid;
 use crate::helpers::users::get_user_by_id;
+use crate::helpers::experiments::get_assignments;
 use bcrypt::{verify, hash};
 use crate::schema::videos::dsl::videos;
 use crate::schema::users::dsl::users;
-use crate::schema::video_plays::dsl::video_plays;
 use crate::schema::users::columns::username;
-use crate::schema::channels_tokens::dsl::channels_tokens;
+
+#[derive(Serialize)]
+pub struct GetMeResponse {
+    pub user: SafeUser,
+    pub experiments: Vec<ExperimentAssignment>,
+}
+
+#[get("/me")]
+pub async fn get_me(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let result: Vec<SafeUser> = users
+        .select(get_safe_user_fields())
+        .filter(id.eq(user.id))
+        .load::<SafeUser>(&db).expect("Query failed.");
+
+    let me = match result.into_iter().nth(0) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Not found"); }
+    };
+
+    HttpResponse::Ok().json(GetMeResponse {
+        user: me,
+        experiments: get_assignments(&db, user.id),
+    })
+}
 
 #[derive(Deserialize)]
 pub struct GetUserParams {
@@ -35,8 +63,6 @@ pub async fn get_user(params: web::Path<GetUserParams>) -> impl Responder {
     let result: Vec<SafeUser> = users
         .select(get_safe_user_fields())
         .filter(id.eq(params.user_id))
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
-        .group_by(id)
         .load::<SafeUser>(&db).expect("Query failed.");
 
     if result.len() > 0 {
@@ -64,8 +90,6 @@ pub async fn get_users(body: web::Json<GetUsersBody>) -> impl Responder {
 
     let result: Vec<SafeUser> = query
         .select(get_safe_user_fields())
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
-        .group_by(id)
         .load::<SafeUser>(&db)
         .expect("Query failed");
 
@@ -231,14 +255,12 @@ pub async fn get_top_channels() -> impl Responder {
             crate::schema::users::subscriptions_enabled,
             crate::schema::users::display_name,
             crate::schema::users::bio,
-            diesel::dsl::sql::<diesel::sql_types::BigInt>("count(\"video_plays\".*)")
+            diesel::dsl::sql::<diesel::sql_types::BigInt>("coalesce(sum(\"videos\".\"play_count\"), 0) as plays")
         )
         )
         .left_join(videos)
-        .left_join(video_plays.on(crate::schema::video_plays::video_id.eq(crate::schema::videos::id)))
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
         .group_by(crate::schema::users::id)
-        .order_by(diesel::dsl::sql::<diesel::sql_types::BigInt>("count").desc())
+        .order_by(diesel::dsl::sql::<diesel::sql_types::BigInt>("plays").desc())
         .limit(10)
         .load::<TopChannel>(&db)
         .expect("Query failed");
@@ -1,7 +1,7 @@
 use std::borrow::Borrow;
 use std::sync::Mutex;
 
-use actix_web::{get, HttpResponse, post, Responder, web};
+use actix_web::{get, HttpRequest, HttpResponse, post, Responder, web};
 use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, TextExpressionMethods, JoinOnDsl};
 use diesel::dsl::exists;
 use crate::diesel::GroupByDsl;
@@ -9,17 +9,21 @@ use serde::Deserialize;
 use validator::Validate;
 
 use crate::{AppState, establish_connection};
-use crate::models::{NewVideoPlay, VideoWithUser, get_safe_user_fields, Tag, PopularTag};
+use crate::models::{VideoWithUser, get_safe_user_fields, Tag, PopularTag, VideoWithProgress};
 use crate::schema::users::dsl::users;
-use crate::schema::video_plays::dsl::video_plays;
 use crate::schema::video_upvotes::dsl::{upvote_type, video_id, video_upvotes};
-use crate::schema::videos::columns::{id, status, title, user_id, description};
+use crate::schema::videos::columns::{id, status, title, user_id, description, trending_score, upload_date};
 use crate::schema::videos::dsl::videos;
 use crate::schema::tags::dsl::tags;
 use crate::schema::videos_tags::dsl::videos_tags;
 use diesel::sql_types::{Integer, Record, VarChar};
-use crate::schema::channels_tokens::dsl::channels_tokens;
 use crate::helpers::recommender::get_recommended_videos;
+use crate::helpers::tags::{set_video_tags, validate_video_tags};
+use crate::helpers::progress::{get_continue_watching_ids, get_progress, get_resume_position, record_progress as store_progress};
+use crate::helpers::videos::get_videos_by_ids;
+use crate::helpers::plays::{count_play_if_eligible, record_play_event};
+use crate::helpers::related::get_related_videos;
+use crate::helpers::experiments::record_outcome;
 
 #[derive(Deserialize)]
 pub struct GetVideoParams {
@@ -38,7 +42,6 @@ pub async fn get_video(params: web::Path<GetVideoParams>, state: web::Data<Mutex
         // .inner_join(video_upvotes)
         .left_join(videos_tags.on(crate::schema::videos_tags::video_id.eq(crate::schema::videos::id)))
         .inner_join(tags.on(crate::schema::videos_tags::tag_id.eq(crate::schema::tags::id)))
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
         .select(
             (
                 crate::schema::videos::id,
@@ -61,22 +64,31 @@ pub async fn get_video(params: web::Path<GetVideoParams>, state: web::Data<Mutex
                         .and(crate::schema::video_upvotes::user_id.eq(user.id))
                     )
                 ),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_upvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_downvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_plays(videos.id)"),
+                crate::schema::videos::upvote_count,
+                crate::schema::videos::downvote_count,
+                crate::schema::videos::play_count,
                 diesel::dsl::sql::<diesel::sql_types::Array<Record<(Integer, VarChar)>>>("array_agg(\"tags\".*) as tags")
             )
         )
         .filter(id.eq(params.video_id))
-        .group_by((crate::schema::videos::id, crate::schema::users::id, crate::schema::channels_tokens::id))
+        .group_by((crate::schema::videos::id, crate::schema::users::id))
         .load::<VideoWithUser>(&db)
         .expect("Query failed");
 
-    if result.len() > 0 {
-        return HttpResponse::Ok().json(result.get(0));
-    }
+    let video = match result.into_iter().nth(0) {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Not found"); }
+    };
 
-    HttpResponse::NotFound().json("Not found")
+    let resume_position = match get_progress(&db, user.id, video.id) {
+        Some(progress) => get_resume_position(&progress),
+        None => None
+    };
+
+    HttpResponse::Ok().json(VideoWithProgress {
+        video,
+        resume_position,
+    })
 }
 
 // TODO: valdiation here??
@@ -89,9 +101,10 @@ pub struct GetVideosBody {
     pub subscriptions: Option<bool>,
     pub upvoted: Option<bool>,
     pub recently_watched: Option<bool>,
+    pub sort: Option<String>,
+    pub page: Option<i64>,
 }
 
-// TODO: recommended
 // TODO: change filters to fiter_or
 // TODO: pagination
 #[post("/")]
@@ -124,7 +137,7 @@ pub async fn get_videos(data: web::Json<GetVideosBody>, state: web::Data<Mutex<A
     }
 
     if let Some(_) = &data.recommended {
-        let result = get_recommended_videos(user.id).await;
+        let result = get_recommended_videos(user.id, data.page.unwrap_or(0)).await;
 
         return HttpResponse::Ok().json(result);
     }
@@ -141,6 +154,13 @@ pub async fn get_videos(data: web::Json<GetVideosBody>, state: web::Data<Mutex<A
         }
     }
 
+    match data.sort.as_deref() {
+        Some("TRENDING") => { query = query.order_by((trending_score.desc(), id.desc())); }
+        Some("NEWEST") => { query = query.order_by((upload_date.desc(), id.desc())); }
+        Some(_) => { return HttpResponse::BadRequest().json("Sort must be TRENDING or NEWEST"); }
+        None => ()
+    }
+
     if let Some(v) = &data.upvoted {
         if v == &true {
             query = query.filter(exists(video_upvotes.
@@ -157,7 +177,6 @@ pub async fn get_videos(data: web::Json<GetVideosBody>, state: web::Data<Mutex<A
         .inner_join(users)
         .left_join(videos_tags.on(crate::schema::videos_tags::video_id.eq(crate::schema::videos::id)))
         .inner_join(tags.on(crate::schema::videos_tags::tag_id.eq(crate::schema::tags::id)))
-        .left_join(channels_tokens.on(crate::schema::channels_tokens::channel_user_id.eq(crate::schema::users::id)))
         .select(
             (
                 crate::schema::videos::id,
@@ -180,39 +199,117 @@ pub async fn get_videos(data: web::Json<GetVideosBody>, state: web::Data<Mutex<A
                         .and(crate::schema::video_upvotes::user_id.eq(user.id))
                     )
                 ),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_upvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_downvotes(videos.id)"),
-                diesel::dsl::sql::<diesel::sql_types::Integer>("count_video_plays(videos.id)"),
+                crate::schema::videos::upvote_count,
+                crate::schema::videos::downvote_count,
+                crate::schema::videos::play_count,
                 diesel::dsl::sql::<diesel::sql_types::Array<Record<(Integer, VarChar)>>>("array_agg(\"tags\".*) as tags")
             )
         )
-        .distinct()
-        .group_by((crate::schema::videos::id, crate::schema::users::id, crate::schema::channels_tokens::id))
+        .group_by((crate::schema::videos::id, crate::schema::users::id))
         .load(&db).expect("Query failed.");
 
     return HttpResponse::Ok().json(items);
 }
 
+#[get("/{video_id}/related")]
+pub async fn get_related(params: web::Path<GetVideoParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let ready: i64 = videos
+        .filter(id.eq(params.video_id).and(status.eq("READY")))
+        .count()
+        .get_result(&db)
+        .expect("Query failed");
+
+    if ready == 0 {
+        return HttpResponse::NotFound().json("Not found");
+    }
+
+    let video_ids: Vec<i32> = get_related_videos(&db, params.video_id, 20)
+        .iter()
+        .map(|related| related.video_id)
+        .collect();
+
+    HttpResponse::Ok().json(get_videos_by_ids(&db, user.id, &video_ids))
+}
+
+#[derive(Deserialize)]
+pub struct GetTrendingParams {
+    pub tag: Option<i32>
+}
+
+#[get("/trending")]
+pub async fn get_trending(params: web::Query<GetTrendingParams>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    sql_function!(fn video_has_tag(v_id: Integer, t_id: Integer) -> Bool);
+
+    let mut query = videos
+        .filter(status.eq("READY").and(trending_score.gt(0.0)))
+        .select(id)
+        .into_boxed();
+
+    if let Some(v) = params.tag {
+        query = query.filter(video_has_tag(id, v));
+    }
+
+    let video_ids: Vec<i32> = query
+        .order_by((trending_score.desc(), id.desc()))
+        .limit(50)
+        .load::<i32>(&db)
+        .expect("Query failed");
+
+    HttpResponse::Ok().json(get_videos_by_ids(&db, user.id, &video_ids))
+}
+
+// The address the request came from. Forwarded headers are only believed when the connection
+// comes from one of the proxies in TRUSTED_PROXIES (comma separated IPs), anyone else could
+// send any address in them.
+fn get_client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr().map(|address| address.ip());
+
+    let trusted = match (peer, std::env::var("TRUSTED_PROXIES")) {
+        (Some(peer), Ok(proxies)) => proxies.split(',')
+            .filter_map(|proxy| proxy.trim().parse::<std::net::IpAddr>().ok())
+            .any(|proxy| proxy == peer),
+        _ => false
+    };
+
+    if !trusted {
+        return peer.map(|ip| ip.to_string());
+    }
+
+    // The remote address can include the port, which changes between connections
+    req.connection_info().realip_remote_addr()
+        .map(|address| match address.parse::<std::net::SocketAddr>() {
+            Ok(socket) => socket.ip().to_string(),
+            Err(_) => address.to_string()
+        })
+}
+
 #[derive(Deserialize)]
 pub struct RecordPlayBody {
     pub video: i32
 }
 
+// Records the start of a play. The play is only counted towards the video's plays once enough
+// of it has been watched, see helpers::plays.
 #[post("/increment-play")]
-pub async fn record_play(data: web::Json<RecordPlayBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+pub async fn record_play(req: HttpRequest, data: web::Json<RecordPlayBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
     let state = state.lock().unwrap();
     let user = state.user.borrow().as_ref().unwrap();
 
     let db = establish_connection();
 
-    let new_video_play = NewVideoPlay {
-        user_id: user.id,
-        video_id: data.video,
-    };
+    let ip = get_client_ip(&req);
 
-    let result = diesel::insert_into(video_plays)
-        .values(new_video_play)
-        .execute(&db);
+    let result = record_play_event(&db, user.id, data.video, ip.as_deref());
 
     match result {
         Ok(_) => HttpResponse::Ok().json("Play recorded"),
@@ -220,6 +317,47 @@ pub async fn record_play(data: web::Json<RecordPlayBody>, state: web::Data<Mutex
     }
 }
 
+#[derive(Deserialize)]
+pub struct RecordProgressBody {
+    pub video: i32,
+    pub position: i32,
+}
+
+// Called periodically by the player while a video is playing
+#[post("/progress")]
+pub async fn record_progress(data: web::Json<RecordProgressBody>, state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    if store_progress(&db, user.id, data.video, data.position).is_err() {
+        return HttpResponse::BadRequest().json("Couldn't record progress");
+    }
+
+    let counted = count_play_if_eligible(&db, user.id, data.video)
+        .expect("Query failed");
+
+    if counted {
+        record_outcome(&db, user.id, "PLAY")
+            .expect("Query failed");
+    }
+
+    HttpResponse::Ok().json("Progress recorded")
+}
+
+#[get("/continue-watching")]
+pub async fn get_continue_watching(state: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = state.lock().unwrap();
+    let user = state.user.borrow().as_ref().unwrap();
+
+    let db = establish_connection();
+
+    let video_ids = get_continue_watching_ids(&db, user.id, 20);
+
+    HttpResponse::Ok().json(get_videos_by_ids(&db, user.id, &video_ids))
+}
+
 #[get("/tags")]
 pub async fn get_available_tags() -> impl Responder {
     let db = establish_connection();
@@ -254,7 +392,8 @@ pub async fn get_popular_tags() -> impl Responder {
 pub struct UpdateVideoBody {
     pub video: i32,
     pub title: Option<String>,
-    pub description: Option<String>
+    pub description: Option<String>,
+    pub tags: Option<Vec<i32>>
 }
 
 #[post("/update")]
@@ -264,6 +403,25 @@ pub async fn update_video(data: web::Json<UpdateVideoBody>, state: web::Data<Mut
 
     let db = establish_connection();
 
+    let owned: i64 = videos
+        .filter(id.eq(data.video).and(user_id.eq(user.id)))
+        .count()
+        .get_result(&db)
+        .expect("Query failed");
+
+    if owned == 0 {
+        return HttpResponse::NotFound().json("Video not found");
+    }
+
+    if let Some(t) = &data.tags {
+        if let Err(e) = validate_video_tags(&db, t) {
+            return HttpResponse::BadRequest().json(e);
+        }
+
+        set_video_tags(&db, data.video, t)
+            .expect("Query failed");
+    }
+
     if let Some(t) = &data.title {
         diesel::update(
             videos.filter(id.eq(data.video).and(user_id.eq(user.id))))
@@ -0,0 +1,72 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, HttpResponse, post, Responder, web};
+
+use crate::establish_connection;
+use crate::helpers::payments::fake_payments;
+use crate::helpers::webhooks::{process_event, process_fake_events, record_event_error, verify_signature, WebhookEvent};
+
+// Events are set up in the Stripe dashboard to be sent here. The signing secret for the
+// endpoint goes in STRIPE_WEBHOOK_SECRET.
+#[post("/stripe")]
+pub async fn stripe_webhook(req: HttpRequest, body: web::Bytes) -> impl Responder {
+    let secret = std::env::var("STRIPE_WEBHOOK_SECRET").expect("Missing STRIPE_WEBHOOK_SECRET in env");
+
+    let signature = match req.headers().get("Stripe-Signature").and_then(|v| v.to_str().ok()) {
+        Some(v) => v,
+        None => { return HttpResponse::BadRequest().json("Missing Stripe-Signature header"); }
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+    if let Err(e) = verify_signature(&body, signature, &secret, now) {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let payload = match std::str::from_utf8(&body) {
+        Ok(v) => v,
+        Err(_) => { return HttpResponse::BadRequest().json("Invalid event"); }
+    };
+
+    let event: WebhookEvent = match serde_json::from_str(payload) {
+        Ok(v) => v,
+        Err(_) => { return HttpResponse::BadRequest().json("Invalid event"); }
+    };
+
+    let db = establish_connection();
+
+    match process_event(&db, &event, payload) {
+        Ok(true) => HttpResponse::Ok().json("Processed"),
+        Ok(false) => HttpResponse::Ok().json("Already processed"),
+        Err(e) => {
+            let message = e.to_string();
+
+            println!("Couldn't handle Stripe event {}: {}", event.id, message);
+
+            if let Err(record_error) = record_event_error(&db, &event, payload, &message) {
+                println!("Couldn't record the error for Stripe event {}: {}", event.id, record_error);
+            }
+
+            // Anything other than a 2xx makes Stripe send the event again later
+            HttpResponse::InternalServerError().json(message)
+        }
+    }
+}
+
+// Delivers the events queued by the fake payments provider, as Stripe would. Only exists when
+// running with PAYMENTS_PROVIDER=fake.
+#[post("/fake")]
+pub async fn deliver_fake_events() -> impl Responder {
+    let fake = match fake_payments() {
+        Some(v) => v,
+        None => { return HttpResponse::NotFound().json("Not found"); }
+    };
+
+    let errors = process_fake_events(&establish_connection(), fake);
+
+    if errors.len() > 0 {
+        return HttpResponse::InternalServerError().json(errors);
+    }
+
+    HttpResponse::Ok().json("Delivered")
+}
@@ -1,3 +1,29 @@
+table! {
+    auto_renewals (id) {
+        id -> Int4,
+        user_id -> Int4,
+        channel_user_id -> Int4,
+        priority -> Int4,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    channel_stats (channel_user_id, granularity, period_start) {
+        channel_user_id -> Int4,
+        granularity -> Varchar,
+        period_start -> Date,
+        plays -> Int4,
+        unique_viewers -> Int4,
+        watch_seconds -> Int4,
+        new_supporters -> Int4,
+        upvotes -> Int4,
+        downvotes -> Int4,
+        comments -> Int4,
+        earnings -> Int4,
+    }
+}
+
 table! {
     channels_tokens (id) {
         id -> Int4,
@@ -5,6 +31,8 @@ table! {
         channel_user_id -> Int4,
         expires -> Timestamp,
         converted -> Bool,
+        transaction_id -> Nullable<Int4>,
+        created -> Timestamp,
     }
 }
 
@@ -27,6 +55,208 @@ table! {
         inactive -> Bool,
         date -> Timestamp,
         video_id -> Int4,
+        upvote_count -> Int4,
+        downvote_count -> Int4,
+    }
+}
+
+table! {
+    experiment_events (id) {
+        id -> Int4,
+        experiment_id -> Int4,
+        variant_id -> Int4,
+        user_id -> Int4,
+        event_type -> Varchar,
+        date -> Timestamp,
+    }
+}
+
+table! {
+    experiment_variants (id) {
+        id -> Int4,
+        experiment_id -> Int4,
+        name -> Varchar,
+        weight -> Int4,
+    }
+}
+
+table! {
+    experiments (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Nullable<Varchar>,
+        active -> Bool,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    job_runs (id) {
+        id -> Int4,
+        job_name -> Varchar,
+        period -> Varchar,
+        status -> Varchar,
+        processed -> Int4,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    ledger_accounts (id) {
+        id -> Int4,
+        account_type -> Varchar,
+        user_id -> Nullable<Int4>,
+        currency -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    ledger_entries (id) {
+        id -> Int4,
+        transaction_id -> Int4,
+        account_id -> Int4,
+        amount -> Int8,
+    }
+}
+
+table! {
+    ledger_transactions (id) {
+        id -> Int4,
+        transaction_type -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    notification_preferences (user_id, notification_type) {
+        user_id -> Int4,
+        notification_type -> Varchar,
+        enabled -> Bool,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        notification_type -> Varchar,
+        data -> Text,
+        created -> Timestamp,
+        read_at -> Nullable<Timestamp>,
+        group_key -> Nullable<Varchar>,
+        event_count -> Int4,
+        updated -> Timestamp,
+        actor_ids -> Array<Int4>,
+    }
+}
+
+table! {
+    payout_preferences (user_id) {
+        user_id -> Int4,
+        schedule -> Varchar,
+        minimum_amount -> Int4,
+        updated -> Timestamp,
+    }
+}
+
+table! {
+    play_events (id) {
+        id -> Int4,
+        user_id -> Int4,
+        video_id -> Int4,
+        ip -> Nullable<Varchar>,
+        date -> Timestamp,
+        counted -> Bool,
+        flagged -> Bool,
+    }
+}
+
+table! {
+    play_flags (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        ip -> Nullable<Varchar>,
+        reason -> Varchar,
+        date -> Timestamp,
+    }
+}
+
+table! {
+    playlists (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        visibility -> Varchar,
+        watch_later -> Bool,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    playlists_videos (id) {
+        id -> Int4,
+        playlist_id -> Int4,
+        video_id -> Int4,
+        position -> Int4,
+        date_added -> Timestamp,
+    }
+}
+
+table! {
+    progress_events (id) {
+        id -> Int8,
+        user_id -> Int4,
+        video_id -> Int4,
+        from_position -> Int4,
+        to_position -> Int4,
+        watched_seconds -> Int4,
+        date -> Timestamp,
+    }
+}
+
+table! {
+    recommendation_feedback (id) {
+        id -> Int4,
+        user_id -> Int4,
+        feedback_type -> Varchar,
+        target_id -> Int4,
+        date -> Timestamp,
+    }
+}
+
+table! {
+    retention_cursor (id) {
+        id -> Bool,
+        last_event_id -> Int8,
+    }
+}
+
+table! {
+    scheduled_jobs (id) {
+        id -> Int4,
+        name -> Varchar,
+        schedule -> Varchar,
+        paused -> Bool,
+        run_requested -> Bool,
+        max_attempts -> Int4,
+        retry_delay_seconds -> Int4,
+        next_run_at -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    stripe_events (id) {
+        id -> Varchar,
+        event_type -> Varchar,
+        payload -> Text,
+        received -> Timestamp,
+        processed_at -> Nullable<Timestamp>,
+        error -> Nullable<Text>,
     }
 }
 
@@ -37,6 +267,42 @@ table! {
     }
 }
 
+table! {
+    token_economics (id) {
+        id -> Int4,
+        effective_from -> Timestamp,
+        token_value -> Int4,
+        currency -> Varchar,
+        channel_token_expiry_days -> Int4,
+        created -> Timestamp,
+        token_rollover -> Varchar,
+        rollover_limit -> Int4,
+    }
+}
+
+table! {
+    token_expirations (id) {
+        id -> Int4,
+        user_id -> Int4,
+        grant_id -> Int4,
+        economics_id -> Int4,
+        token_rollover -> Varchar,
+        expired_count -> Int4,
+        kept_count -> Int4,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    token_plans (id) {
+        id -> Int4,
+        economics_id -> Int4,
+        plan -> Varchar,
+        stripe_price_id -> Varchar,
+        monthly_tokens -> Int4,
+    }
+}
+
 table! {
     token_transactions (id) {
         id -> Int4,
@@ -44,6 +310,22 @@ table! {
         transaction_type -> Varchar,
         amount -> Int4,
         date -> Timestamp,
+        economics_id -> Nullable<Int4>,
+        token_count -> Nullable<Int4>,
+        period -> Nullable<Varchar>,
+        ledger_transaction_id -> Nullable<Int4>,
+        stripe_transfer_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    token_grants (id) {
+        id -> Int4,
+        user_id -> Int4,
+        period -> Varchar,
+        token_count -> Int4,
+        created -> Timestamp,
+        period_end -> Nullable<Timestamp>,
     }
 }
 
@@ -54,6 +336,21 @@ table! {
         used -> Bool,
         date_granted -> Timestamp,
         date_used -> Nullable<Timestamp>,
+        grant_id -> Nullable<Int4>,
+        date_expired -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    user_recommendations (user_id, video_id) {
+        user_id -> Int4,
+        video_id -> Int4,
+        score -> Float8,
+        rank -> Int4,
+        generated -> Timestamp,
+        reason_type -> Varchar,
+        reason_video_id -> Nullable<Int4>,
+        reason_tag_id -> Nullable<Int4>,
     }
 }
 
@@ -74,6 +371,21 @@ table! {
         subscriptions_enabled -> Bool,
         display_name -> Nullable<Varchar>,
         bio -> Nullable<Varchar>,
+        subscriber_count -> Int4,
+        plan -> Varchar,
+        subscription_status -> Varchar,
+        stripe_subscription -> Nullable<Varchar>,
+        cancel_at_period_end -> Bool,
+        current_period_end -> Nullable<Timestamp>,
+        grace_period_ends -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    video_announcements (video_id) {
+        video_id -> Int4,
+        supporter_count -> Int4,
+        created -> Timestamp,
     }
 }
 
@@ -83,6 +395,57 @@ table! {
         user_id -> Int4,
         video_id -> Int4,
         date -> Timestamp,
+        play_event_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    video_progress (id) {
+        id -> Int4,
+        user_id -> Int4,
+        video_id -> Int4,
+        position -> Int4,
+        watched_seconds -> Int4,
+        duration -> Nullable<Int4>,
+        last_updated -> Timestamp,
+        session_seconds -> Int4,
+    }
+}
+
+table! {
+    video_retention (video_id, bucket) {
+        video_id -> Int4,
+        bucket -> Int2,
+        viewers -> Int4,
+    }
+}
+
+table! {
+    video_retention_totals (video_id) {
+        video_id -> Int4,
+        viewers -> Int4,
+        watch_seconds -> Int8,
+    }
+}
+
+table! {
+    video_retention_viewers (video_id, user_id) {
+        video_id -> Int4,
+        user_id -> Int4,
+    }
+}
+
+table! {
+    video_stats (video_id, granularity, period_start) {
+        video_id -> Int4,
+        granularity -> Varchar,
+        period_start -> Date,
+        plays -> Int4,
+        unique_viewers -> Int4,
+        watch_seconds -> Int4,
+        upvotes -> Int4,
+        downvotes -> Int4,
+        comments -> Int4,
     }
 }
 
@@ -97,6 +460,32 @@ table! {
     }
 }
 
+table! {
+    video_similarities (video_id, similar_video_id) {
+        video_id -> Int4,
+        similar_video_id -> Int4,
+        score -> Float8,
+    }
+}
+
+table! {
+    video_viewer_buckets (video_id, user_id, bucket) {
+        video_id -> Int4,
+        user_id -> Int4,
+        bucket -> Int2,
+    }
+}
+
+table! {
+    video_vote_events (id) {
+        id -> Int4,
+        video_id -> Int4,
+        upvote_delta -> Int4,
+        downvote_delta -> Int4,
+        date -> Timestamp,
+    }
+}
+
 table! {
     videos (id) {
         id -> Int4,
@@ -106,6 +495,11 @@ table! {
         description -> Nullable<Varchar>,
         upload_date -> Timestamp,
         status -> Nullable<Varchar>,
+        duration -> Nullable<Int4>,
+        upvote_count -> Int4,
+        downvote_count -> Int4,
+        play_count -> Int4,
+        trending_score -> Float8,
     }
 }
 
@@ -117,6 +511,21 @@ table! {
     }
 }
 
+table! {
+    withdrawal_requests (id) {
+        id -> Int4,
+        channel_user_id -> Int4,
+        idempotency_key -> Varchar,
+        amount -> Int4,
+        currency -> Varchar,
+        status -> Varchar,
+        stripe_transfer_id -> Nullable<Varchar>,
+        failure_reason -> Nullable<Text>,
+        created -> Timestamp,
+        updated -> Timestamp,
+    }
+}
+
 joinable!(channels_tokens -> tokens (token_id));
 joinable!(videos_tags -> tags (tag_id));
 joinable!(videos -> users (user_id));
@@ -125,17 +534,67 @@ joinable!(comments -> videos (user_id));
 joinable!(videos -> video_upvotes (id));
 joinable!(comments -> comment_upvotes (id));
 joinable!(video_plays -> videos (video_id));
+joinable!(video_progress -> videos (video_id));
+joinable!(experiment_variants -> experiments (experiment_id));
+joinable!(token_plans -> token_economics (economics_id));
+joinable!(token_grants -> users (user_id));
+joinable!(ledger_entries -> ledger_accounts (account_id));
+joinable!(ledger_entries -> ledger_transactions (transaction_id));
+joinable!(playlists -> users (user_id));
+joinable!(playlists_videos -> playlists (playlist_id));
+joinable!(playlists_videos -> videos (video_id));
+joinable!(notifications -> users (user_id));
+joinable!(payout_preferences -> users (user_id));
+joinable!(token_expirations -> token_grants (grant_id));
+joinable!(notification_preferences -> users (user_id));
+joinable!(video_announcements -> videos (video_id));
 
 allow_tables_to_appear_in_same_query!(
+    auto_renewals,
+    channel_stats,
     channels_tokens,
     comment_upvotes,
     comments,
+    experiment_events,
+    experiment_variants,
+    experiments,
+    job_runs,
+    ledger_accounts,
+    ledger_entries,
+    ledger_transactions,
+    notification_preferences,
+    notifications,
+    payout_preferences,
+    play_events,
+    play_flags,
+    playlists,
+    playlists_videos,
+    progress_events,
+    recommendation_feedback,
+    retention_cursor,
+    scheduled_jobs,
+    stripe_events,
     tags,
+    token_economics,
+    token_expirations,
+    token_grants,
+    token_plans,
     token_transactions,
     tokens,
+    user_recommendations,
     users,
+    video_announcements,
     video_plays,
+    video_progress,
+    video_retention,
+    video_retention_totals,
+    video_retention_viewers,
+    video_similarities,
+    video_stats,
     video_upvotes,
+    video_viewer_buckets,
+    video_vote_events,
     videos,
     videos_tags,
+    withdrawal_requests,
 );
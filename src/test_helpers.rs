@@ -0,0 +1,26 @@
+use diesel::{Connection, PgConnection};
+use diesel::connection::SimpleConnection;
+
+use crate::establish_connection;
+
+// Tests run against the database in DATABASE_URL, with the migrations applied. Everything a test
+// does happens in a transaction which is rolled back at the end, so they can run against the
+// development database.
+pub fn test_connection() -> PgConnection {
+    dotenv::dotenv().ok();
+
+    establish_connection()
+}
+
+// Runs the test in a transaction which is always rolled back, after loading the fixture SQL
+pub fn with_fixture<F>(fixture: &str, test: F) where F: FnOnce(&PgConnection) {
+    let db = test_connection();
+
+    db.test_transaction::<_, diesel::result::Error, _>(|| {
+        db.batch_execute(fixture)?;
+
+        test(&db);
+
+        Ok(())
+    });
+}